@@ -0,0 +1,85 @@
+/*
+   Checks an installed bundle against its library and the plugins' port
+   metadata:
+
+       lv2check ~/.lv2/rustplugins.lv2 [library]
+
+   The library defaults to librustplugins.so in the bundle directory.  All
+   problems are printed, the exit status is 1 if there were any.
+*/
+
+extern crate libc;
+extern crate lv2check;
+extern crate rustlv2;
+extern crate rustplugins;
+
+use lv2check::Library;
+use rustlv2::lv2_raw::LV2Descriptor;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+
+
+type Entry = extern "C" fn(u32) -> *const LV2Descriptor;
+
+
+fn load(path: &Path) -> Result<Library, String> {
+    let file = path.file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("{}: not a file", path.display()))?;
+    let cpath = CString::new(path.to_string_lossy().into_owned()).unwrap();
+
+    unsafe {
+        // the library stays loaded until the checker exits
+        let handle = libc::dlopen(cpath.as_ptr(), libc::RTLD_NOW);
+        if handle.is_null() {
+            return Err(CStr::from_ptr(libc::dlerror()).to_string_lossy().into_owned());
+        }
+        let entry = libc::dlsym(handle, b"lv2_descriptor\0".as_ptr() as *const libc::c_char);
+        if entry.is_null() {
+            return Err(format!("{}: no lv2_descriptor()", path.display()));
+        }
+        Ok(Library { file: file, entry: mem::transmute::<*mut libc::c_void, Entry>(entry) })
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: lv2check <bundle directory> [library]");
+        process::exit(2);
+    }
+
+    let dir = PathBuf::from(&args[1]);
+    let library = match args.get(2) {
+        Some(path) => PathBuf::from(path),
+        None => dir.join("librustplugins.so")
+    };
+
+    let library = match load(&library) {
+        Ok(library) => library,
+        Err(err) => {
+            eprintln!("lv2check: {}", err);
+            process::exit(1);
+        }
+    };
+    let manifest = match fs::read_to_string(dir.join("manifest.ttl")) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("lv2check: {}: {}", dir.join("manifest.ttl").display(), err);
+            process::exit(1);
+        }
+    };
+
+    let errors = lv2check::check_bundle(&manifest, |file| fs::read_to_string(dir.join(file)).ok(),
+        &library, &rustplugins::bundle_plugins());
+    for err in &errors {
+        println!("error: {}", err);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,320 @@
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn library() -> Library {
+        Library { file: "librustplugins.so".to_string(), entry: rustplugins::lv2_descriptor }
+    }
+
+    /* Check one of the bundles in `fixtures/`. */
+    fn check_fixture(name: &str) -> Vec<String> {
+        let dir = format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let manifest = fs::read_to_string(format!("{}/manifest.ttl", dir)).unwrap();
+        check_bundle(&manifest, |file| fs::read_to_string(format!("{}/{}", dir, file)).ok(),
+            &library(), &rustplugins::bundle_plugins())
+    }
+
+    fn has_error(errors: &[String], text: &str) -> bool {
+        errors.iter().any(|e| e.contains(text))
+    }
+
+    #[test]
+    fn parse_turtle() {
+        let text = "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+                    # comment\n\
+                    <http://example.org/p> a lv2:Plugin ;\n\
+                        lv2:port [ lv2:index 0 ; lv2:symbol \"in\" ] , [ lv2:index 1 ].";
+        let mut blank = 0;
+        let triples = turtle::parse(text, &mut blank).unwrap();
+
+        assert_eq!(triples.len(), 6);
+        assert_eq!(triples[0].predicate, turtle::RDF_TYPE);
+        assert_eq!(triples[0].object, Term::Iri("http://lv2plug.in/ns/lv2core#Plugin".to_string()));
+        assert_eq!(triples[2].object, Term::Literal("in".to_string()));
+        assert_eq!(triples[4].object, Term::Number(1.0));
+        assert_eq!(triples[5].object, Term::Blank(1));
+        assert_eq!(blank, 2);
+    }
+
+    #[test]
+    fn generated_bundle_is_clean() {
+        let files: HashMap<String, String> = rustplugins::bundle_files().unwrap().into_iter().collect();
+        let errors = check_bundle(&files["manifest.ttl"], |file| files.get(file).cloned(),
+            &library(), &rustplugins::bundle_plugins());
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn duplicate_symbol() {
+        let errors = check_fixture("rusteq");
+        assert!(has_error(&errors, "ports 2 and 3 both have the symbol \"outl\""), "{:?}", errors);
+        assert!(has_error(&errors, "port 3 is \"outl\" in the data, but \"outr\" in the code"), "{:?}", errors);
+        assert!(has_error(&errors, "<librusteq.so>"), "{:?}", errors);
+    }
+
+    #[test]
+    fn metro_uri_mismatch() {
+        let errors = check_fixture("rustmetro");
+        assert!(has_error(&errors, "<http://example.org/eg-metro> is not provided by librustplugins.so"), "{:?}", errors);
+        assert!(has_error(&errors, "<http://example.org/rustmetro> is missing"), "{:?}", errors);
+        assert!(has_error(&errors, "<libmetro.so>"), "{:?}", errors);
+    }
+
+    #[test]
+    fn missing_required_feature() {
+        let files: HashMap<String, String> = rustplugins::bundle_files().unwrap().into_iter().collect();
+        let errors = check_bundle(&files["manifest.ttl"], |file| {
+                files.get(file).map(|f| f.replace("lv2:requiredFeature urid:map ;", ""))
+            }, &library(), &rustplugins::bundle_plugins());
+        assert!(has_error(&errors, "<http://example.org/rustmetro> needs urid:map"), "{:?}", errors);
+        assert!(has_error(&errors, "<http://example.org/rustmidigate> needs urid:map"), "{:?}", errors);
+    }
+
+    #[test]
+    fn missing_state_interface() {
+        let files: HashMap<String, String> = rustplugins::bundle_files().unwrap().into_iter().collect();
+        let errors = check_bundle(&files["manifest.ttl"], |file| {
+                files.get(file).map(|f| f.replace("lv2:extensionData state:interface ;", ""))
+            }, &library(), &rustplugins::bundle_plugins());
+        assert!(has_error(&errors, "<http://example.org/rustmetro> has a state interface"), "{:?}", errors);
+    }
+}
+
+
+/*
+   Consistency checks for an LV2 bundle.
+
+   The data files of a bundle and its library are read by the host
+   independently, so nothing keeps them from disagreeing, which usually ends
+   with the host not loading the plugin or connecting the wrong ports.  This
+   crate cross-checks the manifest and the plugin descriptions against the
+   library and against the port metadata of the plugins in this repository.
+*/
+
+extern crate libc;
+extern crate rustlv2;
+extern crate rustplugins;
+
+pub mod turtle;
+
+use rustlv2::host::TestHost;
+use rustlv2::lv2_raw::LV2Descriptor;
+use rustlv2::{PluginInfo, LV2_STATE__INTERFACE};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use turtle::{Term, Triple};
+
+
+const LV2_PLUGIN: &'static str = "http://lv2plug.in/ns/lv2core#Plugin";
+const LV2_BINARY: &'static str = "http://lv2plug.in/ns/lv2core#binary";
+const LV2_PORT: &'static str = "http://lv2plug.in/ns/lv2core#port";
+const LV2_INDEX: &'static str = "http://lv2plug.in/ns/lv2core#index";
+const LV2_SYMBOL: &'static str = "http://lv2plug.in/ns/lv2core#symbol";
+const LV2_REQUIRED_FEATURE: &'static str = "http://lv2plug.in/ns/lv2core#requiredFeature";
+const LV2_EXTENSION_DATA: &'static str = "http://lv2plug.in/ns/lv2core#extensionData";
+const RDFS_SEE_ALSO: &'static str = "http://www.w3.org/2000/01/rdf-schema#seeAlso";
+const URID_MAP: &'static str = "http://lv2plug.in/ns/ext/urid#map";
+const STATE_INTERFACE: &'static str = "http://lv2plug.in/ns/ext/state#interface";
+
+
+/* A loaded plugin library: its file name and `lv2_descriptor()` entry point. */
+pub struct Library {
+    pub file: String,
+    pub entry: extern "C" fn(u32) -> *const LV2Descriptor
+}
+
+impl Library {
+    /* The URIs of all plugins in the library, in the order it reports them. */
+    pub fn uris(&self) -> Vec<String> {
+        let mut uris = Vec::new();
+        loop {
+            let descriptor = (self.entry)(uris.len() as u32);
+            if descriptor.is_null() {
+                return uris;
+            }
+            let uri = unsafe { CStr::from_ptr((*descriptor).uri) };
+            uris.push(uri.to_string_lossy().into_owned());
+        }
+    }
+}
+
+
+/*
+   Check a bundle.  `manifest` is the content of its manifest.ttl, `read`
+   returns the content of the other files of the bundle, and `plugins` holds
+   the descriptions of the plugins in the code.  Returns all problems found,
+   an empty list means the bundle is fine.
+*/
+pub fn check_bundle<F>(manifest: &str, read: F, library: &Library,
+    plugins: &[(&str, &PluginInfo)]) -> Vec<String>
+    where F: Fn(&str) -> Option<String> {
+
+    let mut errors = Vec::new();
+    let mut blank = 0;
+
+    let mut data = match turtle::parse(manifest, &mut blank) {
+        Ok(triples) => triples,
+        Err(err) => {
+            errors.push(format!("manifest.ttl: {}", err));
+            return errors;
+        }
+    };
+
+    let library_uris = library.uris();
+    let manifest_uris: Vec<String> = subjects_of_type(&data, LV2_PLUGIN);
+
+    for uri in &manifest_uris {
+        let binaries = iris(&data, uri, LV2_BINARY);
+        if binaries.is_empty() {
+            errors.push(format!("manifest.ttl: <{}> has no lv2:binary", uri));
+        }
+        for binary in binaries {
+            if binary.rsplit('/').next() != Some(library.file.as_str()) {
+                errors.push(format!("manifest.ttl: the binary of <{}> is <{}>, not {}",
+                    uri, binary, library.file));
+            }
+        }
+        if !library_uris.contains(uri) {
+            errors.push(format!("manifest.ttl: <{}> is not provided by {}", uri, library.file));
+        }
+    }
+    for uri in &library_uris {
+        if !manifest_uris.contains(uri) {
+            errors.push(format!("manifest.ttl: <{}> is missing, but provided by {}", uri, library.file));
+        }
+    }
+
+    let mut files: Vec<String> = manifest_uris.iter()
+        .flat_map(|uri| iris(&data, uri, RDFS_SEE_ALSO))
+        .collect();
+    files.sort();
+    files.dedup();
+    for file in files {
+        match read(&file) {
+            Some(text) => match turtle::parse(&text, &mut blank) {
+                Ok(triples) => data.extend(triples),
+                Err(err) => errors.push(format!("{}: {}", file, err))
+            },
+            None => errors.push(format!("{}: can not be read", file))
+        }
+    }
+
+    for uri in manifest_uris.iter().filter(|uri| library_uris.contains(uri)) {
+        let info = plugins.iter().find(|p| p.0 == uri).map(|p| p.1);
+        check_ports(&data, uri, info, &mut errors);
+        check_features(&data, uri, library, &mut errors);
+    }
+    errors
+}
+
+/*
+   Port indices have to be 0..n and symbols unique.  If the plugin is one of
+   ours, the ports also have to match its `PortIndex`.
+*/
+fn check_ports(data: &[Triple], uri: &str, info: Option<&PluginInfo>, errors: &mut Vec<String>) {
+    let mut ports = Vec::new();
+
+    for port in objects(data, &Term::Iri(uri.to_string()), LV2_PORT) {
+        let index = objects(data, port, LV2_INDEX).first().and_then(|t| t.as_number());
+        let symbol = objects(data, port, LV2_SYMBOL).first().and_then(|t| t.as_literal());
+        match (index, symbol) {
+            (Some(index), Some(symbol)) => ports.push((index as u32, symbol)),
+            (None, _) => errors.push(format!("<{}>: a port has no lv2:index", uri)),
+            (_, None) => errors.push(format!("<{}>: a port has no lv2:symbol", uri))
+        }
+    }
+    if ports.is_empty() {
+        errors.push(format!("<{}>: no ports are described", uri));
+    }
+    ports.sort();
+
+    let mut symbols: HashMap<&str, u32> = HashMap::new();
+    for &(index, symbol) in &ports {
+        if let Some(other) = symbols.insert(symbol, index) {
+            errors.push(format!("<{}>: ports {} and {} both have the symbol \"{}\"", uri, other, index, symbol));
+        }
+    }
+    for (i, &(index, _)) in ports.iter().enumerate() {
+        if index != i as u32 {
+            errors.push(format!("<{}>: port indices are not 0..{}, found {}", uri, ports.len(), index));
+            break;
+        }
+    }
+
+    if let Some(info) = info {
+        if info.ports.len() != ports.len() {
+            errors.push(format!("<{}>: {} ports are described, the code has {}",
+                uri, ports.len(), info.ports.len()));
+        }
+        for port in info.ports {
+            match ports.iter().find(|p| p.0 == port.index) {
+                Some(&(_, symbol)) if symbol != port.symbol => errors.push(format!(
+                    "<{}>: port {} is \"{}\" in the data, but \"{}\" in the code",
+                    uri, port.index, symbol, port.symbol)),
+                Some(_) => (),
+                None => errors.push(format!("<{}>: port {} (\"{}\") is not described",
+                    uri, port.index, port.symbol))
+            }
+        }
+    }
+}
+
+/*
+   A plugin which only instantiates with `urid:map` has to list it as
+   required feature, otherwise a host without it will try to load it.  The
+   state interface has to be listed as well, or the host will not save the
+   plugin's state.
+*/
+fn check_features(data: &[Triple], uri: &str, library: &Library, errors: &mut Vec<String>) {
+    let required = iris(data, uri, LV2_REQUIRED_FEATURE);
+    let extensions = iris(data, uri, LV2_EXTENSION_DATA);
+
+    let host = TestHost::new();
+    let instance = match host.instantiate(library.entry, uri, 48000.0) {
+        Some(instance) => instance,
+        None => {
+            errors.push(format!("<{}> can not be instantiated", uri));
+            return;
+        }
+    };
+
+    if TestHost::bare().instantiate(library.entry, uri, 48000.0).is_none()
+        && !required.iter().any(|f| f == URID_MAP) {
+        errors.push(format!("<{}> needs urid:map, but does not list it as lv2:requiredFeature", uri));
+    }
+    if !instance.extension_data(LV2_STATE__INTERFACE).is_null()
+        && !extensions.iter().any(|e| e == STATE_INTERFACE) {
+        errors.push(format!("<{}> has a state interface, but does not list it as lv2:extensionData", uri));
+    }
+}
+
+
+fn subjects_of_type(data: &[Triple], class: &str) -> Vec<String> {
+    let mut subjects = Vec::new();
+    for t in data {
+        if t.predicate == turtle::RDF_TYPE && t.object.as_iri() == Some(class) {
+            if let Some(iri) = t.subject.as_iri() {
+                if !subjects.iter().any(|s| s == iri) {
+                    subjects.push(iri.to_string());
+                }
+            }
+        }
+    }
+    subjects
+}
+
+fn objects<'a>(data: &'a [Triple], subject: &Term, predicate: &str) -> Vec<&'a Term> {
+    data.iter()
+        .filter(|t| t.subject == *subject && t.predicate == predicate)
+        .map(|t| &t.object)
+        .collect()
+}
+
+fn iris(data: &[Triple], subject: &str, predicate: &str) -> Vec<String> {
+    objects(data, &Term::Iri(subject.to_string()), predicate).iter()
+        .filter_map(|t| t.as_iri())
+        .map(|iri| iri.to_string())
+        .collect()
+}
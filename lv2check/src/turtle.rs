@@ -0,0 +1,358 @@
+/*
+   A small Turtle reader, just enough for LV2 data files: prefixes, IRIs,
+   prefixed names, `a`, string and number literals, and nested blank nodes
+   (`[ ... ]`) with `;` and `,` lists.  Collections and long strings are not
+   supported.
+
+   Prefixed names are expanded, relative IRIs like `<rustamp.ttl>` are kept as
+   they are.
+*/
+
+use std::collections::HashMap;
+
+
+pub const RDF_TYPE: &'static str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Iri(String),
+    Blank(usize),
+    Literal(String),
+    Number(f64)
+}
+
+impl Term {
+    pub fn as_iri(&self) -> Option<&str> {
+        match *self {
+            Term::Iri(ref iri) => Some(iri),
+            _ => None
+        }
+    }
+
+    pub fn as_literal(&self) -> Option<&str> {
+        match *self {
+            Term::Literal(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            Term::Number(n) => Some(n),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Triple {
+    pub subject: Term,
+    pub predicate: String,
+    pub object: Term
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Prefix,
+    Iri(String),
+    PName(String, String),
+    A,
+    Literal(String),
+    Number(f64),
+    Punct(char)
+}
+
+/*
+   Blank node ids are handed out by the caller, so the triples of several
+   files can be merged without clashes.
+*/
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    prefixes: HashMap<String, String>,
+    next_blank: &'a mut usize,
+    triples: Vec<Triple>
+}
+
+pub fn parse(text: &str, next_blank: &mut usize) -> Result<Vec<Triple>, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens: tokens,
+        pos: 0,
+        prefixes: HashMap::new(),
+        next_blank: next_blank,
+        triples: Vec::new()
+    };
+    parser.document()?;
+    Ok(parser.triples)
+}
+
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\n' => { line += 1; i += 1; },
+            c if c.is_whitespace() => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '<' => {
+                let start = i + 1;
+                while i < chars.len() && chars[i] != '>' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(format!("line {}: unterminated IRI", line));
+                }
+                tokens.push((Token::Iri(chars[start..i].iter().collect()), line));
+                i += 1;
+            },
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some(&'\n') => return Err(format!("line {}: unterminated string", line)),
+                        Some(&'"') => break,
+                        Some(&'\\') => {
+                            match chars.get(i + 1) {
+                                Some(&'n') => s.push('\n'),
+                                Some(&'t') => s.push('\t'),
+                                Some(&c) => s.push(c),
+                                None => return Err(format!("line {}: unterminated string", line))
+                            }
+                            i += 2;
+                        },
+                        Some(&c) => { s.push(c); i += 1; }
+                    }
+                }
+                i += 1;
+                // a language tag or datatype does not matter for the checks
+                if chars.get(i) == Some(&'@') {
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '@' || chars[i] == '-') {
+                        i += 1;
+                    }
+                } else if chars.get(i) == Some(&'^') && chars.get(i + 1) == Some(&'^') {
+                    i += 2;
+                    while i < chars.len() && !chars[i].is_whitespace() && !";,.]".contains(chars[i]) {
+                        i += 1;
+                    }
+                }
+                tokens.push((Token::Literal(s), line));
+            },
+            '.' | ';' | ',' | '[' | ']' if !starts_number(&chars, i) => {
+                tokens.push((Token::Punct(c), line));
+                i += 1;
+            },
+            c if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || "eE+-".contains(chars[i])
+                    || starts_number(&chars, i)) {
+                    i += 1;
+                }
+                let s: String = chars[start..i].iter().collect();
+                match s.parse::<f64>() {
+                    Ok(n) => tokens.push((Token::Number(n), line)),
+                    Err(_) => return Err(format!("line {}: invalid number {}", line, s))
+                }
+            },
+            '@' => {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && chars[i].is_alphabetic() {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                if word != "prefix" {
+                    return Err(format!("line {}: @{} is not supported", line, word));
+                }
+                tokens.push((Token::Prefix, line));
+            },
+            _ => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || "_-:.".contains(chars[i])) {
+                    i += 1;
+                }
+                // a trailing dot ends the statement
+                while i > start && chars[i - 1] == '.' {
+                    i -= 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                if word.is_empty() {
+                    return Err(format!("line {}: unexpected '{}'", line, c));
+                }
+                if word == "a" {
+                    tokens.push((Token::A, line));
+                } else {
+                    match word.find(':') {
+                        Some(pos) => tokens.push((Token::PName(word[..pos].to_string(),
+                            word[pos + 1..].to_string()), line)),
+                        None => return Err(format!("line {}: unexpected '{}'", line, word))
+                    }
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn starts_number(chars: &[char], i: usize) -> bool {
+    chars[i] == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())
+}
+
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or(self.tokens.last()).map_or(0, |t| t.1)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        match self.tokens.get(self.pos) {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t.0.clone())
+            },
+            None => Err("unexpected end of file".to_string())
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        let line = self.line();
+        match self.next()? {
+            Token::Punct(p) if p == c => Ok(()),
+            t => Err(format!("line {}: expected '{}', found {:?}", line, c, t))
+        }
+    }
+
+    fn document(&mut self) -> Result<(), String> {
+        while let Some(token) = self.peek().cloned() {
+            if token == Token::Prefix {
+                self.prefix()?;
+            } else {
+                let subject = self.subject()?;
+                if self.peek() != Some(&Token::Punct('.')) {
+                    self.predicate_objects(&subject)?;
+                }
+                self.expect('.')?;
+            }
+        }
+        Ok(())
+    }
+
+    fn prefix(&mut self) -> Result<(), String> {
+        let line = self.line();
+        self.next()?;
+        let name = match self.next()? {
+            Token::PName(ref prefix, ref local) if local.is_empty() => prefix.clone(),
+            t => return Err(format!("line {}: expected a prefix name, found {:?}", line, t))
+        };
+        let iri = match self.next()? {
+            Token::Iri(iri) => iri,
+            t => return Err(format!("line {}: expected an IRI, found {:?}", line, t))
+        };
+        self.prefixes.insert(name, iri);
+        self.expect('.')
+    }
+
+    fn iri(&self, token: &Token) -> Result<Option<String>, String> {
+        match *token {
+            Token::Iri(ref iri) => Ok(Some(iri.clone())),
+            Token::PName(ref prefix, ref local) => match self.prefixes.get(prefix) {
+                Some(base) => Ok(Some(format!("{}{}", base, local))),
+                None => Err(format!("line {}: undefined prefix '{}:'", self.line(), prefix))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    fn subject(&mut self) -> Result<Term, String> {
+        let line = self.line();
+        let token = self.next()?;
+        if let Some(iri) = self.iri(&token)? {
+            return Ok(Term::Iri(iri));
+        }
+        match token {
+            Token::Punct('[') => self.blank(),
+            t => Err(format!("line {}: expected a subject, found {:?}", line, t))
+        }
+    }
+
+    /* The rest of a `[ ... ]` blank node, after the opening bracket. */
+    fn blank(&mut self) -> Result<Term, String> {
+        let node = Term::Blank(*self.next_blank);
+        *self.next_blank += 1;
+        if self.peek() != Some(&Token::Punct(']')) {
+            self.predicate_objects(&node)?;
+        }
+        self.expect(']')?;
+        Ok(node)
+    }
+
+    fn predicate_objects(&mut self, subject: &Term) -> Result<(), String> {
+        loop {
+            let line = self.line();
+            let token = self.next()?;
+            let predicate = match token {
+                Token::A => RDF_TYPE.to_string(),
+                ref t => match self.iri(t)? {
+                    Some(iri) => iri,
+                    None => return Err(format!("line {}: expected a predicate, found {:?}", line, t))
+                }
+            };
+
+            loop {
+                let object = self.object()?;
+                self.triples.push(Triple {
+                    subject: subject.clone(),
+                    predicate: predicate.clone(),
+                    object: object
+                });
+                if self.peek() == Some(&Token::Punct(',')) {
+                    self.next()?;
+                } else {
+                    break;
+                }
+            }
+
+            // `;` may also be repeated or end the list
+            let mut more = false;
+            while self.peek() == Some(&Token::Punct(';')) {
+                self.next()?;
+                more = true;
+            }
+            match self.peek() {
+                Some(&Token::Punct('.')) | Some(&Token::Punct(']')) | None => return Ok(()),
+                _ if more => (),
+                t => return Err(format!("line {}: expected ';', found {:?}", self.line(), t))
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Term, String> {
+        let line = self.line();
+        let token = self.next()?;
+        if let Some(iri) = self.iri(&token)? {
+            return Ok(Term::Iri(iri));
+        }
+        match token {
+            Token::Literal(s) => Ok(Term::Literal(s)),
+            Token::Number(n) => Ok(Term::Number(n)),
+            Token::Punct('[') => self.blank(),
+            t => Err(format!("line {}: expected an object, found {:?}", line, t))
+        }
+    }
+}
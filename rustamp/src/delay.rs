@@ -42,7 +42,7 @@ impl Delay {
     }
 
     pub fn set_vals(&mut self, new_feedback: f32, new_master: f32) -> () {
-        let feed = if new_feedback > 1.0 { 1.0 } else if new_feedback < -1.0 { -1.0 } else { new_feedback };
+        let feed = new_feedback.clamp(-1.0, 1.0);
         self.feedback = feed as f64;
 
         let master = if new_master < 0.0 { 0.0 } else { new_master };
@@ -50,7 +50,7 @@ impl Delay {
     }
 
     fn incr(&mut self) -> () {
-        self.index = self.index + 1;
+        self.index += 1;
         if self.index >= self.buffer.len() || self.index >= self.length {
             self.index = 0;
         }
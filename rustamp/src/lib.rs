@@ -1,7 +1,77 @@
 #[cfg(test)]
 mod tests {
+    use rustlv2::host::*;
+    use super::*;
+
+    // the plugin is exported by the bundle, the tests need their own entry point
+    lv2_descriptors!(Amp);
+
+    fn amp<'h>(host: &'h TestHost, rate: f64) -> Instance<'h> {
+        let mut amp = host.instantiate(lv2_descriptor, "http://example.org/rustamp", rate).unwrap();
+        amp.set_control(PortIndex::AmpGain as u32, 0.0);
+        amp.set_control(PortIndex::AmpDelay as u32, 0.01);
+        amp.set_control(PortIndex::AmpFeedback as u32, 0.0);
+        amp.set_control(PortIndex::AmpMaster as u32, 1.0);
+        amp.connect_audio_output(PortIndex::AmpOutput as u32);
+        amp.activate();
+        amp
+    }
+
+    #[test]
+    fn descriptor() {
+        let host = TestHost::new();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rustamp", 48000.0).is_some());
+        assert!(lv2_descriptor(1).is_null());
+    }
+
+    #[test]
+    fn distortion_without_feedback() {
+        let host = TestHost::new();
+        let mut amp = amp(&host, 48000.0);
+
+        let input: Vec<f32> = (0..256).map(|i| ((i as f32) * 0.05).sin()).collect();
+        amp.set_control(PortIndex::AmpGain as u32, 6.0);
+        amp.set_audio_input(PortIndex::AmpInput as u32, &input);
+        amp.run(input.len());
+
+        let out = amp.audio_output(PortIndex::AmpOutput as u32);
+        for (o, i) in out.iter().zip(&input) {
+            assert_eq!(*o, distortion(db_co(6.0), *i as f64) as f32);
+            assert!(o.abs() < 1.0);
+        }
+    }
+
     #[test]
-    fn it_works() {
+    fn feedback_delay_echo() {
+        let host = TestHost::new();
+        let mut amp = amp(&host, 1000.0);
+
+        // 10 ms at 1 kHz is a 10 sample delay line
+        let mut input = vec![0.0; 32];
+        input[0] = 1.0;
+        amp.set_control(PortIndex::AmpFeedback as u32, 0.5);
+        amp.set_audio_input(PortIndex::AmpInput as u32, &input);
+        amp.run(input.len());
+
+        let out = amp.audio_output(PortIndex::AmpOutput as u32);
+        let first = distortion(1.0, 1.0) as f32;
+        assert_eq!(out[0], first);
+        assert!(out[1..10].iter().all(|x| *x == 0.0));
+        assert_eq!(out[10], -0.5 * first);
+        assert_eq!(out[20], 0.25 * first);
+    }
+
+    #[test]
+    fn master_level() {
+        let host = TestHost::new();
+        let mut amp = amp(&host, 48000.0);
+
+        let input = vec![0.5; 64];
+        amp.set_control(PortIndex::AmpMaster as u32, 0.0);
+        amp.set_audio_input(PortIndex::AmpInput as u32, &input);
+        amp.run(input.len());
+
+        assert!(amp.audio_output(PortIndex::AmpOutput as u32).iter().all(|x| *x == 0.0));
     }
 }
 
@@ -30,18 +100,16 @@ mod tests {
    replacing `http:/` with `lv2` any header in the specification bundle can be
    included, in this case `lv2.h`.
 */
-mod delay;
+pub mod delay;
 
 extern crate libc;
-extern crate lv2_raw;
+extern crate rustlv2;
 extern crate num;
 
 
-use lv2_raw::*;
-use std::f32;
+use rustlv2::*;
 use std::f64;
-use std::mem::*;
-use libc::{c_char, c_void};
+use libc::c_void;
 
 use delay::*;
 
@@ -52,84 +120,74 @@ use delay::*;
    file is a good convention to follow.  If this URI does not match that used
    in the data files, the host will fail to load the plugin.
 */
-static AMP_URI: &'static [u8] = b"http://example.org/rustamp\0";
+const AMP_URI: &'static [u8] = b"http://example.org/rustamp\0";
 
 /*
    In code, ports are referred to by index.  An enumeration of port indices
    should be defined for readability.
 */
-enum PortIndex {
-    AmpGain   = 0,
-    AmpInput  = 1,
-    AmpOutput = 2,
-    AmpDelay  = 3,
-    AmpFeedback = 4,
-    AmpMaster = 5
-}
-
-impl PortIndex {
-
-    fn from_u32(x: u32) -> Option<PortIndex> {
-        match x {
-            0 => Some(PortIndex::AmpGain),
-            1 => Some(PortIndex::AmpInput),
-            2 => Some(PortIndex::AmpOutput),
-            3 => Some(PortIndex::AmpDelay),
-            4 => Some(PortIndex::AmpFeedback),
-            5 => Some(PortIndex::AmpMaster),
-            _ => None
-        }
+port_index! {
+    enum PortIndex {
+        AmpGain   = 0,
+        AmpInput  = 1,
+        AmpOutput = 2,
+        AmpDelay  = 3,
+        AmpFeedback = 4,
+        AmpMaster = 5
     }
 }
 
+/*
+   The description of the ports, from which the plugin's .ttl file is
+   generated.
+*/
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::control_in(PortIndex::AmpGain as u32, "gain", "Gain", 0.0, -90.0, 90.0)
+        .unit("units:db")
+        .scale_points(&[("+5", 5.0), ("0", 0.0), ("-5", -5.0), ("-10", -10.0)]),
+    PortInfo::audio_in(PortIndex::AmpInput as u32, "in", "In"),
+    PortInfo::audio_out(PortIndex::AmpOutput as u32, "out", "Out"),
+    PortInfo::control_in(PortIndex::AmpDelay as u32, "delay", "Delay", 1.0, 0.0, 2.0)
+        .unit("units:s")
+        .scale_points(&[("+0.01", 0.01)]),
+    PortInfo::control_in(PortIndex::AmpFeedback as u32, "feedback", "Feedback", 0.5, 0.0, 0.999)
+        .unit("units:factor"),
+    PortInfo::control_in(PortIndex::AmpMaster as u32, "master", "Master", 1.0, 0.0, 1.0)
+        .unit("units:factor")
+];
+
 /*
    Every plugin defines a private structure for the plugin instance.  All data
    associated with a plugin instance is stored here, and is available to
    every instance method.  In this simple plugin, only port buffers need to be
    stored, since there is no additional instance data.
 */
-#[repr(C)]
-struct Amp{
+pub struct Amp {
     // Port buffers
-    gain: *const f32,
-    input: *const f32,
-    output: *mut f32,
-    delay_time: *const f32,
-    delay_feedback: *const f32,
-    delay_master: *const f32,
+    gain: ControlIn,
+    input: AudioIn,
+    output: AudioOut,
+    delay_time: ControlIn,
+    delay_feedback: ControlIn,
+    delay_master: ControlIn,
     sample_rate: u32,
     delay: Delay
 }
 
 impl Amp {
     fn new(size: usize, rate: u32) -> Amp {
-        Amp { gain: (0 as *const f32), 
-            input: (0 as *const f32),   
-            output: (0 as *mut f32),
-            delay_time: (0 as *const f32),
-            delay_feedback: (0 as *const f32),
-            delay_master: (0 as *const f32),
+        Amp { gain: ControlIn::new(),
+            input: AudioIn::new(),
+            output: AudioOut::new(),
+            delay_time: ControlIn::new(),
+            delay_feedback: ControlIn::new(),
+            delay_master: ControlIn::new(),
             sample_rate: rate,
             delay: Delay::new(size)
         }
     }
 }
 
-/* Define a macro for converting a gain in dB to a coefficient. */
-//#define DB_CO(g) ((g) > -90.0f ? powf(10.0f, (g) * 0.05f) : 0.0f)
-
-fn db_co(g: f32) -> f64 {
-    if g > -90.0_f32 {
-        let base = 10.0_f64;
-        base.powf((g as f64) * 0.05_f64)
-    }
-    else {
-        0.0_f64
-    }
-}
-
-struct Descriptor(LV2Descriptor);
-
 
 const PI_2: f64 = std::f64::consts::PI / 2.0;
 const MAX_DELAY_TIME: u32 = 2000;
@@ -140,115 +198,64 @@ fn distortion(gain: f64, inp: f64) -> f64
      x.atan() / PI_2
 }
 
-impl Descriptor {
-    pub extern "C" fn activate(_handle: LV2Handle) {}
-    pub extern "C" fn deactivate(_handle: LV2Handle) {}
-
-
-    pub extern "C" fn run(handle: LV2Handle, n_samples: u32) {
-        let _amp = handle as *mut Amp;
+impl Plugin for Amp {
+    const URI: &'static [u8] = AMP_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Simple Rust Amplifier with Delay",
+        classes: &["lv2:AmplifierPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable"],
+        extension_data: &[],
+        supported_options: &[],
+        ports: PORTS,
+        parameters: &[]
+    };
+
+    fn instantiate(rate: f64, _features: &Features) -> Option<Amp> {
+        let rate = rate as u32;
+        let size = delay::msec_to_n_samples(MAX_DELAY_TIME, rate);
+
+        Some(Amp::new(size, rate))
+    }
 
-        let mut amp = unsafe { &mut *_amp };
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::AmpGain) => self.gain.connect(data),
+            Some(PortIndex::AmpInput) => self.input.connect(data),
+            Some(PortIndex::AmpOutput) => self.output.connect(data),
+            Some(PortIndex::AmpDelay) => self.delay_time.connect(data),
+            Some(PortIndex::AmpFeedback) => self.delay_feedback.connect(data),
+            Some(PortIndex::AmpMaster) => self.delay_master.connect(data),
+            None => ()
+        }
+    }
 
+    fn run(&mut self, n_samples: u32) {
         let n = n_samples as usize;
 
-        let gain = unsafe { *amp.gain };
-        let input = unsafe { std::slice::from_raw_parts(amp.input, n) };
-        let output = unsafe { std::slice::from_raw_parts_mut(amp.output, n) };
-        let delay_time = unsafe { *amp.delay_time }; 
-        let delay_feedback = unsafe { *amp.delay_feedback };
-        let delay_master = unsafe { *amp.delay_master };
-        let ref mut delay = amp.delay;
+        let gain = self.gain.value();
+        let input = self.input.as_slice(n);
+        let output = self.output.as_mut_slice(n);
+        let delay_time = self.delay_time.value();
+        let delay_feedback = self.delay_feedback.value();
+        let delay_master = self.delay_master.value();
+        let delay = &mut self.delay;
 
         let coef = db_co(gain);
 
         // set new delay time if changed
-        let delay_size = delay::sec_to_n_samples(delay_time, amp.sample_rate as u32);
+        let delay_size = delay::sec_to_n_samples(delay_time, self.sample_rate);
         delay.set_length(delay_size);
         delay.set_vals(delay_feedback, delay_master);
 
-        let mut idx = 0;
-        for pos in input {
-
-            let val = distortion(coef, *pos as f64);
-            let valout = delay.feedbackdelay(val);
-
-            output[idx] = valout as f32;
-            idx = idx + 1;
-        }
-    }
-
-    pub extern "C" fn connect_port(instance: LV2Handle,
-        port: u32,
-        data : *mut c_void)
-    {
-        let mut amp = unsafe { &mut *(instance as *mut Amp) };
-        let p = PortIndex::from_u32(port);
-
-        match p {
-            Some(PortIndex::AmpGain) => amp.gain = data as *const f32,
-            Some(PortIndex::AmpInput) => amp.input = data as *const f32 ,
-            Some(PortIndex::AmpOutput) => amp.output = data as *mut f32 ,
-            Some(PortIndex::AmpDelay) => amp.delay_time = data as *const f32 ,
-            Some(PortIndex::AmpFeedback) => amp.delay_feedback = data as *const f32,
-            Some(PortIndex::AmpMaster) => amp.delay_master = data as *const f32,
-            None => println!("Not a valid port index: {}", port)
-        }
-    }
-
-    pub extern "C" fn instantiate(_desc: *const LV2Descriptor,
-        _rate: f64,
-        _bundle_path: *const c_char,
-        _features: *const *const LV2Feature) -> LV2Handle {
-
-            let rate = _rate as u32;
-            let size = delay::msec_to_n_samples(MAX_DELAY_TIME, rate);
-
-            let ptr: *mut Amp =  unsafe { transmute(Box::new(Amp::new(size, rate))) };
-
-            return ptr as LV2Handle;
-    }
-
-    pub extern "C" fn cleanup(handle: LV2Handle) {
-        unsafe { 
-            let _drop: Box<Amp> = transmute(((handle as *mut Amp)));
+        for (out, inp) in output.iter_mut().zip(input) {
+            let val = distortion(coef, *inp as f64);
+            *out = delay.feedbackdelay(val) as f32;
         }
     }
-
-    pub extern "C" fn extension_data(_uri: *const u8) -> *const c_void {
-        return 0 as *const c_void
-    }
-}
-
-static mut DESCRIPTOR: LV2Descriptor = LV2Descriptor {
-    uri: 0 as *const c_char,
-    instantiate: Descriptor::instantiate,
-    connect_port: Descriptor::connect_port,
-    activate: Some(Descriptor::activate),
-    run: Descriptor::run,
-    deactivate: Some(Descriptor::deactivate),
-    cleanup: Descriptor::cleanup,
-    extension_data: Descriptor::extension_data
-};
-
-/*
-   The `lv2_descriptor()` function is the entry point to the plugin library.  The
-   host will load the library and call this function repeatedly with increasing
-   indices to find all the plugins defined in the library.  The index is not an
-   indentifier, the URI of the returned descriptor is used to determine the
-   identify of the plugin.
-
-   This method is in the ``discovery'' threading class, so no other functions
-   or methods in this plugin library will be called concurrently with it.
-*/
-
-#[no_mangle]
-pub extern "C" fn lv2_descriptor(index: u32) -> *const LV2Descriptor {
-    match index {
-        0 => unsafe {
-            DESCRIPTOR.uri = AMP_URI.as_ptr() as *const c_char;
-            return &DESCRIPTOR as *const LV2Descriptor },
-        _ => return 0 as *const LV2Descriptor
-    }
 }
 
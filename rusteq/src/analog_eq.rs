@@ -2,6 +2,9 @@
 
 // ZynaddSubFx filters
 use std::f64::consts::*;
+use num::complex::Complex64;
+
+use response::FrequencyResponse;
 
 
 #[derive(Clone, Copy, Debug)]
@@ -10,6 +13,49 @@ pub struct Coeff {
     d: [f64; 3]
 }
 
+/*
+   One section computes y = c0 x + c1 x1 + c2 x2 + d1 y1 + d2 y2, its
+   transfer function is therefore
+
+       H(z) = (c0 + c1 z^-1 + c2 z^-2) / (1 - d1 z^-1 - d2 z^-2)
+
+   First order sections only use c0, c1 and d1.  `omega` is the angular
+   frequency in radians per sample.
+*/
+impl Coeff {
+    fn polynomials(&self, order: u32) -> ([f64; 3], [f64; 3]) {
+        if order == 1 {
+            ([self.c[0], self.c[1], 0.0], [1.0, -self.d[1], 0.0])
+        } else {
+            ([self.c[0], self.c[1], self.c[2]], [1.0, -self.d[1], -self.d[2]])
+        }
+    }
+
+    pub fn response(&self, order: u32, omega: f64) -> Complex64 {
+        let (b, a) = self.polynomials(order);
+        let z1 = Complex64::from_polar(&1.0, &-omega);
+        let z2 = z1 * z1;
+        (z2 * b[2] + z1 * b[1] + b[0]) / (z2 * a[2] + z1 * a[1] + a[0])
+    }
+
+    /*
+       The group delay in samples, exactly: for a polynomial P(z) of z^-1
+       the delay is Re(sum(k p_k z^-k) / P(z)), and the one of H is that of
+       the numerator minus that of the denominator.
+    */
+    pub fn group_delay(&self, order: u32, omega: f64) -> f64 {
+        let (b, a) = self.polynomials(order);
+        let delay = |p: [f64; 3]| {
+            let z1 = Complex64::from_polar(&1.0, &-omega);
+            let z2 = z1 * z1;
+            let sum = z2 * p[2] + z1 * p[1] + p[0];
+            let weighted = z2 * (2.0 * p[2]) + z1 * p[1];
+            if sum.norm() == 0.0 { 0.0 } else { (weighted / sum).re }
+        };
+        delay(b) - delay(a)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct FStage {
     x1: f64,
@@ -22,8 +68,52 @@ pub struct FStage {
 
 pub const MAX_FILTER_STAGES: u8 = 5;
 
+// the most sections in series, of the stages or of a slope
+pub const MAX_SECTIONS: usize = (MAX_FILTER_STAGES + 1) as usize;
 
+/* The sections of a filter in series, each with its own coefficients and order. */
 #[derive(Clone, Copy, Debug)]
+pub struct Cascade {
+    coeff: [Coeff; MAX_SECTIONS],
+    order: [u32; MAX_SECTIONS],
+    len: usize
+}
+
+impl Cascade {
+    /* `count` equal sections. */
+    pub fn repeat(coeff: Coeff, order: u32, count: usize) -> Cascade {
+        Cascade { coeff: [coeff; MAX_SECTIONS], order: [order; MAX_SECTIONS], len: count.min(MAX_SECTIONS) }
+    }
+
+    fn empty() -> Cascade {
+        Cascade::repeat(Coeff { c: [0.0; 3], d: [0.0; 3] }, 1, 0)
+    }
+
+    fn push(&mut self, coeff: Coeff, order: u32) -> () {
+        if self.len < MAX_SECTIONS {
+            self.coeff[self.len] = coeff;
+            self.order[self.len] = order;
+            self.len += 1;
+        }
+    }
+
+    pub fn response(&self, omega: f64) -> Complex64 {
+        (0..self.len).fold(Complex64::new(1.0, 0.0), |h, i| h * self.coeff[i].response(self.order[i], omega))
+    }
+
+    pub fn group_delay(&self, omega: f64) -> f64 {
+        (0..self.len).map(|i| self.coeff[i].group_delay(self.order[i], omega)).sum()
+    }
+}
+
+// block size if the host does not tell its maximum (the biggest jack uses)
+pub const DEFAULT_MAX_BLOCK: usize = 8192;
+
+// length of the crossfade after a parameter change, in seconds
+pub const FADE_TIME: f64 = 0.01;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum FilterType {
     LPF1,
     HPF1,
@@ -33,9 +123,26 @@ pub enum FilterType {
     NOTCH2,
     PEAK2,
     LoShelf,
-    HiShelf
+    HiShelf,
+    // first and second order allpass
+    APF1,
+    APF2,
+    // lows cut and highs raised by half the gain each, or the other way
+    // round for a gain below 0 dB
+    Tilt,
+    // band pass with a peak of 0 dB, and one with the gain at the peak
+    BPFPeak,
+    BPFGain
 }
 
+/* Labels and values of the types, for the scale points of type controls. */
+pub const FILTER_TYPES: &'static [(&'static str, f32)] = &[("LPF1", 0.0), ("HPF1", 1.0), ("LPF2", 2.0),
+    ("HPF2", 3.0), ("BPF2", 4.0), ("NOTCH", 5.0), ("PEAK", 6.0), ("LoShelf", 7.0), ("HiShelf", 8.0),
+    ("APF1", 9.0), ("APF2", 10.0), ("Tilt", 11.0), ("BPF 0dB", 12.0), ("BPF Gain", 13.0)];
+
+// the highest value of a type control
+pub const LAST_FILTER_TYPE: f32 = 13.0;
+
 impl FilterType {
 
     pub fn from_u32(x: u32) -> FilterType {
@@ -49,6 +156,11 @@ impl FilterType {
             6 => FilterType::PEAK2,
             7 => FilterType::LoShelf,
             8 => FilterType::HiShelf,
+            9 => FilterType::APF1,
+            10 => FilterType::APF2,
+            11 => FilterType::Tilt,
+            12 => FilterType::BPFPeak,
+            13 => FilterType::BPFGain,
             _ => FilterType::LPF1
         }
     }
@@ -63,7 +175,12 @@ impl FilterType {
             FilterType::NOTCH2 => 5,
             FilterType::PEAK2 => 6,
             FilterType::LoShelf => 7,
-            FilterType::HiShelf => 8
+            FilterType::HiShelf => 8,
+            FilterType::APF1 => 9,
+            FilterType::APF2 => 10,
+            FilterType::Tilt => 11,
+            FilterType::BPFPeak => 12,
+            FilterType::BPFGain => 13
         }
     }
 }
@@ -72,7 +189,7 @@ impl FilterType {
 
 #[warn(non_snake_case)]
 pub fn db_2_rap(db: f64) -> f64 {
-    db.exp() * LN_10 / 20.0
+    (db * LN_10 / 20.0).exp()
 }
 
 #[warn(non_snake_case)]
@@ -80,33 +197,230 @@ pub fn rap_2_db(rap: f64) -> f64 {
     (20.0 * rap.ln()) / LN_10
 }
 
+
+/*
+   How the coefficients are derived from the analog prototypes.  The
+   bilinear transform of ZynAddSubFX cramps the response towards Nyquist,
+   which the matched design avoids: its poles are the impulse invariant ones
+   and its zeros are chosen so that the magnitude equals the one of the
+   analog prototype at DC, Nyquist and the filter frequency (M. Vicanek,
+   "Matched Second Order Digital Filters", 2016).  The allpasses have the
+   magnitude of their prototype with either design and stay bilinear.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Design {
+    Bilinear,
+    Matched
+}
+
+impl Design {
+    pub fn from_u32(x: u32) -> Design {
+        match x {
+            1 => Design::Matched,
+            _ => Design::Bilinear
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            Design::Bilinear => 0,
+            Design::Matched => 1
+        }
+    }
+}
+
+/* Labels and values of the designs, for the scale points of design controls. */
+pub const DESIGNS: &'static [(&'static str, f32)] = &[("Bilinear", 0.0), ("Matched", 1.0)];
+
+/*
+   The slope of the low and high passes.  `Stages` is the ZynAddSubFX way,
+   the section of the type repeated with the Q spread over the stages.  The
+   others are the textbook designs of the given order, as sections with their
+   own frequency and Q, which replace the type's order, the Q and the stages.
+   A Linkwitz-Riley filter is a Butterworth filter of half the order squared,
+   the Chebyshev (type I) filters ripple by `CHEBYSHEV_RIPPLE` in the
+   passband.  Other types than the passes ignore the slope.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slope {
+    Stages,
+    Butterworth(u8),
+    LinkwitzRiley(u8),
+    Chebyshev(u8)
+}
+
+// in dB
+pub const CHEBYSHEV_RIPPLE: f64 = 1.0;
+
+impl Slope {
+    pub fn from_u32(x: u32) -> Slope {
+        match x {
+            1..=8 => Slope::Butterworth(x as u8),
+            9 => Slope::LinkwitzRiley(2),
+            10 => Slope::LinkwitzRiley(4),
+            11 => Slope::LinkwitzRiley(8),
+            12..=15 => Slope::Chebyshev(2 * (x - 11) as u8),
+            _ => Slope::Stages
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            Slope::Stages => 0,
+            Slope::Butterworth(order) => order as u32,
+            Slope::LinkwitzRiley(2) => 9,
+            Slope::LinkwitzRiley(4) => 10,
+            Slope::LinkwitzRiley(_) => 11,
+            Slope::Chebyshev(order) => 11 + order as u32 / 2
+        }
+    }
+
+    /*
+       For the Linkwitz-Riley slopes, the allpass which their low and high
+       pass of the same frequency add up to (with the high pass inverted for
+       12 dB), so that the bands of a crossover can be kept in phase: the Q
+       of its sections, 0 for a first order one.
+    */
+    pub fn crossover_allpass(self) -> Option<([f64; MAX_SECTIONS], usize)> {
+        match self {
+            Slope::LinkwitzRiley(n) => {
+                let (sections, len, _) = Slope::Butterworth(n / 2).prototype();
+                let mut q = [0.0; MAX_SECTIONS];
+                for (q, section) in q.iter_mut().zip(sections[..len].iter()) {
+                    *q = section.1;
+                }
+                Some((q, len))
+            }
+            _ => None
+        }
+    }
+
+    /*
+       The sections of the low pass prototype with a cutoff of 1, as the
+       frequency and Q of each, a Q of 0 for a first order section, and the
+       gain of the passband.
+    */
+    fn prototype(self) -> ([(f64, f64); MAX_SECTIONS], usize, f64) {
+        let mut sections = [(1.0, 0.0); MAX_SECTIONS];
+        let mut len = 0;
+        let mut gain = 1.0;
+        {
+            let mut push = |w: f64, q: f64| {
+                if len < MAX_SECTIONS {
+                    sections[len] = (w, q);
+                    len += 1;
+                }
+            };
+            match self {
+                Slope::Stages => (),
+                Slope::Butterworth(n) | Slope::LinkwitzRiley(n) => {
+                    let (n, times) = if let Slope::LinkwitzRiley(_) = self { (n / 2, 2) } else { (n, 1) };
+                    for _ in 0..times {
+                        for k in 1..(n / 2 + 1) {
+                            let theta = PI * (2 * k - 1) as f64 / (2 * n) as f64;
+                            push(1.0, 1.0 / (2.0 * theta.sin()));
+                        }
+                        if n % 2 == 1 {
+                            push(1.0, 0.0);
+                        }
+                    }
+                }
+                Slope::Chebyshev(n) => {
+                    let epsilon = (db_2_rap(CHEBYSHEV_RIPPLE).powi(2) - 1.0).sqrt();
+                    let v = (1.0 / epsilon).asinh() / n as f64;
+                    for k in 1..(n / 2 + 1) {
+                        let theta = PI * (2 * k - 1) as f64 / (2 * n) as f64;
+                        let sigma = v.sinh() * theta.sin();
+                        let omega = v.cosh() * theta.cos();
+                        let w = (sigma * sigma + omega * omega).sqrt();
+                        push(w, w / (2.0 * sigma));
+                    }
+                    if n % 2 == 1 {
+                        push(v.sinh(), 0.0);
+                    } else {
+                        // the ripple is below 0 dB, starting at the bottom
+                        gain = 1.0 / (1.0 + epsilon * epsilon).sqrt();
+                    }
+                }
+            }
+        }
+        (sections, len, gain)
+    }
+}
+
+/* Labels and values of the slopes, for the scale points of slope controls. */
+pub const SLOPES: &'static [(&'static str, f32)] = &[("Stages", 0.0),
+    ("Butterworth 6 dB", 1.0), ("Butterworth 12 dB", 2.0), ("Butterworth 18 dB", 3.0), ("Butterworth 24 dB", 4.0),
+    ("Butterworth 30 dB", 5.0), ("Butterworth 36 dB", 6.0), ("Butterworth 42 dB", 7.0), ("Butterworth 48 dB", 8.0),
+    ("Linkwitz-Riley 12 dB", 9.0), ("Linkwitz-Riley 24 dB", 10.0), ("Linkwitz-Riley 48 dB", 11.0),
+    ("Chebyshev 12 dB", 12.0), ("Chebyshev 24 dB", 13.0), ("Chebyshev 36 dB", 14.0), ("Chebyshev 48 dB", 15.0)];
+
+// the highest value of a slope control
+pub const LAST_SLOPE: f32 = 15.0;
+
+/*
+   Changing the coefficients of a running filter clicks, so after a change
+   the old filter keeps running for `FADE_TIME` (see `set_fade_len`) and the
+   output is crossfaded from it to the new one.  Changes during a crossfade
+   wait for its end, a sweep therefore moves in steps of the crossfade, each
+   of them crossfaded.
+*/
 #[derive(Debug)]
 pub struct AnalogFilter {
-    coeff: Coeff,
-    old_coeff: Coeff,
+    cascade: Cascade,
+    old_cascade: Cascade,
 
     history: [FStage; (MAX_FILTER_STAGES + 1) as usize],
     old_history: [FStage; (MAX_FILTER_STAGES + 1) as usize],
 
+    // the parameters, which the coefficients follow
     filter_type: FilterType,
     stages: u8,
     freq: f64,
     q: f64,
     gain: f64,
+    design: Design,
+    slope: Slope,
+    // a disabled filter passes the signal unchanged
+    enabled: bool,
 
-    order: u32,
 
-    needs_interpolation: bool,
     first_time: bool,
 
-    above_nq: bool,
-    oldabove_nq: bool,
-
-    samplerate: u32,
-    outgain: f64,
+    // position in the crossfade, none if equal to fade_len
+    fade_pos: usize,
+    fade_len: usize,
+    // parameters changed during the crossfade
+    pending: bool,
 
     samplerate_f: f64,
-    halfsamplerate_f: f64
+
+    // output of the old coefficients while interpolating, allocated up
+    // front so that filterout does not allocate in the realtime thread
+    scratch: Vec<f32>
+}
+
+
+/*
+   The Q control of a filter for the Q `q` of the RBJ cookbook, as e.g.
+   Equalizer APO uses it.  `computefiltercoefs` triples the Q of the peak
+   and takes the square root of it for the shelves and the notch.
+*/
+pub fn control_q(ftype: FilterType, q: f32) -> f32 {
+    match ftype {
+        FilterType::PEAK2 => q / 3.0,
+        FilterType::LoShelf | FilterType::HiShelf | FilterType::NOTCH2 => q * q,
+        _ => q
+    }
+}
+
+/* The RBJ Q of a filter with the Q control `q`, the inverse of `control_q`. */
+pub fn rbj_q(ftype: FilterType, q: f32) -> f32 {
+    match ftype {
+        FilterType::PEAK2 => q * 3.0,
+        FilterType::LoShelf | FilterType::HiShelf | FilterType::NOTCH2 => q.sqrt(),
+        _ => q
+    }
 }
 
 
@@ -116,19 +430,18 @@ impl AnalogFilter {
         ffreq: f32,
         fq: f32,
         fstages: u8,
-        srate: u32) -> AnalogFilter {
+        srate: u32,
+        max_block: usize) -> AnalogFilter {
             
             let st = if fstages > MAX_FILTER_STAGES { MAX_FILTER_STAGES } else { fstages }; 
             
+            let fade_len = ((srate as f64 * FADE_TIME) as usize).max(1);
+
             let mut f = AnalogFilter {
-                outgain: 1.0,
-                samplerate: srate,
                 samplerate_f: srate as f64,
 
-                halfsamplerate_f: (srate as f64) / 2.0,
-
-                coeff: Coeff { c: [0.0; 3], d: [0.0; 3] },
-                old_coeff: Coeff { c: [0.0; 3], d: [0.0; 3] },
+                cascade: Cascade::empty(),
+                old_cascade: Cascade::empty(),
 
                 history: [FStage {x1: 0.0, x2:  0.0, y1: 0.0, y2: 0.0 }; (MAX_FILTER_STAGES + 1) as usize],
                 old_history: [FStage {x1: 0.0, x2:  0.0, y1: 0.0, y2: 0.0 }; (MAX_FILTER_STAGES + 1) as usize],
@@ -138,15 +451,17 @@ impl AnalogFilter {
                 freq: ffreq as f64,
                 q: fq as f64,
                 gain: 1.0,
+                design: Design::Bilinear,
+                slope: Slope::Stages,
+                enabled: true,
 
-                order: 1,
-
-                needs_interpolation: false,
                 first_time: true,
 
-                above_nq: false,
-                oldabove_nq: false
+                fade_pos: fade_len,
+                fade_len: fade_len,
+                pending: false,
 
+                scratch: vec![0.0; max_block.max(1)]
             };
 
             f.setfreq(ffreq);
@@ -155,39 +470,55 @@ impl AnalogFilter {
 
 
     pub fn setfreq(&mut self, freq: f32) -> () {
-        let frequency = if freq < 0.1 { 0.1_f64 } else { freq as f64 };
-
-        let mut rap = self.freq / frequency;
-        if rap < 1.0 { rap = 1.0 / rap }
+        self.freq = if freq < 0.1 { 0.1_f64 } else { freq as f64 };
+        self.changed();
+    }
 
-        self.oldabove_nq = self.above_nq;
-        self.above_nq = frequency > (self.halfsamplerate_f - 500.0);
+    /* Switch to the coefficients for the current parameters at once. */
+    pub fn computefiltercoefs(&mut self) -> () {
+        //println!("computefiltercoefs: {:?}", self.filter_type);
 
-        let nyquistthresh = self.above_nq ^ self.oldabove_nq;
+        let cascade = self.design_cascade();
 
-        if rap > 3.0 || nyquistthresh {
-            self.old_coeff = self.coeff;
-            
-            self.old_history = self.history;
-            if !self.first_time { self.needs_interpolation = true };
+        // sections which were not running so far start from silence
+        for stage in self.cascade.len..cascade.len {
+            self.history[stage] = FStage { x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 };
         }
 
-        self.freq = frequency;
-        self.computefiltercoefs();
-        self.first_time = false;
+        self.cascade = cascade;
     }
 
-    pub fn computefiltercoefs(&mut self) -> () {
-        //println!("computefiltercoefs: {:?}", self.filter_type);
+    /* The parameters changed, crossfade to the new coefficients. */
+    fn changed(&mut self) -> () {
+        if self.first_time {
+            self.computefiltercoefs();
+            self.first_time = false;
+        } else if self.fade_pos < self.fade_len {
+            self.pending = true;
+        } else {
+            self.old_cascade = self.cascade;
+            self.old_history = self.history;
+            self.computefiltercoefs();
+            self.fade_pos = 0;
+        }
+    }
 
-        let (coeff, order) = AnalogFilter::compute_coeff(self.filter_type, self.freq, self.q, self.stages,
-            self.gain, self.samplerate_f);
 
-        self.coeff = coeff;
-        self.order = order;
+    /* Q and gain of each of the `stages` + 1 equal sections. */
+    fn section_q_gain(q: f64, gain: f64, stages: u8) -> (f64, f64) {
+        if stages == 0 {
+            (q, gain)
+        } else {
+            let fact = 1.0 / ((stages + 1) as f64);
+            let tmpq = if q > 1.0 { 
+                    q.powf(fact)
+                } else {
+                    q
+                };
+            (tmpq, gain.powf(fact))
+        }
     }
 
-
     pub fn compute_coeff(ftype: FilterType,
         cutoff: f64,
         fq: f64,
@@ -221,21 +552,7 @@ impl AnalogFilter {
             q = 0.0;
         }
 
-        let mut tmpq;
-        let tmpgain;
-
-        if stages == 0 {
-            tmpq = q;
-            tmpgain = gain;
-        } else {
-            let fact = 1.0 / ((stages + 1) as f64);
-            tmpq = if q > 1.0 { 
-                    q.powf(fact)
-                } else {
-                    q
-                };
-            tmpgain = gain.powf(fact);
-        }
+        let (mut tmpq, tmpgain) = AnalogFilter::section_q_gain(q, gain, stages);
 
         let omega = 2.0 * PI * freq / samplerate_f;
         let sn = omega.sin();
@@ -378,40 +695,334 @@ impl AnalogFilter {
                 }
                 order = 2;
             }
+            // the rest are from the RBJ cookbook
+            FilterType::APF1 => {
+                if !zerocoefs {
+                    tmp = (PI * freq / samplerate_f).tan();
+                    let a = (tmp - 1.0) / (tmp + 1.0);
+                    coeff.c[0] = a;
+                    coeff.c[1] = 1.0;
+                    coeff.d[1] = -a;
+                } else {
+                    coeff.c[0] = 1.0;
+                }
+                order = 1;
+            }
+            FilterType::APF2 => {
+                if !zerocoefs {
+                    alpha = sn / (2.0 * tmpq);
+                    tmp = 1.0 + alpha;
+                    coeff.c[0] = (1.0 - alpha) / tmp;
+                    coeff.c[1] = -2.0 * cs / tmp;
+                    coeff.c[2] = 1.0;
+                    coeff.d[1] = -2.0 * cs / tmp * -1.0;
+                    coeff.d[2] = (1.0 - alpha) / tmp * -1.0;
+                } else {
+                    coeff.c[0] = 1.0;
+                }
+                order = 2;
+            }
+            // the high shelf lowered by the gain: 1 / gain at DC, gain at Nyquist
+            FilterType::Tilt => {
+                if !zerocoefs {
+                    tmpq = tmpq.sqrt();
+                    beta = tmpgain.sqrt() / tmpq;
+                    tgp1 = tmpgain + 1.0;
+                    tgm1 = tmpgain - 1.0;
+                    tmp = tgp1 - tgm1 * cs + beta * sn;
+
+                    coeff.c[0] = (tgp1 + tgm1 * cs + beta * sn) / tmp;
+                    coeff.c[1] = -2.0 * (tgm1 + tgp1 * cs) / tmp;
+                    coeff.c[2] = (tgp1 + tgm1 * cs - beta * sn) / tmp;
+                    coeff.d[1] = 2.0 * (tgm1 - tgp1 * cs) / tmp * -1.0;
+                    coeff.d[2] = (tgp1 - tgm1 * cs - beta * sn) / tmp * -1.0;
+                } else {
+                    coeff.c[0] = 1.0 / tmpgain;
+                }
+                order = 2;
+            }
+            // the gain is squared like the one of the peak and the shelves
+            FilterType::BPFPeak | FilterType::BPFGain => {
+                let peak = if let FilterType::BPFGain = ftype { tmpgain * tmpgain } else { 1.0 };
+                if !zerocoefs {
+                    alpha = sn / (2.0 * tmpq);
+                    tmp = 1.0 + alpha;
+                    coeff.c[0] = alpha / tmp * peak;
+                    coeff.c[1] = 0.0;
+                    coeff.c[2] = -alpha / tmp * peak;
+                    coeff.d[1] = -2.0 * cs / tmp * -1.0;
+                    coeff.d[2] = (1.0 - alpha) / tmp * -1.0;
+                }
+                order = 2;
+            }
         }
         
         (coeff, order)
     }
 
+    /*
+       The sections for the current parameters in the selected design: the
+       type's section repeated for the stages, or for the passes with a
+       slope, the sections of the slope's design.
+    */
+    fn design_cascade(&self) -> Cascade {
+        if !self.enabled {
+            return Cascade::repeat(Coeff { c: [1.0, 0.0, 0.0], d: [0.0; 3] }, 1, 1);
+        }
+        let (ftype, slope, design) = (self.filter_type, self.slope, self.design);
+        let (cutoff, fs) = (self.freq, self.samplerate_f);
+
+        let compute = match design {
+            Design::Bilinear => AnalogFilter::compute_coeff,
+            Design::Matched => AnalogFilter::compute_matched_coeff
+        };
+        let highpass = match (ftype, slope) {
+            (_, Slope::Stages) => None,
+            (FilterType::LPF1, _) | (FilterType::LPF2, _) => Some(false),
+            (FilterType::HPF1, _) | (FilterType::HPF2, _) => Some(true),
+            _ => None
+        };
+        let highpass = match highpass {
+            Some(highpass) => highpass,
+            None => {
+                let (coeff, order) = compute(ftype, cutoff, self.q, self.stages, self.gain, fs);
+                return Cascade::repeat(coeff, order, self.stages as usize + 1);
+            }
+        };
+
+        let (sections, len, passband) = slope.prototype();
+        let mut cascade = Cascade::empty();
+        for &(w, q) in sections[..len].iter() {
+            // the high pass mirrors the low pass at the cutoff, the bilinear
+            // sections are placed on the prewarped axis so that together they
+            // are the transform of the whole prototype
+            let w = if highpass { 1.0 / w } else { w };
+            let freq = match design {
+                Design::Bilinear => fs / PI * (w * (PI * cutoff.min(fs / 2.0 - 500.0) / fs).tan()).atan(),
+                Design::Matched => cutoff * w
+            };
+            let ftype = match (highpass, q == 0.0) {
+                (false, true) => FilterType::LPF1,
+                (false, false) => FilterType::LPF2,
+                (true, true) => FilterType::HPF1,
+                (true, false) => FilterType::HPF2
+            };
+            let (coeff, order) = match (design, q == 0.0) {
+                (Design::Bilinear, true) => AnalogFilter::first_order_coeff(highpass, freq, fs),
+                _ => compute(ftype, freq, q, 0, 1.0, fs)
+            };
+            cascade.push(coeff, order);
+        }
+        for c in cascade.coeff[0].c.iter_mut() {
+            *c *= passband;
+        }
+        cascade
+    }
+
+    /*
+       A first order pass by the bilinear transform.  Unlike the impulse
+       invariant `LPF1` of ZynAddSubFX it adds up to 1 with the high pass of
+       the same frequency, which the Linkwitz-Riley crossovers rely on.
+    */
+    fn first_order_coeff(highpass: bool, freq: f64, fs: f64) -> (Coeff, u32) {
+        let k = (PI * freq.max(0.1).min(fs / 2.0 - 500.0) / fs).tan();
+        let mut coeff = Coeff { c: [0.0; 3], d: [0.0; 3] };
+        if highpass {
+            coeff.c[0] = 1.0 / (1.0 + k);
+            coeff.c[1] = -1.0 / (1.0 + k);
+        } else {
+            coeff.c[0] = k / (1.0 + k);
+            coeff.c[1] = k / (1.0 + k);
+        }
+        coeff.d[1] = (1.0 - k) / (1.0 + k);
+        (coeff, 1)
+    }
+
+    /*
+       The analog prototype of a section the bilinear design transforms, as
+       numerator and denominator in ascending powers of s / (2 pi freq), with
+       Q and gain already adjusted like `compute_coeff` does.  None for the
+       allpasses.
+    */
+    fn analog_prototype(ftype: FilterType, q: f64, gain: f64) -> Option<([f64; 3], [f64; 3])> {
+        let a = gain;
+        let shelf_q = q.sqrt();
+        let beta = a.sqrt() / shelf_q;
+        let bandpass = |peak: f64| ([0.0, peak / q, 0.0], [1.0, 1.0 / q, 1.0]);
+
+        match ftype {
+            FilterType::LPF1 => Some(([1.0, 0.0, 0.0], [1.0, 1.0, 0.0])),
+            FilterType::HPF1 => Some(([0.0, 1.0, 0.0], [1.0, 1.0, 0.0])),
+            FilterType::LPF2 => Some(([1.0, 0.0, 0.0], [1.0, 1.0 / q, 1.0])),
+            FilterType::HPF2 => Some(([0.0, 0.0, 1.0], [1.0, 1.0 / q, 1.0])),
+            FilterType::BPF2 => Some(bandpass((q + 1.0).sqrt())),
+            FilterType::BPFPeak => Some(bandpass(1.0)),
+            FilterType::BPFGain => Some(bandpass(a * a)),
+            FilterType::NOTCH2 => Some(([1.0, 0.0, 1.0], [1.0, 1.0 / q.sqrt(), 1.0])),
+            FilterType::PEAK2 => {
+                let q = q * 3.0;
+                Some(([1.0, a / q, 1.0], [1.0, 1.0 / (a * q), 1.0]))
+            }
+            FilterType::LoShelf => Some(([a * a, a * beta, a], [1.0, beta, a])),
+            FilterType::HiShelf => Some(([a, a * beta, a * a], [a, beta, 1.0])),
+            FilterType::Tilt => Some(([1.0, beta, a], [a, beta, 1.0])),
+            FilterType::APF1 | FilterType::APF2 => None
+        }
+    }
+
+    /*
+       The coefficients of the matched design, see `Design`.  Unlike the
+       bilinear ones they follow the prototype up to Nyquist.
+    */
+    pub fn compute_matched_coeff(ftype: FilterType,
+        cutoff: f64,
+        fq: f64,
+        stages: u8,
+        gain: f64,
+        fs: f64) -> (Coeff, u32) {
+
+        let (q, section_gain) = AnalogFilter::section_q_gain(fq.max(0.0), gain, stages);
+        let (b, a) = match AnalogFilter::analog_prototype(ftype, q, section_gain) {
+            Some(prototype) => prototype,
+            None => return AnalogFilter::compute_coeff(ftype, cutoff, fq, stages, gain, fs)
+        };
+
+        let omega0 = 2.0 * PI * cutoff.max(0.1).min(fs / 2.0) / fs;
+        // the magnitude of the prototype at `omega`
+        let analog = |omega: f64| {
+            let x = omega / omega0;
+            Complex64::new(b[0] - b[2] * x * x, b[1] * x).norm() / Complex64::new(a[0] - a[2] * x * x, a[1] * x).norm()
+        };
+        let mut coeff = Coeff { c: [0.0; 3], d: [0.0; 3] };
+
+        if a[2] == 0.0 {
+            // one pole, matched at DC and Nyquist
+            let p = (-omega0 * a[0] / a[1]).exp();
+            let r0 = analog(0.0) * (1.0 - p);
+            let r1 = analog(PI) * (1.0 + p);
+            coeff.c[0] = (r0 + r1) / 2.0;
+            coeff.c[1] = (r0 - r1) / 2.0;
+            coeff.d[1] = p;
+            return (coeff, 1);
+        }
+
+        // the impulse invariant poles
+        let wn = omega0 * (a[0] / a[2]).sqrt();
+        let zeta = a[1] / (2.0 * (a[0] * a[2]).sqrt());
+        let decay = (-zeta * wn).exp();
+        let a1 = if zeta <= 1.0 {
+            -2.0 * decay * (wn * (1.0 - zeta * zeta).sqrt()).cos()
+        } else {
+            -2.0 * decay * (wn * (zeta * zeta - 1.0).sqrt()).cosh()
+        };
+        let a2 = decay * decay;
+
+        let r1 = analog(PI) * (1.0 - a1 + a2);
+        coeff.d[1] = -a1;
+        coeff.d[2] = -a2;
+        if b[0] == 0.0 && b[1] == 0.0 {
+            // keep the double zero at DC of the high pass
+            coeff.c[0] = r1 / 4.0;
+            coeff.c[1] = -r1 / 2.0;
+            coeff.c[2] = r1 / 4.0;
+            return (coeff, 2);
+        }
+
+        // with phi1 = sin^2(omega / 2), phi0 = 1 - phi1 and phi2 = 4 phi0 phi1
+        // the squared magnitude of a numerator is B0 phi0 + B1 phi1 + B2 phi2,
+        // B0 and B1 follow from DC and Nyquist, B2 from a third frequency
+        let r0 = analog(0.0) * (1.0 + a1 + a2);
+        let omega_m = omega0.min(0.9 * PI);
+        let phi1 = (omega_m / 2.0).sin().powi(2);
+        let phi0 = 1.0 - phi1;
+        let phi2 = 4.0 * phi0 * phi1;
+        let z1 = Complex64::from_polar(&1.0, &-omega_m);
+        let den = (z1 * z1 * a2 + z1 * a1 + 1.0).norm_sqr();
+        let b2 = (analog(omega_m).powi(2) * den - r0 * r0 * phi0 - r1 * r1 * phi1) / phi2;
+
+        // beyond what real coefficients can do, e.g. for poles above Nyquist,
+        // only DC and Nyquist are matched
+        let w = (r0 + r1) / 2.0;
+        let b2 = b2.max(-w * w);
+        coeff.c[0] = (w + (w * w + b2).sqrt()) / 2.0;
+        coeff.c[1] = (r0 - r1) / 2.0;
+        coeff.c[2] = if coeff.c[0] != 0.0 { -b2 / (4.0 * coeff.c[0]) } else { 0.0 };
+        (coeff, 2)
+    }
+
     pub fn set_q(&mut self, q: f32) -> () {
         self.q = q as f64;
-        self.computefiltercoefs();
+        self.changed();
     }
 
     pub fn set_type(&mut self, ftype: &FilterType) -> () {
         self.filter_type = *ftype;
-        self.computefiltercoefs();
+        self.changed();
     }
 
+    /* The gain in dB, see `set_values`. */
     pub fn set_gain(&mut self, gain: f32) -> () {
-        self.gain = db_2_rap(gain as f64);
-        self.computefiltercoefs();
+        self.gain = db_2_rap(gain as f64 / 2.0);
+        self.changed();
     }
 
     pub fn set_stages(&mut self, stages: u8) -> () {
         let _stages = if stages >= MAX_FILTER_STAGES { MAX_FILTER_STAGES - 1 } else { stages };
         if self.stages != _stages {
             self.stages = _stages;
-            self.cleanup();
-            self.computefiltercoefs();
+            self.changed();
         }
     }    
 
+    pub fn set_slope(&mut self, slope: Slope) -> () {
+        if self.slope != slope {
+            self.slope = slope;
+            self.changed();
+        }
+    }
+
+    pub fn set_design(&mut self, design: Design) -> () {
+        if self.design != design {
+            self.design = design;
+            self.changed();
+        }
+    }
+
+    /* Bypass the filter, with a crossfade like any other change. */
+    pub fn set_enabled(&mut self, enabled: bool) -> () {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.changed();
+        }
+    }
+
+    /* Whether filtering would not change the signal, so it can be skipped. */
+    pub fn is_bypassed(&self) -> bool {
+        !self.enabled && !self.pending && self.fade_pos == self.fade_len
+    }
+
+    /*
+       Crossfade over `samples` instead of `FADE_TIME`, for a filter that is
+       updated at a fixed pace and should follow every update.
+    */
+    pub fn set_fade_len(&mut self, samples: usize) -> () {
+        let fading = self.fade_pos < self.fade_len;
+        self.fade_len = samples.max(1);
+        if !fading || self.fade_pos > self.fade_len {
+            self.fade_pos = self.fade_len;
+        }
+    }
+
+    /* Silence the filter, e.g. when the audio is interrupted. */
     pub fn cleanup(&mut self) -> () {
 
         self.history = [FStage {x1: 0.0, x2:  0.0, y1: 0.0, y2: 0.0 }; (MAX_FILTER_STAGES + 1) as usize];
         self.old_history = [FStage {x1: 0.0, x2:  0.0, y1: 0.0, y2: 0.0 }; (MAX_FILTER_STAGES + 1) as usize];
-        self.needs_interpolation = false;
+        if self.pending {
+            self.computefiltercoefs();
+            self.pending = false;
+        }
+        self.fade_pos = self.fade_len;
     }
 
     pub fn biquad_filter_a(coeff: &[f64; 5], src: f64, work: &mut [f64; 4]) -> f64 {
@@ -434,35 +1045,51 @@ impl AnalogFilter {
         work[2]
     }
 
-    pub fn singlefilterout(input: &[f32], output: &mut [f32], 
-                        hist: &mut FStage, coeff: &Coeff, order: &u32) -> () {
+    /*
+       Filter `smp` in place with one stage, so stages can be cascaded on
+       the same buffer.  Any length works.  The second order filter is
+       unrolled by 8 for the bulk of the block; `biquad_filter_a` and `_b`
+       swap the roles of the history values, so after an odd number of
+       samples they are stored back the other way round.
+    */
+    pub fn singlefilterout(smp: &mut [f32], hist: &mut FStage, coeff: &Coeff, order: &u32) -> () {
         
-        assert!((input.len() % 8) == 0);
-
         if *order == 1 {
-            for i in 0..input.len() {
-                let ii = input[i] as f64;
+            for s in smp.iter_mut() {
+                let ii = *s as f64;
                 let y0 = ii * coeff.c[0] + hist.x1 * coeff.c[1] + hist.y1 * coeff.d[1];
                 hist.y1 = y0;
                 hist.x1 = ii;
-                output[i] = y0 as f32;
+                *s = y0 as f32;
             }
         } else if *order == 2 {
             let coeff = [coeff.c[0], coeff.c[1], coeff.c[2], coeff.d[1], coeff.d[2]];
             let mut work = [hist.x1, hist.x2, hist.y1, hist.y2];
 
+            let len = smp.len();
+            let bulk = len - len % 8;
             let mut i = 0;
-            while i < input.len() {
-                output[i + 0] = AnalogFilter::biquad_filter_a(&coeff, input[i + 0] as f64, &mut work) as f32;
-                output[i + 1] = AnalogFilter::biquad_filter_b(&coeff, input[i + 1] as f64, &mut work) as f32;
-                output[i + 2] = AnalogFilter::biquad_filter_a(&coeff, input[i + 2] as f64, &mut work) as f32;
-                output[i + 3] = AnalogFilter::biquad_filter_b(&coeff, input[i + 3] as f64, &mut work) as f32;
-                output[i + 4] = AnalogFilter::biquad_filter_a(&coeff, input[i + 4] as f64, &mut work) as f32;
-                output[i + 5] = AnalogFilter::biquad_filter_b(&coeff, input[i + 5] as f64, &mut work) as f32;
-                output[i + 6] = AnalogFilter::biquad_filter_a(&coeff, input[i + 6] as f64, &mut work) as f32;
-                output[i + 7] = AnalogFilter::biquad_filter_b(&coeff, input[i + 7] as f64, &mut work) as f32;
+            while i < bulk {
+                smp[i] = AnalogFilter::biquad_filter_a(&coeff, smp[i] as f64, &mut work) as f32;
+                smp[i + 1] = AnalogFilter::biquad_filter_b(&coeff, smp[i + 1] as f64, &mut work) as f32;
+                smp[i + 2] = AnalogFilter::biquad_filter_a(&coeff, smp[i + 2] as f64, &mut work) as f32;
+                smp[i + 3] = AnalogFilter::biquad_filter_b(&coeff, smp[i + 3] as f64, &mut work) as f32;
+                smp[i + 4] = AnalogFilter::biquad_filter_a(&coeff, smp[i + 4] as f64, &mut work) as f32;
+                smp[i + 5] = AnalogFilter::biquad_filter_b(&coeff, smp[i + 5] as f64, &mut work) as f32;
+                smp[i + 6] = AnalogFilter::biquad_filter_a(&coeff, smp[i + 6] as f64, &mut work) as f32;
+                smp[i + 7] = AnalogFilter::biquad_filter_b(&coeff, smp[i + 7] as f64, &mut work) as f32;
                 i += 8;
             }
+            while i + 1 < len {
+                smp[i] = AnalogFilter::biquad_filter_a(&coeff, smp[i] as f64, &mut work) as f32;
+                smp[i + 1] = AnalogFilter::biquad_filter_b(&coeff, smp[i + 1] as f64, &mut work) as f32;
+                i += 2;
+            }
+            if i < len {
+                smp[i] = AnalogFilter::biquad_filter_a(&coeff, smp[i] as f64, &mut work) as f32;
+                work.swap(0, 1);
+                work.swap(2, 3);
+            }
             hist.x1 = work[0];
             hist.x2 = work[1];
             hist.y1 = work[2];
@@ -472,99 +1099,466 @@ impl AnalogFilter {
 
 
     pub fn filterout(&mut self, input: &[f32], output: &mut [f32]) -> () {
-        for i in 0..(self.stages + 1) {
-            AnalogFilter::singlefilterout(input, output, &mut self.history[i as usize], 
-                &self.coeff, &self.order);
-        }
-
-        if self.needs_interpolation {
-            // 8192 is the biggest buffer size used for jack. We should be on 
-            // the safe side here (cross the fingers), but now we don't have 
-            // to allocate in the realtime thread (which is a big no-no)
-            const BUF_SIZE: usize = 8192;
-            let mut ismp_buf = [0.0; BUF_SIZE];
-            let ismp = &mut ismp_buf[0..output.len()];
-
-            for i in 0..(self.stages + 1) {
-                AnalogFilter::singlefilterout(input, &mut ismp[..], &mut self.old_history[i as usize],
-                    &self.old_coeff, &self.order);
-            }
+        output.copy_from_slice(input);
+        self.filter(output);
+    }
 
-            let len = ismp.len();
-            let len_f = len as f64;
-            for i in 0..len {
-                let x = i as f64 / len_f;
-                let isi = ismp[i] as f64;
-                let ii = input[i] as f64;
-                let val = isi * (1.0 - x) + ii * x;
-                output[i] = val as f32;
-                
+    /*
+       Filter a block in place.  Blocks longer than the `max_block` given to
+       `new` are split.
+    */
+    pub fn filter(&mut self, output: &mut [f32]) -> () {
+        let max_block = self.scratch.len();
+        if output.len() > max_block {
+            for out in output.chunks_mut(max_block) {
+                self.filter(out);
             }
+            return;
+        }
 
-            self.needs_interpolation = false;
+        // changes waiting for the end of the crossfade start right there,
+        // whatever the block size
+        let rest = self.fade_len - self.fade_pos;
+        if self.pending && rest > 0 && output.len() > rest {
+            let (head, tail) = output.split_at_mut(rest);
+            self.filter(head);
+            self.filter(tail);
+            return;
         }
-    }
 
-    pub fn h(&mut self, freq: f64) -> f64 {
-        let fr = freq / self.samplerate_f * PI * 2.0;
-        let mut x = self.coeff.c[0];
-        let mut y = 0.0;
+        let fading = self.fade_pos < self.fade_len;
+        if fading {
+            self.scratch[0..output.len()].copy_from_slice(output);
+        }
 
-        for n in 1..3 {
-            let t = n as f64 * fr;
-            x += t.cos() * self.coeff.c[n];
-            y -= t.sin() * self.coeff.c[n];
+        // the sections are in series, each one filters the output of the last
+        let cascade = &self.cascade;
+        for (i, history) in self.history[..cascade.len].iter_mut().enumerate() {
+            AnalogFilter::singlefilterout(output, history, &cascade.coeff[i], &cascade.order[i]);
         }
 
-        let mut h = x * x + y * y;
-        x = 1.0;
-        y = 0.0;
+        if fading {
+            let ismp = &mut self.scratch[0..output.len()];
+
+            let old = &self.old_cascade;
+            for (i, history) in self.old_history[..old.len].iter_mut().enumerate() {
+                AnalogFilter::singlefilterout(ismp, history, &old.coeff[i], &old.order[i]);
+            }
+
+            let len_f = self.fade_len as f64;
+            for (i, out) in output.iter_mut().enumerate() {
+                let pos = self.fade_pos + i;
+                if pos >= self.fade_len {
+                    break;
+                }
+                let x = pos as f64 / len_f;
+                let val = ismp[i] as f64 * (1.0 - x) + *out as f64 * x;
+                *out = val as f32;
+            }
 
-        for n in 1..3 {
-            let t = n as f64 * fr;
-            x -= t.cos() * self.coeff.d[n];
-            y += t.sin() * self.coeff.d[n];
+            self.fade_pos = (self.fade_pos + output.len()).min(self.fade_len);
+            if self.fade_pos == self.fade_len && self.pending {
+                self.pending = false;
+                self.changed();
+            }
         }
-        h = h / (x * x + y * y);
-        h.powf((self.stages as f64 + 1.0 ) / 2.0)
     }
 
+    /* The magnitude of the response at `freq`, with all stages. */
+    pub fn h(&mut self, freq: f64) -> f64 {
+        self.response(freq).norm()
+    }
 
-    pub fn set_values(&mut self, ftype: f32, freq: f32, q: f32, stages: f32, gain: f64) -> () {
+    pub fn samplerate(&self) -> f64 {
+        self.samplerate_f
+    }
+
+
+    /*
+       Set the filter from control values, `gain` in dB.  The filters take
+       the square root of its amplitude (A in the RBJ cookbook), so peaks
+       and shelves reach `gain` and the tilt spans it.
+    */
+    pub fn set_values(&mut self, ftype: f32, freq: f32, q: f32, stages: f32, gain: f32) -> () {
         
         let typ = FilterType::from_u32(ftype as u32);
 
         self.q = q as f64;
         self.filter_type = typ;
-        self.gain = db_2_rap(gain as f64);
+        self.gain = db_2_rap(gain as f64 / 2.0);
+        self.stages = if stages as u8 >= MAX_FILTER_STAGES { MAX_FILTER_STAGES - 1 } else { stages as u8 };
+        self.freq = if freq < 0.1 { 0.1_f64 } else { freq as f64 };
 
-        let _stages = if stages as u8 >= MAX_FILTER_STAGES { MAX_FILTER_STAGES - 1 } else { stages as u8 };
-        if self.stages != _stages {
-            self.stages = _stages;
-            self.cleanup();
+        self.changed();
+    }
+
+}
+
+
+/* The response of the current coefficients, with all stages. */
+impl FrequencyResponse for AnalogFilter {
+    fn response(&self, freq: f64) -> Complex64 {
+        self.sections().response(freq)
+    }
+
+    fn group_delay(&self, freq: f64) -> f64 {
+        self.sections().group_delay(freq)
+    }
+}
+
+impl AnalogFilter {
+    fn sections(&self) -> Sections {
+        Sections { cascade: self.cascade, samplerate: self.samplerate_f, gain: 1.0 }
+    }
+
+    /*
+       The filter as it is once a running crossfade and the changes pending
+       after it are done, e.g. to draw the curve the user just set.
+    */
+    pub fn target(&self) -> Sections {
+        Sections { cascade: self.design_cascade(), samplerate: self.samplerate_f, gain: 1.0 }
+    }
+}
+
+
+/* The sections of a filter in series, followed by `gain`. */
+#[derive(Clone, Copy, Debug)]
+pub struct Sections {
+    cascade: Cascade,
+    samplerate: f64,
+    gain: f64
+}
+
+impl FrequencyResponse for Sections {
+    fn response(&self, freq: f64) -> Complex64 {
+        self.cascade.response(2.0 * PI * freq / self.samplerate) * self.gain
+    }
+
+    fn group_delay(&self, freq: f64) -> f64 {
+        self.cascade.group_delay(2.0 * PI * freq / self.samplerate) / self.samplerate
+    }
+}
+
+
+/*
+   The state variable filter of ZynAddSubFX (SVFilter), which has a low,
+   high, band pass and notch output of the same two integrators.  It
+   resonates differently from the analog filter, and its low and high pass
+   lose some level with rising Q (the input is scaled by sqrt(q)).  The
+   integrators are tuned with f = 4 freq / rate as in ZynAddSubFX, instead
+   of 2 sin(pi freq / rate), which puts the center at about 2 / pi of the
+   set frequency.
+
+   The parameters and crossfades work like the ones of `AnalogFilter`.  The
+   types without a state variable counterpart (peak, shelves, tilt and the
+   allpasses) pass the signal unchanged, only the output gain is applied.
+   Like in ZynAddSubFX the gain is not part of the filter but an output
+   gain, half of it in dB above 0 dB.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SVType {
+    Low,
+    High,
+    Band,
+    Notch
+}
+
+impl SVType {
+    pub fn from_filter_type(ftype: FilterType) -> Option<SVType> {
+        match ftype {
+            FilterType::LPF1 | FilterType::LPF2 => Some(SVType::Low),
+            FilterType::HPF1 | FilterType::HPF2 => Some(SVType::High),
+            FilterType::BPF2 | FilterType::BPFPeak | FilterType::BPFGain => Some(SVType::Band),
+            FilterType::NOTCH2 => Some(SVType::Notch),
+            FilterType::PEAK2 | FilterType::LoShelf | FilterType::HiShelf | FilterType::APF1
+                | FilterType::APF2 | FilterType::Tilt => None
         }
+    }
+}
 
-        let frequency = if freq < 0.1 { 0.1_f64 } else { freq as f64 };
+#[derive(Clone, Copy, Debug)]
+pub struct SVStage {
+    low: f64,
+    high: f64,
+    band: f64,
+    notch: f64
+}
 
-        let mut rap = self.freq / frequency;
-        if rap < 1.0 { rap = 1.0 / rap }
+const SV_SILENCE: SVStage = SVStage { low: 0.0, high: 0.0, band: 0.0, notch: 0.0 };
 
-        self.oldabove_nq = self.above_nq;
-        self.above_nq = frequency > (self.halfsamplerate_f - 500.0);
+#[derive(Clone, Copy, Debug)]
+pub struct SVParams {
+    f: f64,
+    q: f64,
+    q_sqrt: f64,
+    // none passes the signal unchanged
+    output: Option<SVType>,
+    outgain: f64
+}
 
-        let nyquistthresh = self.above_nq ^ self.oldabove_nq;
+impl SVParams {
+    /*
+       With u = z^-1 every stage is the biquad
+
+           low:   f^2 sqrt(q) u / D
+           high:  sqrt(q) (1 - u)^2 / D
+           band:  f sqrt(q) (1 - u) / D
+           notch: sqrt(q) (1 + (f^2 - 2) u + u^2) / D
+
+       where D = 1 + (f^2 + q f - 2) u + (1 - q f) u^2.
+    */
+    fn coeff(&self) -> Coeff {
+        let (f, q, qs) = (self.f, self.q, self.q_sqrt);
+        let c = match self.output {
+            Some(SVType::Low) => [0.0, f * f * qs, 0.0],
+            Some(SVType::High) => [qs, -2.0 * qs, qs],
+            Some(SVType::Band) => [f * qs, -f * qs, 0.0],
+            Some(SVType::Notch) => [qs, (f * f - 2.0) * qs, qs],
+            None => return Coeff { c: [1.0, 0.0, 0.0], d: [0.0; 3] }
+        };
+        Coeff { c: c, d: [0.0, 2.0 - f * f - q * f, q * f - 1.0] }
+    }
+}
 
-        if rap > 3.0 || nyquistthresh {
-            self.old_coeff = self.coeff;
-            
+#[derive(Debug)]
+pub struct SVFilter {
+    params: SVParams,
+    old_params: SVParams,
+
+    history: [SVStage; (MAX_FILTER_STAGES + 1) as usize],
+    old_history: [SVStage; (MAX_FILTER_STAGES + 1) as usize],
+
+    filter_type: FilterType,
+    stages: u8,
+    freq: f64,
+    q: f64,
+    gain: f64,
+    enabled: bool,
+
+    active_stages: u8,
+    old_stages: u8,
+
+    first_time: bool,
+    fade_pos: usize,
+    fade_len: usize,
+    pending: bool,
+
+    samplerate_f: f64,
+    scratch: Vec<f32>
+}
+
+impl SVFilter {
+    pub fn new(ftype: &FilterType,
+        ffreq: f32,
+        fq: f32,
+        fstages: u8,
+        srate: u32,
+        max_block: usize) -> SVFilter {
+
+        let st = if fstages >= MAX_FILTER_STAGES { MAX_FILTER_STAGES - 1 } else { fstages };
+        let fade_len = ((srate as f64 * FADE_TIME) as usize).max(1);
+        let unity = SVParams { f: 0.0, q: 0.0, q_sqrt: 0.0, output: None, outgain: 1.0 };
+
+        let mut f = SVFilter {
+            params: unity,
+            old_params: unity,
+            history: [SV_SILENCE; (MAX_FILTER_STAGES + 1) as usize],
+            old_history: [SV_SILENCE; (MAX_FILTER_STAGES + 1) as usize],
+            filter_type: *ftype,
+            stages: st,
+            freq: if ffreq < 0.1 { 0.1 } else { ffreq as f64 },
+            q: fq as f64,
+            gain: 1.0,
+            enabled: true,
+            active_stages: st,
+            old_stages: st,
+            first_time: true,
+            fade_pos: fade_len,
+            fade_len: fade_len,
+            pending: false,
+            samplerate_f: srate as f64,
+            scratch: vec![0.0; max_block.max(1)]
+        };
+        f.changed();
+        f
+    }
+
+    pub fn compute_params(ftype: FilterType, freq: f64, q: f64, stages: u8, gain: f64, fs: f64,
+        enabled: bool) -> SVParams {
+
+        if !enabled {
+            return SVParams { f: 0.0, q: 0.0, q_sqrt: 0.0, output: None, outgain: 1.0 };
+        }
+        let f = (freq / fs * 4.0).min(0.99999);
+        let q = (1.0 - q.sqrt().atan() * 2.0 / PI).powf(1.0 / (stages as f64 + 1.0));
+        SVParams {
+            f: f,
+            q: q,
+            q_sqrt: q.sqrt(),
+            output: SVType::from_filter_type(ftype),
+            outgain: if gain > 1.0 { gain.sqrt() } else { gain }
+        }
+    }
+
+    /* Switch to the parameters at once. */
+    fn computefiltercoefs(&mut self) -> () {
+        self.params = SVFilter::compute_params(self.filter_type, self.freq, self.q, self.stages,
+            self.gain, self.samplerate_f, self.enabled);
+
+        for stage in (self.active_stages as usize + 1)..(self.stages as usize + 1) {
+            self.history[stage] = SV_SILENCE;
+        }
+        self.active_stages = self.stages;
+    }
+
+    fn changed(&mut self) -> () {
+        if self.first_time {
+            self.computefiltercoefs();
+            self.first_time = false;
+        } else if self.fade_pos < self.fade_len {
+            self.pending = true;
+        } else {
+            self.old_params = self.params;
+            self.old_stages = self.active_stages;
             self.old_history = self.history;
-            if !self.first_time { self.needs_interpolation = true };
+            self.computefiltercoefs();
+            self.fade_pos = 0;
         }
+    }
 
-        self.freq = frequency;
-        self.first_time = false;
-        
-        self.computefiltercoefs();
+    /* The same as `AnalogFilter::set_values`, `gain` in dB is the output gain. */
+    pub fn set_values(&mut self, ftype: f32, freq: f32, q: f32, stages: f32, gain: f32) -> () {
+        self.filter_type = FilterType::from_u32(ftype as u32);
+        self.freq = if freq < 0.1 { 0.1_f64 } else { freq as f64 };
+        self.q = q as f64;
+        self.stages = if stages as u8 >= MAX_FILTER_STAGES { MAX_FILTER_STAGES - 1 } else { stages as u8 };
+        self.gain = db_2_rap(gain as f64);
+        self.changed();
     }
 
-}
\ No newline at end of file
+    pub fn set_enabled(&mut self, enabled: bool) -> () {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.changed();
+        }
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        !self.enabled && !self.pending && self.fade_pos == self.fade_len
+    }
+
+    pub fn cleanup(&mut self) -> () {
+        self.history = [SV_SILENCE; (MAX_FILTER_STAGES + 1) as usize];
+        self.old_history = [SV_SILENCE; (MAX_FILTER_STAGES + 1) as usize];
+        if self.pending {
+            self.computefiltercoefs();
+            self.pending = false;
+        }
+        self.fade_pos = self.fade_len;
+    }
+
+    pub fn singlefilterout(smp: &mut [f32], x: &mut SVStage, par: &SVParams) -> () {
+        let output = match par.output {
+            Some(output) => output,
+            None => return
+        };
+        for s in smp.iter_mut() {
+            x.low += par.f * x.band;
+            x.high = par.q_sqrt * *s as f64 - x.low - par.q * x.band;
+            x.band += par.f * x.high;
+            x.notch = x.high + x.low;
+            *s = match output {
+                SVType::Low => x.low,
+                SVType::High => x.high,
+                SVType::Band => x.band,
+                SVType::Notch => x.notch
+            } as f32;
+        }
+    }
+
+    pub fn filterout(&mut self, input: &[f32], output: &mut [f32]) -> () {
+        output.copy_from_slice(input);
+        self.filter(output);
+    }
+
+    /* Filter a block in place, like `AnalogFilter::filter`. */
+    pub fn filter(&mut self, output: &mut [f32]) -> () {
+        let max_block = self.scratch.len();
+        if output.len() > max_block {
+            for out in output.chunks_mut(max_block) {
+                self.filter(out);
+            }
+            return;
+        }
+
+        // changes waiting for the end of the crossfade start right there,
+        // whatever the block size
+        let rest = self.fade_len - self.fade_pos;
+        if self.pending && rest > 0 && output.len() > rest {
+            let (head, tail) = output.split_at_mut(rest);
+            self.filter(head);
+            self.filter(tail);
+            return;
+        }
+
+        let fading = self.fade_pos < self.fade_len;
+        if fading {
+            self.scratch[0..output.len()].copy_from_slice(output);
+        }
+
+        for i in 0..(self.active_stages + 1) {
+            SVFilter::singlefilterout(output, &mut self.history[i as usize], &self.params);
+        }
+
+        if fading {
+            let ismp = &mut self.scratch[0..output.len()];
+
+            for i in 0..(self.old_stages + 1) {
+                SVFilter::singlefilterout(ismp, &mut self.old_history[i as usize], &self.old_params);
+            }
+
+            let len_f = self.fade_len as f64;
+            for (i, out) in output.iter_mut().enumerate() {
+                let pos = self.fade_pos + i;
+                let x = if pos >= self.fade_len { 1.0 } else { pos as f64 / len_f };
+                let val = ismp[i] as f64 * self.old_params.outgain * (1.0 - x)
+                    + *out as f64 * self.params.outgain * x;
+                *out = val as f32;
+            }
+
+            self.fade_pos = (self.fade_pos + output.len()).min(self.fade_len);
+            if self.fade_pos == self.fade_len && self.pending {
+                self.pending = false;
+                self.changed();
+            }
+        } else if self.params.outgain != 1.0 {
+            let gain = self.params.outgain;
+            for out in output.iter_mut() {
+                *out = (*out as f64 * gain) as f32;
+            }
+        }
+    }
+
+    fn sections(&self, params: &SVParams, stages: u8) -> Sections {
+        Sections { cascade: Cascade::repeat(params.coeff(), 2, stages as usize + 1),
+            samplerate: self.samplerate_f, gain: params.outgain }
+    }
+
+    /* The filter once the crossfade and pending changes are done. */
+    pub fn target(&self) -> Sections {
+        let params = SVFilter::compute_params(self.filter_type, self.freq, self.q, self.stages,
+            self.gain, self.samplerate_f, self.enabled);
+        self.sections(&params, self.stages)
+    }
+}
+
+/* The response of the current parameters, with all stages. */
+impl FrequencyResponse for SVFilter {
+    fn response(&self, freq: f64) -> Complex64 {
+        self.sections(&self.params, self.active_stages).response(freq)
+    }
+
+    fn group_delay(&self, freq: f64) -> f64 {
+        self.sections(&self.params, self.active_stages).group_delay(freq)
+    }
+}
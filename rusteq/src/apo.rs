@@ -0,0 +1,357 @@
+/*
+   Filter lists in the text format of Equalizer APO, which REW exports its
+   room corrections in:
+
+       Preamp: -6.5 dB
+       Filter 1: ON PK Fc 63.5 Hz Gain -7.0 dB Q 4.00
+       Filter 2: ON LSC Fc 120 Hz Gain 3.0 dB Q 0.71
+       Filter 3: OFF None
+
+   The filters map to the band settings of the EQs: the filter type, the
+   frequency, the gain and the Q as the controls of a band take them.  The
+   Q of the ZynAddSubFX filters is not the Q of the RBJ cookbook filters
+   Equalizer APO uses for every type, see `analog_eq::control_q`.
+
+   Only `Preamp` and `Filter` lines are read, the rest of a REW file (the
+   header, notes, `Channel` or `Device` lines of Equalizer APO) is skipped.
+   The filter types are
+
+       PK               peak, Fc, Gain and Q or BW Oct
+       LP, HP           second order low and high pass, Q 0.707
+       LPQ, HPQ         the same with Q
+       LS, HS           shelves with a slope of 12 dB per octave, Fc and Gain
+       LSC, HSC         shelves with Q, or with the slope as "LSC 9 dB"
+       NO, BP, AP       notch, band pass (0 dB peak) and all pass, Fc and Q
+
+   First order shelves ("LS 6dB") have no counterpart here and are errors.
+*/
+
+use std::fmt;
+
+use analog_eq::{FilterType, control_q, rbj_q};
+
+
+// Q where none is given, a Butterworth response
+pub const DEFAULT_Q: f32 = ::std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub enabled: bool,
+    pub ftype: FilterType,
+    // Hz
+    pub freq: f32,
+    // dB at the peak or the shelf, as the gain control of the parametric EQ
+    pub gain: f32,
+    // as the Q control of the EQs
+    pub q: f32
+}
+
+/* The filters of a file in series, after the preamp. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chain {
+    // dB
+    pub preamp: f32,
+    pub bands: Vec<Band>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // 1 based
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+
+/* The Q of a peak `octaves` wide. */
+fn bandwidth_q(octaves: f32) -> f32 {
+    let x = 2f32.powf(octaves);
+    x.sqrt() / (x - 1.0)
+}
+
+/* The Q of a shelf with `slope` dB per octave, S = slope / 12 in the RBJ cookbook. */
+fn slope_q(slope: f32, gain: f32) -> f32 {
+    let a = 10f32.powf(gain / 40.0);
+    let s = slope / 12.0;
+    1.0 / ((a + 1.0 / a) * (1.0 / s - 1.0) + 2.0).max(0.0).sqrt()
+}
+
+
+pub fn parse(text: &str) -> Result<Chain, ParseError> {
+    let mut chain = Chain::default();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| ParseError { line: i + 1, message: message };
+        let line = line.trim();
+        if let Some(preamp) = line.strip_prefix("Preamp:") {
+            let mut words = preamp.split_whitespace();
+            chain.preamp += value(&mut words, "Preamp").map_err(error)?;
+            if let Some(word) = words.next().filter(|w| !w.eq_ignore_ascii_case("dB")) {
+                return Err(error(format!("unexpected {}", word)));
+            }
+        } else if let Some(filter) = line.strip_prefix("Filter") {
+            // "Filter:" or "Filter 12:", not the "Filter Settings file" header of REW
+            let colon = match filter.find(':') {
+                Some(colon) if filter[..colon].trim().chars().all(|c| c.is_ascii_digit()) => colon,
+                _ => continue
+            };
+            if let Some(band) = parse_filter(&filter[colon + 1..]).map_err(error)? {
+                chain.bands.push(band);
+            }
+        }
+    }
+    Ok(chain)
+}
+
+/* The part of a filter line after the colon, `None` for an unused filter. */
+fn parse_filter(line: &str) -> Result<Option<Band>, String> {
+    let mut words = line.split_whitespace().peekable();
+    let enabled = match words.next() {
+        Some("ON") => true,
+        Some("OFF") => false,
+        Some(word) => return Err(format!("expected ON or OFF, not {}", word)),
+        None => return Err("no filter".to_string())
+    };
+    let name = words.next().ok_or_else(|| "no filter type".to_string())?;
+    if name == "None" {
+        return Ok(None);
+    }
+    let ftype = match name {
+        "PK" => FilterType::PEAK2,
+        "LP" | "LPQ" => FilterType::LPF2,
+        "HP" | "HPQ" => FilterType::HPF2,
+        "LS" | "LSC" => FilterType::LoShelf,
+        "HS" | "HSC" => FilterType::HiShelf,
+        "NO" => FilterType::NOTCH2,
+        "BP" => FilterType::BPFPeak,
+        "AP" => FilterType::APF2,
+        _ => return Err(format!("unsupported filter type {}", name))
+    };
+
+    // the slope of a shelf, "12dB" or "12 dB"
+    let mut slope = None;
+    if let Some(word) = words.peek().cloned() {
+        if let Ok(number) = word.trim_end_matches("dB").parse::<f32>() {
+            words.next();
+            if !word.ends_with("dB") && words.next() != Some("dB") {
+                return Err(format!("expected dB after the slope {}", number));
+            }
+            slope = Some(number);
+        }
+    }
+
+    let (mut freq, mut gain, mut q) = (None, None, None);
+    while let Some(word) = words.next() {
+        match word {
+            "Fc" => {
+                freq = Some(value(&mut words, "Fc")?);
+                unit(&mut words, "Hz");
+            },
+            "Gain" => {
+                gain = Some(value(&mut words, "Gain")?);
+                unit(&mut words, "dB");
+            },
+            "Q" => q = Some(value(&mut words, "Q")?),
+            "BW" => {
+                if words.next() != Some("Oct") {
+                    return Err("only BW Oct is supported".to_string());
+                }
+                q = Some(bandwidth_q(value(&mut words, "BW Oct")?));
+            },
+            _ => return Err(format!("unexpected {}", word))
+        }
+    }
+
+    let freq = freq.ok_or_else(|| format!("{} without Fc", name))?;
+    let gain = match (ftype, gain) {
+        (FilterType::PEAK2, None) | (FilterType::LoShelf, None) | (FilterType::HiShelf, None) =>
+            return Err(format!("{} without Gain", name)),
+        (_, gain) => gain.unwrap_or(0.0)
+    };
+    let q = match (name, slope, q) {
+        (_, Some(slope), _) if slope <= 6.0 => return Err(format!("{} with a first order slope", name)),
+        ("LS", Some(slope), None) | ("HS", Some(slope), None) if slope == 12.0 => DEFAULT_Q,
+        ("LSC", Some(slope), None) | ("HSC", Some(slope), None) => slope_q(slope, gain),
+        (_, Some(_), _) => return Err(format!("{} with an unsupported slope", name)),
+        ("PK", None, None) => return Err(format!("{} without Q", name)),
+        (_, None, q) => q.unwrap_or(DEFAULT_Q)
+    };
+    if !(freq > 0.0 && q > 0.0) {
+        return Err(format!("{} needs a positive Fc and Q", name));
+    }
+
+    Ok(Some(Band {
+        enabled: enabled,
+        ftype: ftype,
+        freq: freq,
+        gain: gain,
+        q: control_q(ftype, q)
+    }))
+}
+
+fn value<'a, I: Iterator<Item = &'a str>>(words: &mut I, key: &str) -> Result<f32, String> {
+    let word = words.next().ok_or_else(|| format!("no value for {}", key))?;
+    word.parse().map_err(|_| format!("{}: not a number: {}", key, word))
+}
+
+/* Skip the optional unit after a value. */
+fn unit<'a, I: Iterator<Item = &'a str>>(words: &mut ::std::iter::Peekable<I>, unit: &str) {
+    if words.peek().is_some_and(|w| w.eq_ignore_ascii_case(unit)) {
+        words.next();
+    }
+}
+
+
+/*
+   The chain as Equalizer APO lines, which `parse` reads back.  Bands of
+   types Equalizer APO does not have are left out.
+*/
+pub fn write(chain: &Chain) -> String {
+    let mut text = String::new();
+    if chain.preamp != 0.0 {
+        text.push_str(&format!("Preamp: {} dB\n", number(chain.preamp)));
+    }
+    let mut n = 0;
+    for band in &chain.bands {
+        let name = match type_name(band.ftype) {
+            Some(name) => name,
+            None => continue
+        };
+        n += 1;
+        let mut line = format!("Filter {}: {} {} Fc {} Hz", n, if band.enabled { "ON" } else { "OFF" }, name,
+            number(band.freq));
+        match band.ftype {
+            FilterType::PEAK2 | FilterType::LoShelf | FilterType::HiShelf =>
+                line.push_str(&format!(" Gain {} dB", number(band.gain))),
+            _ => ()
+        }
+        line.push_str(&format!(" Q {}\n", number(rbj_q(band.ftype, band.q))));
+        text.push_str(&line);
+    }
+    text
+}
+
+/* The name Equalizer APO has for `ftype`, if it has the type. */
+pub fn type_name(ftype: FilterType) -> Option<&'static str> {
+    match ftype {
+        FilterType::PEAK2 => Some("PK"),
+        FilterType::LPF2 => Some("LPQ"),
+        FilterType::HPF2 => Some("HPQ"),
+        FilterType::LoShelf => Some("LSC"),
+        FilterType::HiShelf => Some("HSC"),
+        FilterType::NOTCH2 => Some("NO"),
+        FilterType::BPFPeak => Some("BP"),
+        FilterType::APF2 => Some("AP"),
+        _ => None
+    }
+}
+
+/* Up to four decimals, without trailing zeros. */
+fn number(x: f32) -> String {
+    let s = format!("{:.4}", x);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analog_eq::{AnalogFilter, DEFAULT_MAX_BLOCK};
+    use num::complex::Complex64;
+    use response::FrequencyResponse;
+    use tests::RATE;
+
+    const REW_FILTERS: &str = "Filter Settings file
+
+Room EQ V5.20
+Dated: 01.03.2024 10:00:00
+
+Notes:
+
+Equaliser: Generic
+Average 1
+Preamp: -4.5 dB
+Filter  1: ON  PK       Fc   63.5 Hz  Gain  -7.0 dB  Q  4.00
+Filter  2: ON  LSC 9 dB Fc  120 Hz  Gain   3.0 dB
+Filter  3: OFF PK       Fc   1000 Hz  Gain   2.0 dB  BW Oct 1.0
+Filter  4: ON  None
+Filter: ON HP Fc 25 Hz
+Filter 5: ON NO Fc 50 Hz Q 10
+";
+
+    #[test]
+    fn apo_filters() {
+        let chain = parse(REW_FILTERS).unwrap();
+        assert_eq!(chain.preamp, -4.5);
+        assert_eq!(chain.bands.len(), 5);
+
+        let peak = chain.bands[0];
+        assert!(peak.enabled && peak.ftype == FilterType::PEAK2);
+        assert_eq!((peak.freq, peak.gain), (63.5, -7.0));
+        assert!((rbj_q(peak.ftype, peak.q) - 4.0).abs() < 1e-6);
+        assert_eq!(chain.bands[1].ftype, FilterType::LoShelf);
+        assert!(!chain.bands[2].enabled);
+        assert!((rbj_q(FilterType::PEAK2, chain.bands[2].q) - ::std::f32::consts::SQRT_2).abs() < 1e-3);
+        assert_eq!((chain.bands[3].ftype, chain.bands[3].q), (FilterType::HPF2, DEFAULT_Q));
+        assert_eq!(chain.bands[4].ftype, FilterType::NOTCH2);
+
+        // written and read back it is the same chain
+        let again = parse(&write(&chain)).unwrap();
+        assert_eq!(again.preamp, chain.preamp);
+        for (a, b) in again.bands.iter().zip(&chain.bands) {
+            assert!(a.enabled == b.enabled && a.ftype == b.ftype && a.freq == b.freq && a.gain == b.gain);
+            assert!((a.q / b.q - 1.0).abs() < 1e-3, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn apo_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error("Preamp: -1 dB\nFilter 1: ON PK Fc 100 Hz Q 1").line, 2);
+        assert_eq!(error("Filter 1: ON LS 6dB Fc 100 Hz Gain 3 dB").line, 1);
+        assert_eq!(error("\nFilter 1: ON XX Fc 100 Hz").line, 2);
+        assert_eq!(error("Filter 1: ON PK Fc hundred Hz Gain 3 dB Q 1").message, "Fc: not a number: hundred");
+        assert_eq!(error("Filter 1: MAYBE PK").message, "expected ON or OFF, not MAYBE");
+    }
+
+    /* The magnitude in dB of an RBJ cookbook filter. */
+    fn rbj_db(ftype: FilterType, f0: f64, gain: f64, q: f64, freq: f64) -> f64 {
+        let a = 10f64.powf(gain / 40.0);
+        let w0 = 2.0 * ::std::f64::consts::PI * f0 / RATE;
+        let (cs, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        let (b, d) = match ftype {
+            FilterType::PEAK2 => ([1.0 + alpha * a, -2.0 * cs, 1.0 - alpha * a], [1.0 + alpha / a, -2.0 * cs, 1.0 - alpha / a]),
+            FilterType::NOTCH2 => ([1.0, -2.0 * cs, 1.0], [1.0 + alpha, -2.0 * cs, 1.0 - alpha]),
+            FilterType::LoShelf => {
+                let beta = 2.0 * a.sqrt() * alpha;
+                ([a * ((a + 1.0) - (a - 1.0) * cs + beta), 2.0 * a * ((a - 1.0) - (a + 1.0) * cs),
+                    a * ((a + 1.0) - (a - 1.0) * cs - beta)],
+                 [(a + 1.0) + (a - 1.0) * cs + beta, -2.0 * ((a - 1.0) + (a + 1.0) * cs),
+                    (a + 1.0) + (a - 1.0) * cs - beta])
+            },
+            _ => panic!("no RBJ reference for {:?}", ftype)
+        };
+        let z = Complex64::from_polar(&1.0, &(-2.0 * ::std::f64::consts::PI * freq / RATE));
+        let h = (b[0] + z * b[1] + z * z * b[2]) / (d[0] + z * d[1] + z * z * d[2]);
+        20.0 * h.norm().log10()
+    }
+
+    #[test]
+    fn apo_q_is_rbj_q() {
+        for &(ftype, gain, q) in &[(FilterType::PEAK2, -7.0, 4.0), (FilterType::PEAK2, 5.0, 0.7),
+            (FilterType::LoShelf, 6.0, 0.9), (FilterType::NOTCH2, 0.0, 3.0)] {
+
+            let mut f = AnalogFilter::new(&ftype, 1000.0, 1.0, 0, RATE as u32, DEFAULT_MAX_BLOCK);
+            f.set_values(ftype.to_u32() as f32, 1000.0, control_q(ftype, q), 0.0, gain);
+            f.cleanup();
+            for &freq in &[100.0, 700.0, 900.0, 1100.0, 1500.0, 8000.0] {
+                let expected = rbj_db(ftype, 1000.0, gain as f64, q as f64, freq);
+                assert!((f.magnitude_db(freq) - expected).abs() < 0.01, "{:?} Q {} at {} Hz", ftype, q, freq);
+            }
+        }
+    }
+}
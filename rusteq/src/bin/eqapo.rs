@@ -0,0 +1,143 @@
+/*
+   Converts between filter lists of Equalizer APO or REW and the settings
+   of the EQs, see the apo module:
+
+       eqapo show room.txt
+       eqapo preset room.txt ~/.lv2/room.lv2 [--name "Living Room"]
+       eqapo export [--preamp DB] PEAK:63.5:1.33:-7 LoShelf:120:0.81:3 ...
+
+   `show` lists the filters as the controls of an EQ band take them (the
+   Q differs from the one in the file for peaks, shelves and notches).
+   `preset` writes an LV2 preset bundle for the parametric EQ which loads
+   the filters as its chain, after the eight bands; the ports are left as
+   they are.  `export` writes the Equalizer APO lines for bands given by
+   the values of their type, frequency, Q and gain controls.
+*/
+
+extern crate rusteq;
+extern crate rustlv2;
+
+use rusteq::analog_eq::*;
+use rusteq::apo;
+use rusteq::paraeq::FILTERS_KEY;
+use rusteq::ParaEQ;
+use rustlv2::ttl::plugin_uri;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+
+const USAGE: &'static str = "usage: eqapo show <filters.txt>\n       \
+    eqapo preset <filters.txt> <bundle.lv2> [--name NAME]\n       \
+    eqapo export [--preamp DB] TYPE:FREQ:Q:GAIN ...";
+
+
+fn read(path: &str) -> Result<apo::Chain, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    apo::parse(&text).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn show(path: &str) -> Result<(), String> {
+    let chain = read(path)?;
+    println!("Preamp: {} dB", chain.preamp);
+    for (i, band) in chain.bands.iter().enumerate() {
+        let name = FILTER_TYPES.iter()
+            .find(|point| point.1 == band.ftype.to_u32() as f32)
+            .map_or("?", |point| point.0);
+        println!("Band {}: {} {} Frequency {} Hz Q {} Gain {} dB", i + 1, if band.enabled { "ON " } else { "OFF" },
+            name, band.freq, band.q, band.gain);
+    }
+    Ok(())
+}
+
+/* A Turtle string literal. */
+fn literal(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn preset(path: &str, bundle: &str, name: Option<String>) -> Result<(), String> {
+    let chain = read(path)?;
+    let name = name.unwrap_or_else(|| {
+        Path::new(path).file_stem().map_or("Imported".to_string(), |stem| stem.to_string_lossy().into_owned())
+    });
+    let file: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect::<String>() + ".ttl";
+    let plugin = plugin_uri::<ParaEQ>();
+    let key = String::from_utf8_lossy(&FILTERS_KEY[..FILTERS_KEY.len() - 1]).into_owned();
+    let prefixes = "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+        @prefix pset: <http://lv2plug.in/ns/ext/presets#> .\n\
+        @prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .\n";
+
+    let manifest = format!("{}\n<{}>\n    a pset:Preset ;\n    lv2:appliesTo <{}> ;\n    rdfs:seeAlso <{}> .\n",
+        prefixes, file, plugin, file);
+    let preset = format!("{}@prefix state: <http://lv2plug.in/ns/ext/state#> .\n\n\
+        <>\n    a pset:Preset ;\n    lv2:appliesTo <{}> ;\n    rdfs:label {} ;\n    \
+        state:state [\n        <{}> {}\n    ] .\n",
+        prefixes, plugin, literal(&name), key, literal(&apo::write(&chain)));
+
+    let dir = Path::new(bundle);
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(dir.join("manifest.ttl"), manifest))
+        .and_then(|_| fs::write(dir.join(&file), preset))
+        .map_err(|err| format!("{}: {}", bundle, err))
+}
+
+/* A band from the values of its controls, TYPE:FREQ:Q:GAIN. */
+fn band(arg: &str) -> Result<apo::Band, String> {
+    let parts: Vec<&str> = arg.split(':').collect();
+    if parts.len() != 4 {
+        return Err(format!("{}: expected TYPE:FREQ:Q:GAIN", arg));
+    }
+    let ftype = FILTER_TYPES.iter()
+        .find(|point| point.0.eq_ignore_ascii_case(parts[0]))
+        .map(|point| point.1 as u32)
+        .or_else(|| parts[0].parse().ok().filter(|n| *n as f32 <= LAST_FILTER_TYPE))
+        .map(FilterType::from_u32)
+        .ok_or_else(|| format!("{}: unknown type {}", arg, parts[0]))?;
+    if apo::type_name(ftype).is_none() {
+        return Err(format!("{}: Equalizer APO has no filter of type {}", arg, parts[0]));
+    }
+    let number = |s: &str| s.parse::<f32>().map_err(|_| format!("{}: not a number: {}", arg, s));
+    Ok(apo::Band {
+        enabled: true,
+        ftype: ftype,
+        freq: number(parts[1])?,
+        q: number(parts[2])?,
+        gain: number(parts[3])?
+    })
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let mut chain = apo::Chain::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--preamp" {
+            let value = args.next().ok_or_else(|| "--preamp needs a value".to_string())?;
+            chain.preamp = value.parse().map_err(|_| format!("--preamp: not a number: {}", value))?;
+        } else {
+            chain.bands.push(band(arg)?);
+        }
+    }
+    print!("{}", apo::write(&chain));
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["show", path] => show(path),
+        ["preset", path, bundle] => preset(path, bundle, None),
+        ["preset", path, bundle, "--name", name] => preset(path, bundle, Some(name.to_string())),
+        ["export", ..] => export(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("eqapo: {}", err);
+        process::exit(1);
+    }
+}
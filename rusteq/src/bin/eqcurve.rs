@@ -0,0 +1,183 @@
+/*
+   Renders the frequency response of the EQ for a set of its control values
+   to an SVG or PNG file, chosen by the extension of the output file:
+
+       eqcurve --type PEAK --freq 1000 --q 2 --gain 6 peak.svg
+
+   The options take the values of the plugin's controls: the type by number
+   or name (LPF1, HPF1, LPF2, HPF2, BPF2, NOTCH, PEAK, LoShelf, HiShelf, APF1,
+   APF2, Tilt, "BPF 0dB", "BPF Gain"), frequency in Hz, Q, stages, gain in
+   dB, the category (analog or sv for the state variable filter), the design
+   of the analog filter (bilinear or matched) and its slope (by number or
+   name, e.g. "Butterworth 24 dB"), plus the sample rate.  The size of the
+   image, the frequency range and the dB range can be set as well, by
+   default the dB range follows the curve.
+*/
+
+extern crate rusteq;
+
+use rusteq::analog_eq::*;
+use rusteq::plot::Plot;
+use rusteq::response::magnitude_curve;
+use std::env;
+use std::fs;
+use std::process;
+
+
+const USAGE: &'static str = "usage: eqcurve [--type T] [--freq HZ] [--q Q] [--stages N] [--gain DB] \
+    [--category analog|sv] [--design bilinear|matched] [--slope S] [--rate HZ] [--width PX] [--height PX] [--from HZ] [--to HZ] [--range MIN:MAX] <output.svg|output.png>";
+
+// points of the curve, plenty for a smooth line
+const POINTS: usize = 1000;
+
+
+struct Options {
+    ftype: f32,
+    freq: f32,
+    q: f32,
+    stages: f32,
+    gain: f32,
+    state_variable: bool,
+    design: Design,
+    slope: Slope,
+    rate: u32,
+    width: u32,
+    height: u32,
+    from: f64,
+    to: f64,
+    range: Option<(f64, f64)>,
+    output: String
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    // the defaults of the plugin's controls
+    let mut options = Options {
+        ftype: 0.0,
+        freq: 100.0,
+        q: 0.707,
+        stages: 1.0,
+        gain: 0.0,
+        state_variable: false,
+        design: Design::Bilinear,
+        slope: Slope::Stages,
+        rate: 48000,
+        width: 800,
+        height: 400,
+        from: 20.0,
+        to: 20000.0,
+        range: None,
+        output: String::new()
+    };
+
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if output.is_some() {
+                return Err(format!("more than one output file: {}", arg));
+            }
+            output = Some(arg.clone());
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--type" => options.ftype = scale_point(arg, FILTER_TYPES, LAST_FILTER_TYPE, value)?,
+            "--freq" => options.freq = number(arg, value)?,
+            "--q" => options.q = number(arg, value)?,
+            "--stages" => options.stages = number(arg, value)?,
+            "--gain" => options.gain = number(arg, value)?,
+            "--category" => options.state_variable = match value.as_str() {
+                "analog" | "0" => false,
+                "sv" | "1" => true,
+                _ => return Err(format!("--category: unknown category {}", value))
+            },
+            "--design" => options.design = match value.as_str() {
+                "bilinear" | "0" => Design::Bilinear,
+                "matched" | "1" => Design::Matched,
+                _ => return Err(format!("--design: unknown design {}", value))
+            },
+            "--slope" => options.slope = Slope::from_u32(scale_point(arg, SLOPES, LAST_SLOPE, value)? as u32),
+            "--rate" => options.rate = number(arg, value)?,
+            "--width" => options.width = number(arg, value)?,
+            "--height" => options.height = number(arg, value)?,
+            "--from" => options.from = number(arg, value)?,
+            "--to" => options.to = number(arg, value)?,
+            "--range" => {
+                let mut parts = value.splitn(2, ':');
+                let min = number(arg, parts.next().unwrap_or(""))?;
+                let max = number(arg, parts.next().unwrap_or(""))?;
+                if min >= max {
+                    return Err(format!("--range: {} is not below {}", min, max));
+                }
+                options.range = Some((min, max));
+            },
+            _ => return Err(format!("unknown option {}", arg))
+        }
+    }
+
+    options.output = output.ok_or_else(|| "no output file".to_string())?;
+    if options.width < 100 || options.height < 100 {
+        return Err("the image must be at least 100x100".to_string());
+    }
+    if !(options.from > 0.0 && options.from < options.to) {
+        return Err("the frequency range must be positive and increasing".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{}: not a number: {}", option, value))
+}
+
+/* The value of a control by the label of its scale point or by number. */
+fn scale_point(option: &str, points: &[(&str, f32)], last: f32, value: &str) -> Result<f32, String> {
+    if let Some(&(_, n)) = points.iter().find(|t| t.0.eq_ignore_ascii_case(value)) {
+        return Ok(n);
+    }
+    match value.parse::<u32>() {
+        Ok(n) if n as f32 <= last => Ok(n as f32),
+        _ => Err(format!("{}: unknown value {}", option, value))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("eqcurve: {}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    // set up like the plugin does from its controls
+    let curve = if options.state_variable {
+        let mut filter = SVFilter::new(&FilterType::LPF1, 1000.0, 1.0, 1, options.rate, DEFAULT_MAX_BLOCK);
+        filter.set_values(options.ftype, options.freq, options.q, options.stages, options.gain);
+        magnitude_curve(&filter, options.from, options.to, POINTS)
+    } else {
+        let mut filter = AnalogFilter::new(&FilterType::LPF1, 1000.0, 1.0, 1, options.rate, DEFAULT_MAX_BLOCK);
+        filter.set_design(options.design);
+        filter.set_slope(options.slope);
+        filter.set_values(options.ftype, options.freq, options.q, options.stages, options.gain);
+        magnitude_curve(&filter, options.from, options.to, POINTS)
+    };
+    let plot = Plot::new(options.width, options.height, options.from, options.to);
+    let plot = match options.range {
+        Some((min, max)) => plot.range(min, max),
+        None => plot.fit(&curve)
+    };
+
+    let data = if options.output.ends_with(".svg") {
+        plot.svg(&curve).into_bytes()
+    } else if options.output.ends_with(".png") {
+        plot.png(&curve)
+    } else {
+        eprintln!("eqcurve: {}: the output must be a .svg or .png file", options.output);
+        process::exit(2);
+    };
+    if let Err(err) = fs::write(&options.output, data) {
+        eprintln!("eqcurve: {}: {}", options.output, err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,102 @@
+/*
+   Which part of a stereo signal an EQ works on.  Mid and side are encoded
+   as m = (l + r) / 2 and s = (l - r) / 2, so decoding is l = m + s and
+   r = m - s without further scaling.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelMode {
+    // both channels with the same settings
+    Stereo,
+    Left,
+    Right,
+    Mid,
+    Side
+}
+
+impl ChannelMode {
+    pub fn from_u32(x: u32) -> ChannelMode {
+        match x {
+            1 => ChannelMode::Left,
+            2 => ChannelMode::Right,
+            3 => ChannelMode::Mid,
+            4 => ChannelMode::Side,
+            _ => ChannelMode::Stereo
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        match self {
+            ChannelMode::Stereo => 0,
+            ChannelMode::Left => 1,
+            ChannelMode::Right => 2,
+            ChannelMode::Mid => 3,
+            ChannelMode::Side => 4
+        }
+    }
+
+    /*
+       Which of the two channels `process` filters, in the mid and side
+       modes the first one is the mid and the second one the side signal.
+    */
+    pub fn filtered(self) -> (bool, bool) {
+        match self {
+            ChannelMode::Stereo => (true, true),
+            ChannelMode::Left | ChannelMode::Mid => (true, false),
+            ChannelMode::Right | ChannelMode::Side => (false, true)
+        }
+    }
+
+    /* Whether the channels are encoded to mid and side for filtering. */
+    pub fn mid_side(self) -> bool {
+        self == ChannelMode::Mid || self == ChannelMode::Side
+    }
+
+    /*
+       Apply `filter_l` and `filter_r` to the channels in place, the way the
+       mode says.  In the mid and side modes `filter_l` filters the mid or
+       `filter_r` the side signal.
+    */
+    pub fn process<L, R>(self, left: &mut [f32], right: &mut [f32], filter_l: L, filter_r: R)
+        where L: FnOnce(&mut [f32]), R: FnOnce(&mut [f32]) {
+
+        match self {
+            ChannelMode::Stereo => {
+                filter_l(left);
+                filter_r(right);
+            },
+            ChannelMode::Left => filter_l(left),
+            ChannelMode::Right => filter_r(right),
+            ChannelMode::Mid => {
+                ms_encode(left, right);
+                filter_l(left);
+                ms_decode(left, right);
+            },
+            ChannelMode::Side => {
+                ms_encode(left, right);
+                filter_r(right);
+                ms_decode(left, right);
+            }
+        }
+    }
+}
+
+/* Left and right to mid and side, in place. */
+pub fn ms_encode(left: &mut [f32], right: &mut [f32]) {
+    for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+        let mid = (*l + *r) * 0.5;
+        let side = (*l - *r) * 0.5;
+        *l = mid;
+        *r = side;
+    }
+}
+
+/* Mid and side back to left and right, in place. */
+pub fn ms_decode(mid: &mut [f32], side: &mut [f32]) {
+    for (m, s) in mid.iter_mut().zip(side.iter_mut()) {
+        let left = *m + *s;
+        let right = *m - *s;
+        *m = left;
+        *s = right;
+    }
+}
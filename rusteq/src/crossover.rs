@@ -0,0 +1,344 @@
+/*
+   A crossover: splits a stereo signal into two to four bands at up to three
+   crossover frequencies, for processing the bands in parallel.  Every
+   crossover is a Linkwitz-Riley low and high pass (`AnalogFilter` with a
+   `Slope::LinkwitzRiley`), which add up to an allpass.  The bands below a
+   crossover run through that allpass as well, so all bands stay in phase
+   and their sum is the input with only its phase changed.
+
+   The bands are in the order of the crossover frequencies as they are set,
+   a frequency below the one before it is raised to it.  Outputs of bands
+   not in use are silent.
+*/
+
+use rustlv2::*;
+use libc::c_void;
+
+use analog_eq::*;
+
+
+const CROSSOVER_URI: &'static [u8] = b"http://example.org/rustcrossover\0";
+
+pub const MAX_BANDS: usize = 4;
+const CROSSOVERS: usize = MAX_BANDS - 1;
+// sections of an allpass, two for 48 dB
+const ALLPASS_SECTIONS: usize = 2;
+
+port_index! {
+    pub(crate) enum PortIndex {
+        XInputL = 0,
+        XInputR = 1,
+        XBands = 10,
+        XFreq1 = 11,
+        XFreq2 = 12,
+        XFreq3 = 13,
+        XSlope = 14
+    }
+}
+
+/* The outputs follow the inputs, left and right of band `n` (0 based). */
+const FIRST_OUTPUT: u32 = 2;
+
+pub fn band_output(band: usize, right: bool) -> u32 {
+    FIRST_OUTPUT + 2 * band as u32 + right as u32
+}
+
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::audio_in(PortIndex::XInputL as u32, "inl", "In L"),
+    PortInfo::audio_in(PortIndex::XInputR as u32, "inr", "In R"),
+    PortInfo::audio_out(2, "out1l", "Band 1 L"),
+    PortInfo::audio_out(3, "out1r", "Band 1 R"),
+    PortInfo::audio_out(4, "out2l", "Band 2 L"),
+    PortInfo::audio_out(5, "out2r", "Band 2 R"),
+    PortInfo::audio_out(6, "out3l", "Band 3 L"),
+    PortInfo::audio_out(7, "out3r", "Band 3 R"),
+    PortInfo::audio_out(8, "out4l", "Band 4 L"),
+    PortInfo::audio_out(9, "out4r", "Band 4 R"),
+    PortInfo::control_in(PortIndex::XBands as u32, "bands", "Bands", 3.0, 2.0, 4.0)
+        .properties(&["epp:hasStrictBounds", "lv2:integer"]),
+    PortInfo::control_in(PortIndex::XFreq1 as u32, "frequency1", "Crossover 1", 200.0, 20.0, 20000.0)
+        .unit("units:hz")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+    PortInfo::control_in(PortIndex::XFreq2 as u32, "frequency2", "Crossover 2", 2000.0, 20.0, 20000.0)
+        .unit("units:hz")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+    PortInfo::control_in(PortIndex::XFreq3 as u32, "frequency3", "Crossover 3", 8000.0, 20.0, 20000.0)
+        .unit("units:hz")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+    PortInfo::control_in(PortIndex::XSlope as u32, "slope", "Slope", 1.0, 0.0, 2.0)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(&[("12 dB", 0.0), ("24 dB", 1.0), ("48 dB", 2.0)])
+];
+
+fn slope(value: f32) -> Slope {
+    match value as u32 {
+        0 => Slope::LinkwitzRiley(2),
+        1 => Slope::LinkwitzRiley(4),
+        _ => Slope::LinkwitzRiley(8)
+    }
+}
+
+
+/* The filters of one channel. */
+struct Channel {
+    lows: Vec<AnalogFilter>,
+    highs: Vec<AnalogFilter>,
+    // for band b and each crossover above it, see `allpass`
+    allpasses: Vec<AnalogFilter>,
+    // what is left above the crossovers so far
+    rest: Vec<f32>
+}
+
+fn allpass(band: usize, crossover: usize, section: usize) -> usize {
+    (band * CROSSOVERS + crossover) * ALLPASS_SECTIONS + section
+}
+
+impl Channel {
+    fn new(srate: u32, max_block: usize) -> Channel {
+        let filter = |ftype| AnalogFilter::new(&ftype, 1000.0, 0.707, 0, srate, max_block);
+        Channel {
+            lows: (0..CROSSOVERS).map(|_| filter(FilterType::LPF2)).collect(),
+            highs: (0..CROSSOVERS).map(|_| filter(FilterType::HPF2)).collect(),
+            allpasses: (0..CROSSOVERS * CROSSOVERS * ALLPASS_SECTIONS).map(|_| filter(FilterType::APF2)).collect(),
+            rest: vec![0.0; max_block.max(1)]
+        }
+    }
+
+    fn set(&mut self, freqs: &[f32; CROSSOVERS], slope: Slope) {
+        let (qs, sections) = slope.crossover_allpass().unwrap_or(([0.0; MAX_SECTIONS], 0));
+        for (i, &freq) in freqs.iter().enumerate() {
+            self.lows[i].set_slope(slope);
+            self.lows[i].set_values(FilterType::LPF2.to_u32() as f32, freq, 0.707, 0.0, 0.0);
+            self.highs[i].set_slope(slope);
+            self.highs[i].set_values(FilterType::HPF2.to_u32() as f32, freq, 0.707, 0.0, 0.0);
+
+            // the allpasses of this crossover in the bands below it
+            for band in 0..i {
+                for (section, &q) in qs.iter().take(ALLPASS_SECTIONS).enumerate() {
+                    let filter = &mut self.allpasses[allpass(band, i, section)];
+                    filter.set_enabled(section < sections);
+                    let ftype = if q == 0.0 { FilterType::APF1 } else { FilterType::APF2 };
+                    filter.set_values(ftype.to_u32() as f32, freq, q as f32, 0.0, 0.0);
+                }
+            }
+        }
+    }
+
+    fn cleanup(&mut self) {
+        for filter in self.lows.iter_mut().chain(self.highs.iter_mut()).chain(self.allpasses.iter_mut()) {
+            filter.cleanup();
+        }
+    }
+
+    /*
+       Split the block in `rest` off into `output`, the bands from the
+       lowest one up to the `last`, all as long as the block.  The high pass
+       of 12 dB is inverted to add up to an allpass with the low pass.
+    */
+    fn split(&mut self, band: usize, last: usize, output: &mut [f32], invert: bool) {
+        let rest = &mut self.rest[..output.len()];
+        output.copy_from_slice(rest);
+        if band == last {
+            return;
+        }
+        self.lows[band].filter(output);
+        for crossover in (band + 1)..last {
+            for section in 0..ALLPASS_SECTIONS {
+                let filter = &mut self.allpasses[allpass(band, crossover, section)];
+                if !filter.is_bypassed() {
+                    filter.filter(output);
+                }
+            }
+        }
+        self.highs[band].filter(rest);
+        if invert {
+            for s in rest.iter_mut() {
+                *s = -*s;
+            }
+        }
+    }
+}
+
+
+pub struct Crossover {
+    input_l: AudioIn,
+    input_r: AudioIn,
+    outputs: Vec<AudioOut>,
+    bands: ControlIn,
+    freqs: [ControlIn; CROSSOVERS],
+    slope: ControlIn,
+    // the control values the filters were set up with
+    cached: [f32; CROSSOVERS + 2],
+    channels: [Channel; 2],
+    max_block: usize
+}
+
+impl Plugin for Crossover {
+    const URI: &'static [u8] = CROSSOVER_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust crossover",
+        classes: &["lv2:FilterPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable", "opts:options", "bufsz:boundedBlockLength"],
+        extension_data: &[],
+        supported_options: &["bufsz:maxBlockLength"],
+        ports: PORTS,
+        parameters: &[]
+    };
+
+    fn instantiate(rate: f64, features: &Features) -> Option<Crossover> {
+        let max_block = features.max_block_length().unwrap_or(DEFAULT_MAX_BLOCK);
+
+        Some(Crossover {
+            input_l: AudioIn::new(),
+            input_r: AudioIn::new(),
+            outputs: (0..2 * MAX_BANDS).map(|_| AudioOut::new()).collect(),
+            bands: ControlIn::new(),
+            freqs: [ControlIn::new(), ControlIn::new(), ControlIn::new()],
+            slope: ControlIn::new(),
+            cached: [f32::NAN; CROSSOVERS + 2],
+            channels: [Channel::new(rate as u32, max_block), Channel::new(rate as u32, max_block)],
+            max_block: max_block
+        })
+    }
+
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::XInputL) => self.input_l.connect(data),
+            Some(PortIndex::XInputR) => self.input_r.connect(data),
+            Some(PortIndex::XBands) => self.bands.connect(data),
+            Some(PortIndex::XFreq1) => self.freqs[0].connect(data),
+            Some(PortIndex::XFreq2) => self.freqs[1].connect(data),
+            Some(PortIndex::XFreq3) => self.freqs[2].connect(data),
+            Some(PortIndex::XSlope) => self.slope.connect(data),
+            None => {
+                if let Some(output) = self.outputs.get_mut((port - FIRST_OUTPUT) as usize) {
+                    output.connect(data);
+                }
+            }
+        }
+    }
+
+    /* The first run sets up the filters without crossfade. */
+    fn activate(&mut self) {
+        self.cached = [f32::NAN; CROSSOVERS + 2];
+    }
+
+    fn run(&mut self, n_samples: u32) {
+        let n = n_samples as usize;
+
+        let bands = (self.bands.value() as usize).clamp(2, MAX_BANDS);
+        let mut freqs = [self.freqs[0].value(), self.freqs[1].value(), self.freqs[2].value()];
+        for i in 1..CROSSOVERS {
+            freqs[i] = freqs[i].max(freqs[i - 1]);
+        }
+        let values = [bands as f32, freqs[0], freqs[1], freqs[2], self.slope.value()];
+        if values != self.cached {
+            // bands coming into use or going out of it start from silence
+            let restart = values[0] != self.cached[0];
+            self.cached = values;
+            for channel in self.channels.iter_mut() {
+                channel.set(&freqs, slope(values[4]));
+                if restart {
+                    channel.cleanup();
+                }
+            }
+        }
+        let invert = slope(values[4]) == Slope::LinkwitzRiley(2);
+
+        let inputs = [self.input_l.as_slice(n), self.input_r.as_slice(n)];
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            for band in bands..MAX_BANDS {
+                for s in self.outputs[2 * band + c].as_mut_slice(n).iter_mut() {
+                    *s = 0.0;
+                }
+            }
+
+            // the host may run longer blocks than it announced
+            let mut start = 0;
+            while start < n {
+                let end = (start + self.max_block).min(n);
+                channel.rest[..end - start].copy_from_slice(&inputs[c][start..end]);
+                for band in 0..bands {
+                    let output = &mut self.outputs[2 * band + c].as_mut_slice(n)[start..end];
+                    channel.split(band, bands - 1, output, invert);
+                }
+                start = end;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use tests::{instantiate_with_defaults, level_db, run_stereo, sine};
+
+    fn crossover(host: &TestHost, bands: f32, slope: f32) -> Instance<'_> {
+        let mut plugin = instantiate_with_defaults::<Crossover>(host, "http://example.org/rustcrossover");
+        plugin.set_control(PortIndex::XBands as u32, bands);
+        plugin.set_control(PortIndex::XSlope as u32, slope);
+        plugin.activate();
+        plugin
+    }
+
+    /* The levels of the left outputs of the bands, in dB. */
+    fn crossover_levels(plugin: &mut Instance, freq: f32) -> (f32, Vec<f32>) {
+        let input = sine(freq, 9600);
+        run_stereo(plugin, (PortIndex::XInputL as u32, PortIndex::XInputR as u32),
+            &input, &vec![0.0; input.len()]);
+
+        let mut sum = vec![0.0; input.len()];
+        let mut levels = Vec::new();
+        for band in 0..MAX_BANDS {
+            let out = plugin.audio_output(band_output(band, false));
+            for (s, x) in sum.iter_mut().zip(out.iter()) {
+                *s += x;
+            }
+            levels.push(level_db(out, &input, 4800));
+            assert!(plugin.audio_output(band_output(band, true)).iter().all(|x| *x == 0.0));
+        }
+        (level_db(&sum, &input, 4800), levels)
+    }
+
+    #[test]
+    fn crossover_sums_flat() {
+        let host = TestHost::new();
+        for bands in 2..5 {
+            for slope in 0..3 {
+                let mut plugin = crossover(&host, bands as f32, slope as f32);
+                for &freq in &[50.0, 200.0, 700.0, 2000.0, 5000.0, 8000.0, 15000.0] {
+                    let (sum, levels) = crossover_levels(&mut plugin, freq);
+                    assert!(sum.abs() < 0.01, "{} bands, slope {}, {} Hz: {} dB", bands, slope, freq, sum);
+                    assert!(levels[bands..].iter().all(|l| *l == f32::NEG_INFINITY));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crossover_bands() {
+        let host = TestHost::new();
+        let mut plugin = crossover(&host, 4.0, 1.0);
+        // the crossovers are at 200, 2000 and 8000 Hz
+        for &(freq, band) in &[(30.0, 0), (630.0, 1), (4000.0, 2), (18000.0, 3)] {
+            let (_, levels) = crossover_levels(&mut plugin, freq);
+            for (i, level) in levels.iter().enumerate() {
+                if i == band {
+                    assert!(level.abs() < 1.0, "{} Hz in band {}: {} dB", freq, i, level);
+                } else {
+                    assert!(*level < -18.0, "{} Hz in band {}: {} dB", freq, i, level);
+                }
+            }
+        }
+
+        // at the crossover half in each band
+        let (_, levels) = crossover_levels(&mut plugin, 2000.0);
+        assert!((levels[1] + 6.02).abs() < 0.1 && (levels[2] + 6.02).abs() < 0.1, "{:?}", levels);
+    }
+}
@@ -0,0 +1,74 @@
+/*
+   Publishing the magnitude curve of an EQ on an atom output, so that host
+   UIs or inline displays can draw it.  Every change of the controls sends
+   one object
+
+       [] a rusteq:Curve ;
+           rusteq:minFrequency 20.0 ;
+           rusteq:maxFrequency 20000.0 ;
+           rusteq:magnitude [ a atom:Vector ; ... CURVE_POINTS floats in dB ... ] .
+
+   with the magnitudes at log spaced frequencies from the minimum to the
+   maximum frequency, see `response::log_frequency`.
+*/
+
+use rustlv2::*;
+use rustlv2::lv2_raw::LV2Urid;
+
+use response::*;
+
+
+pub const CURVE_URI: &'static [u8] = b"http://example.org/rusteq#Curve\0";
+pub const CURVE_MIN_FREQUENCY: &'static [u8] = b"http://example.org/rusteq#minFrequency\0";
+pub const CURVE_MAX_FREQUENCY: &'static [u8] = b"http://example.org/rusteq#maxFrequency\0";
+pub const CURVE_MAGNITUDE: &'static [u8] = b"http://example.org/rusteq#magnitude\0";
+
+pub const CURVE_POINTS: usize = 64;
+pub const CURVE_FROM: f64 = 20.0;
+pub const CURVE_TO: f64 = 20000.0;
+
+// the sequence holding one curve event, with some room to spare
+pub const CURVE_BUFFER_SIZE: u32 = 1024;
+
+
+pub struct CurvePublisher {
+    forge: Forge,
+    curve: LV2Urid,
+    min_frequency: LV2Urid,
+    max_frequency: LV2Urid,
+    magnitude: LV2Urid
+}
+
+impl CurvePublisher {
+    pub fn new(map: &UridMap) -> CurvePublisher {
+        CurvePublisher {
+            forge: Forge::new(map),
+            curve: map.map(CURVE_URI),
+            min_frequency: map.map(CURVE_MIN_FREQUENCY),
+            max_frequency: map.map(CURVE_MAX_FREQUENCY),
+            magnitude: map.map(CURVE_MAGNITUDE)
+        }
+    }
+
+    /*
+       Write this run's sequence to `port`, with the curve of `filter` if
+       `filter` is given.  Returns false if the port is not connected or the
+       curve did not fit into it, the curve then has to be sent later.
+    */
+    pub fn publish<R: FrequencyResponse + ?Sized>(&self, port: &mut AtomOut, filter: Option<&R>) -> bool {
+        let mut seq = match self.forge.sequence(port) {
+            Some(seq) => seq,
+            None => return false
+        };
+        match filter {
+            Some(filter) => seq.object(0, self.curve, |obj| {
+                obj.float(self.min_frequency, CURVE_FROM as f32);
+                obj.float(self.max_frequency, CURVE_TO as f32);
+                obj.float_vector(self.magnitude, CURVE_POINTS, |i| {
+                    filter.magnitude_db(log_frequency(CURVE_FROM, CURVE_TO, CURVE_POINTS, i)) as f32
+                });
+            }),
+            None => true
+        }
+    }
+}
@@ -0,0 +1,538 @@
+/*
+   A dynamic EQ: four bands in series whose gain follows the level of the
+   signal, to tame resonances only while they ring.  Each band is a peak or
+   a shelf (`AnalogFilter`) on both channels.  An envelope follower on the
+   mid signal, filtered to the band (a band pass with the bandwidth of the
+   peak, or a low or high pass at the frequency of the shelf), drives its
+   gain like a compressor: above the threshold the gain changes by the
+   level over it times 1 - 1 / ratio, up to the range.  Negative ranges cut
+   above the threshold, positive ones boost.
+
+   The gains are updated every `UPDATE_STEP` samples, counted across
+   blocks like in the formant filter, and every update crossfades the
+   filters over the step.  So the gain follows the envelope in steps of
+   `UPDATE_STEP`, each of them smooth.  The current gain of every band is
+   reported on an output port for meters.
+*/
+
+use rustlv2::*;
+use libc::c_void;
+
+use analog_eq::*;
+
+
+const DYNAMIC_URI: &'static [u8] = b"http://example.org/rustdynamiceq\0";
+
+pub const BANDS: usize = 4;
+
+pub const UPDATE_STEP: usize = 64;
+
+// changes of the gain smaller than this are not worth a crossfade
+const GAIN_STEP_DB: f32 = 0.05;
+
+port_index! {
+    pub(crate) enum PortIndex {
+        DInputL = 0,
+        DInputR = 1,
+        DOutputL = 2,
+        DOutputR = 3
+    }
+}
+
+/* The ports of band `n` (0 based) follow the audio ports in the order of `BandControl`. */
+const FIRST_BAND_PORT: u32 = 4;
+const BAND_CONTROLS: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandControl {
+    Enable = 0,
+    Type = 1,
+    Freq = 2,
+    Q = 3,
+    Threshold = 4,
+    Ratio = 5,
+    Attack = 6,
+    Release = 7,
+    Range = 8,
+    // output, the gain in dB
+    Gain = 9
+}
+
+pub fn band_port(band: usize, control: BandControl) -> u32 {
+    FIRST_BAND_PORT + band as u32 * BAND_CONTROLS + control as u32
+}
+
+// the types of `FILTER_TYPES` a band can have
+const BAND_TYPES: &'static [(&'static str, f32)] = &[("PEAK", 6.0), ("LoShelf", 7.0), ("HiShelf", 8.0)];
+
+/*
+   The ports of one band, with its number as a literal for the symbols and
+   names, its first port index and its default type and frequency.
+*/
+macro_rules! dynamic_ports {
+    ($($n:tt => ($first:expr, $ftype:expr, $freq:expr)),+) => {
+        &[
+            PortInfo::audio_in(PortIndex::DInputL as u32, "inl", "In L"),
+            PortInfo::audio_in(PortIndex::DInputR as u32, "inr", "In R"),
+            PortInfo::audio_out(PortIndex::DOutputL as u32, "outl", "Out L"),
+            PortInfo::audio_out(PortIndex::DOutputR as u32, "outr", "Out R"),
+            $(
+                PortInfo::control_in($first, concat!("enable", $n), concat!("Band ", $n, " Enable"), 0.0, 0.0, 1.0)
+                    .properties(&["lv2:toggled"]),
+                PortInfo::control_in($first + 1, concat!("type", $n), concat!("Band ", $n, " Type"), $ftype, 6.0, 8.0)
+                    .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+                    .scale_points(BAND_TYPES),
+                PortInfo::control_in($first + 2, concat!("frequency", $n), concat!("Band ", $n, " Frequency"),
+                    $freq, 20.0, 20000.0)
+                    .unit("units:hz")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 3, concat!("q", $n), concat!("Band ", $n, " Q"), 0.707, 0.1, 20.0)
+                    .unit("units:coef")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 4, concat!("threshold", $n), concat!("Band ", $n, " Threshold"),
+                    -24.0, -60.0, 0.0)
+                    .unit("units:db")
+                    .properties(&["epp:hasStrictBounds"]),
+                PortInfo::control_in($first + 5, concat!("ratio", $n), concat!("Band ", $n, " Ratio"), 4.0, 1.0, 20.0)
+                    .unit("units:coef")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 6, concat!("attack", $n), concat!("Band ", $n, " Attack"), 5.0, 0.1, 100.0)
+                    .unit("units:ms")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 7, concat!("release", $n), concat!("Band ", $n, " Release"),
+                    100.0, 5.0, 2000.0)
+                    .unit("units:ms")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 8, concat!("range", $n), concat!("Band ", $n, " Range"), -12.0, -24.0, 24.0)
+                    .unit("units:db")
+                    .properties(&["epp:hasStrictBounds"]),
+                PortInfo::control_out($first + 9, concat!("gain", $n), concat!("Band ", $n, " Gain"))
+                    .unit("units:db"),
+            )+
+        ]
+    }
+}
+
+const PORTS: &'static [PortInfo] = dynamic_ports! {
+    1 => (4, 7.0, 100.0),
+    2 => (14, 6.0, 500.0),
+    3 => (24, 6.0, 2500.0),
+    4 => (34, 8.0, 8000.0)
+};
+
+
+/* The settings of a band, from its controls. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BandSettings {
+    pub enabled: bool,
+    pub ftype: FilterType,
+    pub freq: f32,
+    pub q: f32,
+    // dB
+    pub threshold: f32,
+    pub ratio: f32,
+    // seconds
+    pub attack: f32,
+    pub release: f32,
+    // dB
+    pub range: f32
+}
+
+impl BandSettings {
+    /* The gain in dB for the detected `level` in dB. */
+    pub fn gain(&self, level: f32) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+        let over = (level - self.threshold).max(0.0);
+        let amount = over * (1.0 - 1.0 / self.ratio.max(1.0));
+        amount.min(self.range.abs()) * self.range.signum()
+    }
+
+    /* The filter the level is detected after, its type and Q. */
+    fn detector(&self) -> (FilterType, f32) {
+        match self.ftype {
+            FilterType::LoShelf => (FilterType::LPF2, 0.707),
+            FilterType::HiShelf => (FilterType::HPF2, 0.707),
+            _ => (FilterType::BPFPeak, rbj_q(FilterType::PEAK2, self.q))
+        }
+    }
+}
+
+
+pub struct DynamicBand {
+    settings: BandSettings,
+    // the detector and the filters follow new settings at the next update
+    changed: bool,
+    detector: AnalogFilter,
+    filter_l: AnalogFilter,
+    filter_r: AnalogFilter,
+    // per sample, of the envelope
+    attack: f64,
+    release: f64,
+    envelope: f64,
+    // dB, what the filters are set to
+    gain: f32,
+    first_time: bool,
+    samplerate_f: f64
+}
+
+impl DynamicBand {
+    pub fn new(srate: u32) -> DynamicBand {
+        let filter = || {
+            let mut filter = AnalogFilter::new(&FilterType::PEAK2, 1000.0, 0.707, 0, srate, UPDATE_STEP);
+            filter.set_fade_len(UPDATE_STEP);
+            filter
+        };
+        DynamicBand {
+            settings: BandSettings {
+                enabled: false,
+                ftype: FilterType::PEAK2,
+                freq: 1000.0,
+                q: 0.707,
+                threshold: 0.0,
+                ratio: 1.0,
+                attack: 0.0,
+                release: 0.0,
+                range: 0.0
+            },
+            changed: true,
+            detector: filter(),
+            filter_l: filter(),
+            filter_r: filter(),
+            attack: 1.0,
+            release: 1.0,
+            envelope: 0.0,
+            gain: 0.0,
+            first_time: true,
+            samplerate_f: srate as f64
+        }
+    }
+
+    pub fn set(&mut self, settings: BandSettings) {
+        if settings == self.settings {
+            return;
+        }
+        let samplerate = self.samplerate_f;
+        let coefficient = |seconds: f32| {
+            let samples = seconds as f64 * samplerate;
+            if samples > 0.0 { 1.0 - (-1.0 / samples).exp() } else { 1.0 }
+        };
+        self.attack = coefficient(settings.attack);
+        self.release = coefficient(settings.release);
+        self.settings = settings;
+        self.changed = true;
+    }
+
+    /* The gain in dB the filters are set to. */
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /* Silence the band, the next update starts at the settings without crossfade. */
+    pub fn cleanup(&mut self) {
+        for filter in [&mut self.detector, &mut self.filter_l, &mut self.filter_r].iter_mut() {
+            filter.cleanup();
+        }
+        self.envelope = 0.0;
+        self.first_time = true;
+    }
+
+    /* Follow the envelope and new settings. */
+    fn update(&mut self) {
+        let settings = self.settings;
+        if self.changed {
+            let (ftype, q) = settings.detector();
+            self.detector.set_values(ftype.to_u32() as f32, settings.freq, q, 0.0, 0.0);
+        }
+
+        let level = 20.0 * self.envelope.max(1e-10).log10() as f32;
+        let gain = settings.gain(level);
+        // small steps are skipped, but no gain is left short of 0 dB or the range
+        let settled = gain == 0.0 || gain == settings.range;
+        if self.changed || self.first_time || (gain - self.gain).abs() > GAIN_STEP_DB || (settled && gain != self.gain) {
+            self.gain = gain;
+            for filter in [&mut self.filter_l, &mut self.filter_r].iter_mut() {
+                filter.set_enabled(settings.enabled);
+                filter.set_values(settings.ftype.to_u32() as f32, settings.freq, settings.q, 0.0, gain);
+            }
+        }
+        if self.first_time {
+            for filter in [&mut self.detector, &mut self.filter_l, &mut self.filter_r].iter_mut() {
+                filter.cleanup();
+            }
+        }
+        self.changed = false;
+        self.first_time = false;
+    }
+
+    /* Run the envelope over `mid`, `scratch` is as long as it. */
+    fn detect(&mut self, mid: &[f32], scratch: &mut [f32]) {
+        if !self.settings.enabled {
+            self.envelope = 0.0;
+            return;
+        }
+        scratch.copy_from_slice(mid);
+        self.detector.filter(scratch);
+        for x in scratch.iter() {
+            let x = x.abs() as f64;
+            let k = if x > self.envelope { self.attack } else { self.release };
+            self.envelope += (x - self.envelope) * k;
+        }
+    }
+
+    fn filter(&mut self, left: &mut [f32], right: &mut [f32]) {
+        if !self.filter_l.is_bypassed() {
+            self.filter_l.filter(left);
+            self.filter_r.filter(right);
+        }
+    }
+}
+
+
+pub struct DynamicEQ {
+    input_l: AudioIn,
+    input_r: AudioIn,
+    output_l: AudioOut,
+    output_r: AudioOut,
+    controls: Vec<[ControlIn; BAND_CONTROLS as usize - 1]>,
+    meters: Vec<ControlOut>,
+    bands: Vec<DynamicBand>,
+    // samples until the next update
+    until_update: usize,
+    mid: [f32; UPDATE_STEP],
+    scratch: [f32; UPDATE_STEP],
+    activated: bool
+}
+
+impl DynamicEQ {
+    fn settings(&self, band: usize) -> BandSettings {
+        let value = |control: BandControl| self.controls[band][control as usize].value();
+        BandSettings {
+            enabled: value(BandControl::Enable) > 0.0,
+            ftype: FilterType::from_u32(value(BandControl::Type) as u32),
+            freq: value(BandControl::Freq),
+            q: value(BandControl::Q),
+            threshold: value(BandControl::Threshold),
+            ratio: value(BandControl::Ratio),
+            attack: value(BandControl::Attack) / 1000.0,
+            release: value(BandControl::Release) / 1000.0,
+            range: value(BandControl::Range)
+        }
+    }
+}
+
+
+impl Plugin for DynamicEQ {
+    const URI: &'static [u8] = DYNAMIC_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust 4 band dynamic EQ",
+        classes: &["lv2:EQPlugin", "lv2:DynamicsPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable"],
+        extension_data: &[],
+        supported_options: &[],
+        ports: PORTS,
+        parameters: &[]
+    };
+
+    fn instantiate(rate: f64, _features: &Features) -> Option<DynamicEQ> {
+        Some(DynamicEQ {
+            input_l: AudioIn::new(),
+            input_r: AudioIn::new(),
+            output_l: AudioOut::new(),
+            output_r: AudioOut::new(),
+            controls: (0..BANDS).map(|_| Default::default()).collect(),
+            meters: (0..BANDS).map(|_| ControlOut::new()).collect(),
+            bands: (0..BANDS).map(|_| DynamicBand::new(rate as u32)).collect(),
+            until_update: 0,
+            mid: [0.0; UPDATE_STEP],
+            scratch: [0.0; UPDATE_STEP],
+            activated: false
+        })
+    }
+
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::DInputL) => self.input_l.connect(data),
+            Some(PortIndex::DInputR) => self.input_r.connect(data),
+            Some(PortIndex::DOutputL) => self.output_l.connect(data),
+            Some(PortIndex::DOutputR) => self.output_r.connect(data),
+            None => {
+                let band = ((port - FIRST_BAND_PORT) / BAND_CONTROLS) as usize;
+                let control = (port - FIRST_BAND_PORT) % BAND_CONTROLS;
+                if band >= BANDS {
+                    return;
+                }
+                if control == BandControl::Gain as u32 {
+                    self.meters[band].connect(data);
+                } else {
+                    self.controls[band][control as usize].connect(data);
+                }
+            }
+        }
+    }
+
+    /* The first run starts at the settings then, with a silent envelope. */
+    fn activate(&mut self) {
+        self.activated = true;
+    }
+
+    fn run(&mut self, n_samples: u32) {
+        let n = n_samples as usize;
+
+        if self.activated {
+            for band in self.bands.iter_mut() {
+                band.cleanup();
+            }
+            self.until_update = 0;
+            self.activated = false;
+        }
+        for i in 0..BANDS {
+            let settings = self.settings(i);
+            self.bands[i].set(settings);
+        }
+
+        let input_l = self.input_l.as_slice(n);
+        let input_r = self.input_r.as_slice(n);
+        let output_l = self.output_l.as_mut_slice(n);
+        let output_r = self.output_r.as_mut_slice(n);
+        output_l.copy_from_slice(input_l);
+        output_r.copy_from_slice(input_r);
+
+        let mut pos = 0;
+        while pos < n {
+            if self.until_update == 0 {
+                for band in self.bands.iter_mut() {
+                    band.update();
+                }
+                self.until_update = UPDATE_STEP;
+            }
+            let len = self.until_update.min(n - pos);
+            let mid = &mut self.mid[..len];
+            for (m, (l, r)) in mid.iter_mut().zip(input_l[pos..pos + len].iter().zip(&input_r[pos..pos + len])) {
+                *m = (l + r) * 0.5;
+            }
+            for band in self.bands.iter_mut() {
+                band.detect(mid, &mut self.scratch[..len]);
+                band.filter(&mut output_l[pos..pos + len], &mut output_r[pos..pos + len]);
+            }
+            self.until_update -= len;
+            pos += len;
+        }
+
+        for (meter, band) in self.meters.iter_mut().zip(self.bands.iter()) {
+            meter.set(band.gain());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use tests::{instantiate_with_defaults, level_db, run_stereo, sine, split_runs};
+
+    /* The dynamic EQ with its defaults and band 2, a peak at 500 Hz, enabled. */
+    fn dynamic_eq(host: &TestHost) -> Instance<'_> {
+        let mut plugin = instantiate_with_defaults::<DynamicEQ>(host, "http://example.org/rustdynamiceq");
+        plugin.set_control(band_port(1, BandControl::Enable), 1.0);
+        plugin.activate();
+        plugin
+    }
+
+    /* The level of the second half of the left output in dB, with the gain meter of band 2. */
+    fn dynamic_level(plugin: &mut Instance, freq: f32, amplitude: f32) -> (f32, f32) {
+        let input: Vec<f32> = sine(freq, 9600).iter().map(|x| x * amplitude).collect();
+        run_stereo(plugin, (PortIndex::DInputL as u32, PortIndex::DInputR as u32), &input, &input);
+        (level_db(plugin.audio_output(PortIndex::DOutputL as u32), &input, 4800),
+            plugin.control(band_port(1, BandControl::Gain)))
+    }
+
+    #[test]
+    fn dynamic_eq_gain() {
+        let settings = BandSettings {
+            enabled: true,
+            ftype: FilterType::PEAK2,
+            freq: 1000.0,
+            q: 1.0,
+            threshold: -30.0,
+            ratio: 4.0,
+            attack: 0.005,
+            release: 0.1,
+            range: -12.0
+        };
+        assert_eq!(settings.gain(-40.0), 0.0);
+        assert_eq!(settings.gain(-22.0), -6.0);
+        assert_eq!(settings.gain(0.0), -12.0);
+        assert_eq!(BandSettings { range: 6.0, ..settings }.gain(-26.0), 3.0);
+        assert_eq!(BandSettings { ratio: 1.0, ..settings }.gain(0.0), 0.0);
+        assert_eq!(BandSettings { enabled: false, ..settings }.gain(0.0), 0.0);
+    }
+
+    #[test]
+    fn dynamic_eq_cuts_resonances_when_they_ring() {
+        let host = TestHost::new();
+        let mut plugin = dynamic_eq(&host);
+
+        // below the threshold of -24 dB nothing changes
+        let (level, gain) = dynamic_level(&mut plugin, 500.0, 0.01);
+        assert!(level.abs() < 0.01 && gain == 0.0, "{} dB, gain {} dB", level, gain);
+
+        // far above it the cut is the range of -12 dB
+        let (level, gain) = dynamic_level(&mut plugin, 500.0, 0.5);
+        assert_eq!(gain, -12.0);
+        assert!((level + 12.0).abs() < 0.5, "{} dB", level);
+
+        // and it is released when the resonance stops, 100 ms per 8.7 dB
+        let (_, gain) = dynamic_level(&mut plugin, 500.0, 0.0001);
+        assert!(gain > -12.0 && gain < 0.0, "gain {} dB", gain);
+        let (_, gain) = dynamic_level(&mut plugin, 500.0, 0.0001);
+        assert_eq!(gain, 0.0);
+
+        // loud, but away from the band
+        let (level, gain) = dynamic_level(&mut plugin, 8000.0, 0.5);
+        assert!(level.abs() < 0.1 && gain == 0.0, "{} dB, gain {} dB", level, gain);
+    }
+
+    #[test]
+    fn dynamic_eq_follows_a_fast_attack() {
+        let host = TestHost::new();
+        let mut plugin = dynamic_eq(&host);
+        plugin.set_control(band_port(1, BandControl::Attack), 0.1);
+
+        // every update of the gain gets through, the cut is there within 10 ms
+        let input: Vec<f32> = sine(500.0, 480).iter().map(|x| x * 0.5).collect();
+        run_stereo(&mut plugin, (PortIndex::DInputL as u32, PortIndex::DInputR as u32), &input, &input);
+        let level = level_db(plugin.audio_output(PortIndex::DOutputL as u32), &input, 288);
+        assert!(level < -10.0, "{} dB", level);
+    }
+
+    #[test]
+    fn dynamic_eq_block_split_is_bit_identical() {
+        let host = TestHost::new();
+        let input: Vec<f32> = sine(500.0, 4800).iter().zip(sine(3000.0, 4800))
+            .map(|(a, b)| 0.5 * a + 0.1 * b)
+            .collect();
+
+        let mut whole = dynamic_eq(&host);
+        whole.set_audio_input(PortIndex::DInputL as u32, &input);
+        whole.set_audio_input(PortIndex::DInputR as u32, &input);
+        whole.run(input.len());
+        let expected = whole.audio_output(PortIndex::DOutputL as u32).to_vec();
+
+        let mut split = dynamic_eq(&host);
+        let out = split_runs(&input, |i, o| {
+            split.set_audio_input(PortIndex::DInputL as u32, i);
+            split.set_audio_input(PortIndex::DInputR as u32, i);
+            split.run(i.len());
+            o.copy_from_slice(split.audio_output(PortIndex::DOutputL as u32));
+        });
+        let first = out.iter().zip(&expected).position(|(a, b)| a != b);
+        assert_eq!(first, None);
+    }
+}
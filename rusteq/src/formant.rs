@@ -0,0 +1,334 @@
+/*
+   The formant filter of ZynAddSubFX: band passes (`AnalogFilter` with
+   `FilterType::BPF2`) in parallel, one per formant, tuned to the formants
+   of a vowel.  Between two vowels the formants are morphed, and they glide
+   to new settings with the smoothing time.
+
+   The formants are updated every `UPDATE_STEP` samples, counted across
+   blocks, so the output does not depend on how the host splits the audio.
+   Every update crossfades the band passes over the step, so each of them
+   takes effect before the next one, and the amplitudes of the formants are
+   ramped over the step as well.
+*/
+
+use num::complex::Complex64;
+
+use analog_eq::*;
+use response::FrequencyResponse;
+
+
+pub const FORMANTS: usize = 5;
+
+// A, E, I, O, U
+pub const VOWELS: usize = 5;
+
+pub const UPDATE_STEP: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Formant {
+    pub freq: f64,
+    // linear
+    pub amp: f64,
+    pub q: f64
+}
+
+impl Formant {
+    /*
+       The factor for the output of its band pass.  The band pass of the
+       analog filter peaks at sqrt(q + 1), this puts the peak at `amp`.
+    */
+    fn level(&self) -> f64 {
+        self.amp / (self.q + 1.0).sqrt()
+    }
+}
+
+/*
+   Frequency in Hz, amplitude in dB and bandwidth in Hz of the formants of
+   a tenor voice, as in the formant table of the Csound manual.  Zyn ships
+   its vowels as presets, its built in defaults are random.
+*/
+const VOWEL_TABLE: [[(f64, f64, f64); FORMANTS]; VOWELS] = [
+    [(650.0, 0.0, 80.0), (1080.0, -6.0, 90.0), (2650.0, -7.0, 120.0), (2900.0, -8.0, 130.0), (3250.0, -22.0, 140.0)],
+    [(400.0, 0.0, 70.0), (1700.0, -14.0, 80.0), (2600.0, -12.0, 100.0), (3200.0, -14.0, 120.0), (3580.0, -20.0, 120.0)],
+    [(290.0, 0.0, 40.0), (1870.0, -15.0, 90.0), (2800.0, -18.0, 100.0), (3250.0, -20.0, 120.0), (3540.0, -30.0, 120.0)],
+    [(400.0, 0.0, 40.0), (800.0, -10.0, 80.0), (2600.0, -12.0, 100.0), (2800.0, -12.0, 120.0), (3000.0, -26.0, 120.0)],
+    [(350.0, 0.0, 40.0), (600.0, -20.0, 60.0), (2700.0, -17.0, 100.0), (2900.0, -14.0, 120.0), (3300.0, -26.0, 120.0)]
+];
+
+pub fn vowel_formant(vowel: usize, formant: usize) -> Formant {
+    let (freq, db, bandwidth) = VOWEL_TABLE[vowel][formant];
+    Formant { freq: freq, amp: db_2_rap(db), q: freq / bandwidth }
+}
+
+/*
+   How sharp the morph between two vowels is (the vowel clearness of Zyn at
+   its default): the position is bent so that the formants stay near the
+   vowels and move quickly in between.
+*/
+const CLEARNESS: f64 = 4.641_588_833_612_779;
+
+
+pub struct FormantFilter {
+    filters: Vec<AnalogFilter>,
+    current: [Formant; FORMANTS],
+    // what the band passes are set to
+    applied: [Formant; FORMANTS],
+    // the levels at the start of the current step
+    old_level: [f64; FORMANTS],
+    // the settings the formants glide to
+    position: f64,
+    shift: f64,
+    q_factor: f64,
+    // per step, 1 jumps to the target at once
+    smoothing: f64,
+    // samples until the next update
+    until_update: usize,
+    first_time: bool,
+    samplerate_f: f64,
+    input: [f32; UPDATE_STEP],
+    band: [f32; UPDATE_STEP]
+}
+
+impl FormantFilter {
+    pub fn new(srate: u32) -> FormantFilter {
+        let neutral = Formant { freq: 1000.0, amp: 1.0, q: 2.0 };
+        FormantFilter {
+            filters: (0..FORMANTS)
+                .map(|_| {
+                    let mut filter = AnalogFilter::new(&FilterType::BPF2, 1000.0, 2.0, 0, srate, UPDATE_STEP);
+                    filter.set_fade_len(UPDATE_STEP);
+                    filter
+                })
+                .collect(),
+            current: [neutral; FORMANTS],
+            applied: [neutral; FORMANTS],
+            old_level: [1.0; FORMANTS],
+            position: 0.0,
+            shift: 0.0,
+            q_factor: 1.0,
+            smoothing: 1.0,
+            until_update: 0,
+            first_time: true,
+            samplerate_f: srate as f64,
+            input: [0.0; UPDATE_STEP],
+            band: [0.0; UPDATE_STEP]
+        }
+    }
+
+    /*
+       The vowel at `position`, from 0 (A) to 4 (U), in between the formants
+       are morphed.  The formants are shifted by `shift` semitones and their
+       Q is multiplied by `q_factor`.
+    */
+    pub fn set_target(&mut self, position: f32, shift: f32, q_factor: f32) -> () {
+        self.position = (position as f64).max(0.0).min((VOWELS - 1) as f64);
+        self.shift = shift as f64;
+        self.q_factor = q_factor as f64;
+    }
+
+    /* The time it takes to get about two thirds of the way to a new target. */
+    pub fn set_smoothing(&mut self, seconds: f64) -> () {
+        let steps = seconds * self.samplerate_f / UPDATE_STEP as f64;
+        self.smoothing = if steps > 0.0 { 1.0 - (-1.0 / steps).exp() } else { 1.0 };
+    }
+
+    /* Silence the filter, the next block starts at the target. */
+    pub fn cleanup(&mut self) -> () {
+        for filter in self.filters.iter_mut() {
+            filter.cleanup();
+        }
+        self.until_update = 0;
+        self.first_time = true;
+    }
+
+    /* The formants for the current target, without smoothing. */
+    pub fn target(&self) -> [Formant; FORMANTS] {
+        let first = (self.position.floor() as usize).min(VOWELS - 2);
+        let x = self.position - first as f64;
+        let x = ((x * 2.0 - 1.0) * CLEARNESS).atan() / CLEARNESS.atan() * 0.5 + 0.5;
+        let ratio = 2f64.powf(self.shift / 12.0);
+
+        let mut formants = [Formant { freq: 0.0, amp: 0.0, q: 0.0 }; FORMANTS];
+        for (i, formant) in formants.iter_mut().enumerate() {
+            let a = vowel_formant(first, i);
+            let b = vowel_formant(first + 1, i);
+            *formant = Formant {
+                freq: (a.freq * (1.0 - x) + b.freq * x) * ratio,
+                amp: a.amp * (1.0 - x) + b.amp * x,
+                q: (a.q * (1.0 - x) + b.q * x) * self.q_factor
+            };
+        }
+        formants
+    }
+
+    fn update(&mut self) -> () {
+        let target = self.target();
+        let k = if self.first_time { 1.0 } else { self.smoothing };
+
+        for (i, (current, target)) in self.current.iter_mut().zip(target.iter()).enumerate() {
+            self.old_level[i] = if self.first_time { target.level() } else { current.level() };
+            let next = Formant {
+                freq: current.freq + (target.freq - current.freq) * k,
+                amp: current.amp + (target.amp - current.amp) * k,
+                q: current.q + (target.q - current.q) * k
+            };
+            *current = next;
+
+            // the band passes crossfade on every change, leave them alone
+            // while there is nothing audible to do
+            let applied = &mut self.applied[i];
+            let moved = (next.freq - applied.freq).abs() > 0.001 * applied.freq
+                || (next.q - applied.q).abs() > 0.001 * applied.q;
+            if moved || self.first_time {
+                *applied = next;
+                self.filters[i].set_values(FilterType::BPF2.to_u32() as f32, next.freq as f32,
+                    next.q as f32, 0.0, 0.0);
+                if self.first_time {
+                    self.filters[i].cleanup();
+                }
+            }
+        }
+        self.first_time = false;
+    }
+
+    pub fn filterout(&mut self, input: &[f32], output: &mut [f32]) -> () {
+        output.copy_from_slice(input);
+        self.filter(output);
+    }
+
+    /* Filter a block of any length in place. */
+    pub fn filter(&mut self, smp: &mut [f32]) -> () {
+        let mut pos = 0;
+        while pos < smp.len() {
+            if self.until_update == 0 {
+                self.update();
+                self.until_update = UPDATE_STEP;
+            }
+            let len = self.until_update.min(smp.len() - pos);
+            // where this part starts within the step
+            let offset = UPDATE_STEP - self.until_update;
+            self.filter_part(&mut smp[pos..pos + len], offset);
+            self.until_update -= len;
+            pos += len;
+        }
+    }
+
+    fn filter_part(&mut self, smp: &mut [f32], offset: usize) -> () {
+        let input = &mut self.input[..smp.len()];
+        let band = &mut self.band[..smp.len()];
+        input.copy_from_slice(smp);
+        for s in smp.iter_mut() {
+            *s = 0.0;
+        }
+
+        for (i, filter) in self.filters.iter_mut().enumerate() {
+            band.copy_from_slice(input);
+            filter.filter(band);
+            let (from, to) = (self.old_level[i], self.current[i].level());
+            for (n, (s, b)) in smp.iter_mut().zip(band.iter()).enumerate() {
+                let x = (offset + n + 1) as f64 / UPDATE_STEP as f64;
+                *s += (*b as f64 * (from + (to - from) * x)) as f32;
+            }
+        }
+    }
+
+    /* The response of the formants as they are now, summed up. */
+    pub fn response(&self, freq: f64) -> Complex64 {
+        self.filters.iter().zip(self.current.iter())
+            .fold(Complex64::new(0.0, 0.0), |h, (filter, formant)| h + filter.response(freq) * formant.level())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::{level_db, sine, split_runs, RATE};
+
+    /* Level of a sine at `freq` through `f` after it settled, in dB. */
+    fn formant_level(f: &mut FormantFilter, freq: f32) -> f32 {
+        let input = sine(freq, 9600);
+        let mut out = vec![0.0; input.len()];
+        f.filterout(&input, &mut out);
+        level_db(&out, &input, 4800)
+    }
+
+    fn vowel(position: f32, shift: f32) -> FormantFilter {
+        let mut f = FormantFilter::new(RATE as u32);
+        f.set_target(position, shift, 1.0);
+        f
+    }
+
+    #[test]
+    fn vowels_have_their_formants() {
+        for v in 0..VOWELS {
+            for i in 0..FORMANTS {
+                let mut f = vowel(v as f32, 0.0);
+                let freq = vowel_formant(v, i).freq as f32;
+                let measured = formant_level(&mut f, freq);
+                let predicted = 20.0 * f.response(freq as f64).norm().log10() as f32;
+                assert!((measured - predicted).abs() < 0.1, "vowel {} formant {}: {} dB, predicted {} dB",
+                    v, i, measured, predicted);
+            }
+        }
+
+        // the first formants of A and I
+        assert!(formant_level(&mut vowel(0.0, 0.0), 650.0) > formant_level(&mut vowel(2.0, 0.0), 650.0) + 10.0);
+        assert!(formant_level(&mut vowel(2.0, 0.0), 290.0) > formant_level(&mut vowel(0.0, 0.0), 290.0) + 10.0);
+        // an octave up
+        assert!(formant_level(&mut vowel(0.0, 12.0), 1300.0) > formant_level(&mut vowel(0.0, 0.0), 1300.0) + 6.0);
+    }
+
+    #[test]
+    fn vowels_morph() {
+        let f1 = |position| {
+            let mut f = vowel(position, 0.0);
+            formant_level(&mut f, 1.0);
+            f.target()[0].freq
+        };
+        assert_eq!(f1(0.0), 650.0);
+        assert_eq!(f1(1.0), 400.0);
+        assert!((f1(0.5) - 525.0).abs() < 1e-9);
+        // stays close to the vowels for longer
+        assert!(f1(0.25) > 600.0);
+    }
+
+    #[test]
+    fn formant_smoothing() {
+        let after_change = |smoothing: f64| {
+            let mut f = vowel(0.0, 0.0);
+            f.set_smoothing(smoothing);
+            formant_level(&mut f, 290.0);
+            f.set_target(2.0, 0.0, 1.0);
+            let mut out = vec![0.0; 480];
+            f.filterout(&sine(290.0, 480), &mut out);
+            20.0 * f.response(290.0).norm().log10()
+        };
+        assert!(after_change(0.0).abs() < 0.5);
+        assert!(after_change(0.2) < -10.0);
+    }
+
+    #[test]
+    fn formant_block_split_is_bit_identical() {
+        let input: Vec<f32> = sine(440.0, 4000).iter().zip(sine(2000.0, 4000))
+            .map(|(a, b)| a + 0.5 * b)
+            .collect();
+        let filter = || {
+            let mut f = vowel(1.5, 3.0);
+            f.set_smoothing(0.02);
+            f
+        };
+
+        let mut whole = filter();
+        let mut expected = vec![0.0; input.len()];
+        whole.filterout(&input[..2000], &mut expected[..2000]);
+        whole.set_target(3.0, 0.0, 2.0);
+        whole.filterout(&input[2000..], &mut expected[2000..]);
+
+        let mut split = filter();
+        let mut out = split_runs(&input[..2000], |i, o| split.filterout(i, o));
+        split.set_target(3.0, 0.0, 2.0);
+        out.extend(split_runs(&input[2000..], |i, o| split.filterout(i, o)));
+        let first = out.iter().zip(&expected).position(|(a, b)| a != b);
+        assert_eq!(first, None);
+    }
+}
@@ -1,7 +1,690 @@
 #[cfg(test)]
 mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use curve::*;
+    use response::*;
+
+    // the plugin is exported by the bundle, the tests need their own entry point
+    lv2_descriptors!(EQ, ParaEQ, VowelFilter, Crossover, DynamicEQ);
+    use std::f32::consts::PI;
+
+    pub const RATE: f64 = 48000.0;
+
+    pub fn eq<'h>(host: &'h TestHost, ftype: FilterType, freq: f32) -> Instance<'h> {
+        let mut eq = host.instantiate(lv2_descriptor, "http://example.org/rusteq", RATE).unwrap();
+        eq.set_control(PortIndex::EQType as u32, ftype.to_u32() as f32);
+        eq.set_control(PortIndex::EQFreq as u32, freq);
+        eq.set_control(PortIndex::EQQ as u32, 0.707);
+        eq.set_control(PortIndex::EQStages as u32, 1.0);
+        eq.set_control(PortIndex::EQGain as u32, 0.0);
+        eq.set_control(PortIndex::EQMode as u32, 0.0);
+        eq.set_control(PortIndex::EQCategory as u32, 0.0);
+        eq.set_control(PortIndex::EQDesign as u32, 0.0);
+        eq.set_control(PortIndex::EQSlope as u32, 0.0);
+        eq.set_control(PortIndex::EQLinear as u32, 0.0);
+        eq.set_control(PortIndex::EQLatency as u32, -1.0);
+        eq.connect_audio_output(PortIndex::EQOutputL as u32);
+        eq.connect_audio_output(PortIndex::EQOutputR as u32);
+        eq.activate();
+        eq
+    }
+
+    pub fn sine(freq: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * PI * freq * i as f32 / RATE as f32).sin()).collect()
+    }
+
+    pub fn rms(data: &[f32]) -> f32 {
+        (data.iter().map(|x| x * x).sum::<f32>() / data.len() as f32).sqrt()
+    }
+
+    /* Level of `out` relative to `input` once `settle` samples went by, in dB */
+    pub fn level_db(out: &[f32], input: &[f32], settle: usize) -> f32 {
+        20.0 * (rms(&out[settle..]) / rms(&input[settle..])).log10()
+    }
+
+    /* Runs `left` and `right` through the input ports `inputs` of the plugin */
+    pub fn run_stereo(plugin: &mut Instance, inputs: (u32, u32), left: &[f32], right: &[f32]) {
+        plugin.set_audio_input(inputs.0, left);
+        plugin.set_audio_input(inputs.1, right);
+        plugin.run(left.len());
+    }
+
+    /* Level of the second half of the output relative to the input in dB */
+    fn level(eq: &mut Instance, freq: f32) -> f32 {
+        let input = sine(freq, 4800);
+        run_stereo(eq, (PortIndex::EQInputL as u32, PortIndex::EQInputR as u32), &input, &input);
+        level_db(eq.audio_output(PortIndex::EQOutputL as u32), &input, 2400)
+    }
+
+    #[test]
+    fn descriptor() {
+        let host = TestHost::new();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rusteq", RATE).is_some());
+        assert!(lv2_descriptor(5).is_null());
+    }
+
+    #[test]
+    fn lowpass() {
+        let host = TestHost::new();
+
+        assert!(level(&mut eq(&host, FilterType::LPF2, 1000.0), 100.0).abs() < 1.0);
+        assert!(level(&mut eq(&host, FilterType::LPF2, 1000.0), 10000.0) < -30.0);
+    }
+
+    #[test]
+    fn highpass() {
+        let host = TestHost::new();
+
+        assert!(level(&mut eq(&host, FilterType::HPF2, 1000.0), 100.0) < -30.0);
+        assert!(level(&mut eq(&host, FilterType::HPF2, 1000.0), 10000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn db_2_rap_is_the_inverse_of_rap_2_db() {
+        assert!((db_2_rap(20.0) - 10.0).abs() < 1e-9);
+        assert!((db_2_rap(-6.0) - 0.501187).abs() < 1e-6);
+        for &db in &[-24.0, -3.0, 0.0, 6.0, 12.0] {
+            assert!((rap_2_db(db_2_rap(db)) - db).abs() < 1e-9, "{} dB", db);
+        }
+    }
+
+    #[test]
+    fn peaks_and_shelves_reach_their_gain() {
+        let host = TestHost::new();
+        for &(ftype, freq, gain) in &[(FilterType::PEAK2, 1000.0, 6.0), (FilterType::PEAK2, 1000.0, -9.0),
+            (FilterType::LoShelf, 30.0, 6.0), (FilterType::HiShelf, 16000.0, -6.0)] {
+
+            let mut eq = eq(&host, ftype, 1000.0);
+            eq.set_control(PortIndex::EQGain as u32, gain);
+            let level = level(&mut eq, freq);
+            assert!((level - gain).abs() < 0.1, "{:?} at {} Hz: {} dB", ftype, freq, level);
+        }
+    }
+
+    #[test]
+    fn peak_with_zero_gain_is_transparent() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::PEAK2, 1000.0);
+
+        let input = sine(1000.0, 512);
+        eq.set_audio_input(PortIndex::EQInputL as u32, &input);
+        eq.set_audio_input(PortIndex::EQInputR as u32, &input);
+        eq.run(input.len());
+
+        for (o, i) in eq.audio_output(PortIndex::EQOutputL as u32).iter().zip(&input) {
+            assert!((o - i).abs() < 1e-4);
+        }
+    }
+
+    /*
+       Output for `input` after a jump of the frequency, which makes the
+       filter interpolate, run in blocks of `block` samples.
+    */
+    fn after_jump(host: &TestHost, input: &[f32], block: usize) -> Vec<f32> {
+        let mut eq = eq(host, FilterType::LPF2, 100.0);
+        eq.set_audio_input(PortIndex::EQInputL as u32, &sine(100.0, 64));
+        eq.set_audio_input(PortIndex::EQInputR as u32, &sine(100.0, 64));
+        eq.run(64);
+
+        eq.set_control(PortIndex::EQFreq as u32, 5000.0);
+        let mut out = Vec::new();
+        for chunk in input.chunks(block) {
+            eq.set_audio_input(PortIndex::EQInputL as u32, chunk);
+            eq.set_audio_input(PortIndex::EQInputR as u32, chunk);
+            eq.run(chunk.len());
+            out.extend_from_slice(eq.audio_output(PortIndex::EQOutputL as u32));
+        }
+        out
+    }
+
+    #[test]
+    fn blocks_longer_than_8192() {
+        let out = after_jump(&TestHost::new(), &sine(1000.0, 10000), 10000);
+        assert!(out.iter().all(|x| x.is_finite()));
+        assert!(rms(&out[8192..]) > 0.5);
+    }
+
+    #[test]
+    fn blocks_longer_than_announced() {
+        let input = sine(1000.0, 2048);
+        let host = TestHost::with_max_block_length(256);
+
+        // as if the host had kept its word
+        assert_eq!(after_jump(&host, &input, 2048), after_jump(&host, &input, 256));
+    }
+
+    // block sizes a host splitting at events might use
+    const SPLITS: &[usize] = &[1, 7, 8, 13, 2, 64, 3, 5, 100, 1, 9];
+
+    pub fn split_runs(input: &[f32], mut process: impl FnMut(&[f32], &mut [f32])) -> Vec<f32> {
+        let mut out = vec![0.0; input.len()];
+        let mut offset = 0;
+        for split in SPLITS.iter().cycle() {
+            if offset == input.len() {
+                break;
+            }
+            let end = (offset + split).min(input.len());
+            process(&input[offset..end], &mut out[offset..end]);
+            offset = end;
+        }
+        out
+    }
+
+    #[test]
+    fn any_block_split_is_bit_identical() {
+        let input: Vec<f32> = sine(440.0, 1000).iter().zip(sine(7000.0, 1000))
+            .map(|(a, b)| a + 0.5 * b)
+            .collect();
+
+        for ftype in 0..14 {
+            for stages in 0..3 {
+                let filter = || {
+                    let mut f = AnalogFilter::new(&FilterType::from_u32(ftype), 1000.0, 2.0, stages,
+                        RATE as u32, DEFAULT_MAX_BLOCK);
+                    f.set_gain(6.0);
+                    f
+                };
+
+                let mut whole = filter();
+                let mut expected = vec![0.0; input.len()];
+                whole.filterout(&input, &mut expected);
+
+                let mut split = filter();
+                assert_eq!(split_runs(&input, |i, o| split.filterout(i, o)), expected,
+                    "type {} with {} stages", ftype, stages);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_host_blocks() {
+        let host = TestHost::new();
+        let input = sine(1000.0, 300);
+
+        let mut eq1 = eq(&host, FilterType::PEAK2, 1000.0);
+        eq1.set_control(PortIndex::EQGain as u32, 10.0);
+        eq1.set_audio_input(PortIndex::EQInputL as u32, &input);
+        eq1.set_audio_input(PortIndex::EQInputR as u32, &input);
+        eq1.run(input.len());
+        let expected = eq1.audio_output(PortIndex::EQOutputL as u32).to_vec();
+
+        let mut eq2 = eq(&host, FilterType::PEAK2, 1000.0);
+        eq2.set_control(PortIndex::EQGain as u32, 10.0);
+        let out = split_runs(&input, |i, o| {
+            eq2.set_audio_input(PortIndex::EQInputL as u32, i);
+            eq2.set_audio_input(PortIndex::EQInputR as u32, i);
+            eq2.run(i.len());
+            o.copy_from_slice(eq2.audio_output(PortIndex::EQOutputL as u32));
+        });
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn stages_cascade() {
+        let input = sine(2500.0, 9600);
+        let mut single = 0.0;
+
+        for stages in 0..5 {
+            let mut filter = AnalogFilter::new(&FilterType::LPF2, 1000.0, 0.707, stages,
+                RATE as u32, DEFAULT_MAX_BLOCK);
+            let mut out = vec![0.0; input.len()];
+            filter.filterout(&input, &mut out);
+
+            let measured = level_db(&out, &input, 4800);
+            let predicted = rap_2_db(filter.h(2500.0)) as f32;
+            if stages == 0 {
+                single = measured;
+            }
+
+            assert!(measured < -10.0 * (stages + 1) as f32);
+            assert!((measured - predicted).abs() < 0.5, "{} stages: {} dB, h says {} dB",
+                stages, measured, predicted);
+            assert!((measured - single * (stages + 1) as f32).abs() < 0.5, "{} stages: {} dB",
+                stages, measured);
+        }
+    }
+
+    #[test]
+    fn crossfade_is_independent_of_block_size() {
+        let input = sine(1000.0, 2000);
+        let filter = || AnalogFilter::new(&FilterType::LPF2, 100.0, 0.707, 1, RATE as u32, DEFAULT_MAX_BLOCK);
+
+        let mut whole = filter();
+        let mut expected = vec![0.0; input.len()];
+        whole.filterout(&input[..100], &mut expected[..100]);
+        whole.set_values(3.0, 5000.0, 2.0, 2.0, 0.0);
+        whole.filterout(&input[100..], &mut expected[100..]);
+
+        let mut split = filter();
+        let mut out = split_runs(&input[..100], |i, o| split.filterout(i, o));
+        split.set_values(3.0, 5000.0, 2.0, 2.0, 0.0);
+        out.extend(split_runs(&input[100..], |i, o| split.filterout(i, o)));
+        assert_eq!(out, expected);
+    }
+
+    pub fn largest_step(data: &[f32]) -> f32 {
+        data.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max)
+    }
+
+    /* Runs a 1 kHz sine in blocks of 32 samples and changes a control after 320. */
+    fn change_control(ftype: FilterType, freq: f32, port: PortIndex, value: f32) -> Vec<f32> {
+        let host = TestHost::new();
+        let mut eq = eq(&host, ftype, freq);
+        let input = sine(1000.0, 2048);
+
+        let mut out = Vec::new();
+        for (n, chunk) in input.chunks(32).enumerate() {
+            if n == 10 {
+                eq.set_control(port as u32, value);
+            }
+            eq.set_audio_input(PortIndex::EQInputL as u32, chunk);
+            eq.set_audio_input(PortIndex::EQInputR as u32, chunk);
+            eq.run(chunk.len());
+            out.extend_from_slice(eq.audio_output(PortIndex::EQOutputL as u32));
+        }
+        out
+    }
+
+    #[test]
+    fn no_clicks_on_changes() {
+        // a 1 kHz sine of amplitude 1 moves by at most 0.13 per sample
+        let changes = [
+            ("frequency jump", change_control(FilterType::LPF2, 100.0, PortIndex::EQFreq, 5000.0)),
+            ("type change", change_control(FilterType::LPF2, 1000.0, PortIndex::EQType, 3.0)),
+            ("Q change", change_control(FilterType::HPF2, 200.0, PortIndex::EQQ, 4.0)),
+            ("gain change", change_control(FilterType::LoShelf, 2000.0, PortIndex::EQGain, -20.0)),
+            ("category switch", change_control(FilterType::LPF2, 2000.0, PortIndex::EQCategory, 1.0)),
+            ("slope change", change_control(FilterType::LPF2, 2000.0, PortIndex::EQSlope, 12.0))
+        ];
+        for &(change, ref out) in &changes {
+            assert!(largest_step(&out[300..]) < 0.2, "{}: step of {}", change, largest_step(&out[300..]));
+        }
+    }
+
+    /*
+       An instance of the plugin `P` at `uri` with all controls at their
+       defaults and all outputs connected.
+    */
+    pub fn instantiate_with_defaults<'h, P: Plugin>(host: &'h TestHost, uri: &str) -> Instance<'h> {
+        let mut plugin = host.instantiate(lv2_descriptor, uri, RATE).unwrap();
+        for port in P::INFO.ports {
+            match port.range {
+                Some((default, _, _)) => plugin.set_control(port.index, default),
+                None if port.kind == PortKind::AudioOut => plugin.connect_audio_output(port.index),
+                None if port.kind == PortKind::ControlOut => plugin.set_control(port.index, 0.0),
+                None => ()
+            }
+        }
+        plugin
+    }
+
+    /* Runs l and r through a 500 Hz low pass in `mode`. */
+    fn in_mode(mode: ChannelMode, l: &[f32], r: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::LPF2, 500.0);
+        eq.set_control(PortIndex::EQMode as u32, mode.to_u32() as f32);
+        eq.set_audio_input(PortIndex::EQInputL as u32, l);
+        eq.set_audio_input(PortIndex::EQInputR as u32, r);
+        eq.run(l.len());
+        (eq.audio_output(PortIndex::EQOutputL as u32).to_vec(), eq.audio_output(PortIndex::EQOutputR as u32).to_vec())
+    }
+
+    fn same(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
     #[test]
-    fn it_works() {
+    fn channel_modes() {
+        let x = sine(5000.0, 4800);
+        let minus_x: Vec<f32> = x.iter().map(|v| -v).collect();
+        let filtered = |out: &[f32]| rms(&out[2400..]) < 0.05;
+
+        let (l, r) = in_mode(ChannelMode::Left, &x, &x);
+        assert!(filtered(&l) && r == x);
+        let (l, r) = in_mode(ChannelMode::Right, &x, &x);
+        assert!(l == x && filtered(&r));
+
+        // a mono signal is all mid, l = -r all side
+        let (l, r) = in_mode(ChannelMode::Mid, &x, &x);
+        assert!(filtered(&l) && filtered(&r));
+        let (l, r) = in_mode(ChannelMode::Mid, &x, &minus_x);
+        assert!(same(&l, &x) && same(&r, &minus_x));
+        let (l, r) = in_mode(ChannelMode::Side, &x, &minus_x);
+        assert!(filtered(&l) && filtered(&r));
+        let (l, r) = in_mode(ChannelMode::Side, &x, &x);
+        assert!(same(&l, &x) && same(&r, &x));
+    }
+
+    #[test]
+    fn channel_mode_switches_are_click_free() {
+        // 50 Hz hardly passes the 1 kHz high pass and changes by 0.0065 per sample at most
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::HPF2, 1000.0);
+        let x = sine(50.0, 720 * 8);
+        let modes = [ChannelMode::Stereo, ChannelMode::Left, ChannelMode::Stereo, ChannelMode::Mid,
+            ChannelMode::Right, ChannelMode::Side, ChannelMode::Left, ChannelMode::Stereo];
+
+        let (mut out_l, mut out_r) = (Vec::new(), Vec::new());
+        for (block, mode) in x.chunks(720).zip(modes.iter()) {
+            eq.set_control(PortIndex::EQMode as u32, mode.to_u32() as f32);
+            eq.set_audio_input(PortIndex::EQInputL as u32, block);
+            eq.set_audio_input(PortIndex::EQInputR as u32, block);
+            eq.run(block.len());
+            out_l.extend_from_slice(eq.audio_output(PortIndex::EQOutputL as u32));
+            out_r.extend_from_slice(eq.audio_output(PortIndex::EQOutputR as u32));
+        }
+        for out in [&out_l, &out_r].iter() {
+            let step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+            assert!(step < 0.02, "{}", step);
+        }
+    }
+
+    pub fn filter(ftype: FilterType, freq: f32, q: f32, stages: u8, gain_db: f32) -> AnalogFilter {
+        let mut f = AnalogFilter::new(&ftype, freq, q, stages, RATE as u32, DEFAULT_MAX_BLOCK);
+        f.set_gain(gain_db);
+        f
+    }
+
+    #[test]
+    fn state_variable_response_matches_audio() {
+        for ftype in 2..14 {
+            let mut f = SVFilter::new(&FilterType::LPF1, 1000.0, 1.0, 0, RATE as u32, DEFAULT_MAX_BLOCK);
+            f.set_values(ftype as f32, 1000.0, 2.0, 1.0, 6.0);
+            for &freq in &[200.0, 1000.0, 3000.0] {
+                let input = sine(freq, 9600);
+                let mut out = vec![0.0; input.len()];
+                f.filterout(&input, &mut out);
+
+                let measured = level_db(&out, &input, 4800);
+                let predicted = f.magnitude_db(freq as f64) as f32;
+                let close = if predicted < -60.0 { measured < -60.0 } else { (measured - predicted).abs() < 0.1 };
+                assert!(close, "type {} at {} Hz: {} dB, predicted {} dB", ftype, freq, measured, predicted);
+            }
+        }
+    }
+
+    #[test]
+    fn state_variable_category() {
+        let host = TestHost::new();
+        let sv = |ftype, freq| {
+            let mut eq = eq(&host, ftype, 1000.0);
+            eq.set_control(PortIndex::EQCategory as u32, 1.0);
+            level(&mut eq, freq)
+        };
+
+        // sqrt(q) scales the input of both stages
+        let pass = sv(FilterType::LPF2, 100.0);
+        assert!(pass < -2.0 && pass > -3.0, "{} dB", pass);
+        assert!(sv(FilterType::LPF2, 10000.0) < -30.0);
+        assert!(sv(FilterType::HPF2, 100.0) < -30.0);
+        // tuned like in ZynAddSubFX, see SVFilter
+        assert!(sv(FilterType::NOTCH2, 1000.0 * 2.0 / PI) < -30.0);
+        // no peak in the state variable filter
+        assert!(sv(FilterType::PEAK2, 1000.0).abs() < 0.01);
+
+        let mut analog = eq(&host, FilterType::LPF2, 1000.0);
+        assert!(level(&mut analog, 100.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn cookbook_types() {
+        let allpass = [filter(FilterType::APF1, 1000.0, 2.0, 0, 6.0), filter(FilterType::APF2, 1000.0, 2.0, 0, 6.0)];
+        for f in allpass.iter() {
+            for &freq in &[20.0, 1000.0, 20000.0] {
+                assert!(f.magnitude_db(freq).abs() < 1e-6);
+            }
+        }
+        // a quarter and half a turn at the frequency
+        assert!((allpass[0].phase(1000.0) + PI as f64 / 2.0).abs() < 1e-3);
+        assert!((allpass[1].phase(1000.0).abs() - PI as f64).abs() < 1e-3);
+
+        // a shelf of the gain, lowered by half of it
+        let tilt = filter(FilterType::Tilt, 1000.0, 0.707, 0, 6.0);
+        assert!((tilt.magnitude_db(10.0) + 3.0).abs() < 0.1);
+        assert!((tilt.magnitude_db(20000.0) - 3.0).abs() < 0.1);
+        assert!(tilt.magnitude_db(1000.0).abs() < 0.1);
+
+        let peak = filter(FilterType::BPFPeak, 1000.0, 2.0, 0, 6.0);
+        assert!(peak.magnitude_db(1000.0).abs() < 0.01);
+        assert!(peak.magnitude_db(100.0) < -20.0);
+        let gain = filter(FilterType::BPFGain, 1000.0, 2.0, 0, 6.0);
+        assert!((gain.magnitude_db(1000.0) - 6.0).abs() < 0.01);
+        assert!((gain.magnitude_db(100.0) - peak.magnitude_db(100.0) - 6.0).abs() < 0.01);
+    }
+
+    fn matched(ftype: FilterType, freq: f32, q: f32, stages: u8, gain_db: f32) -> AnalogFilter {
+        let mut f = filter(ftype, freq, q, stages, gain_db);
+        f.set_design(Design::Matched);
+        f.cleanup();
+        f
+    }
+
+    #[test]
+    fn matched_design() {
+        // well below Nyquist both designs are close to the prototype
+        for ftype in 0..14 {
+            let bilinear = filter(FilterType::from_u32(ftype), 1000.0, 2.0, 0, 6.0);
+            let matched = matched(FilterType::from_u32(ftype), 1000.0, 2.0, 0, 6.0);
+            for &freq in &[100.0, 1000.0, 3000.0] {
+                let (b, m) = (bilinear.magnitude_db(freq), matched.magnitude_db(freq));
+                let close = if b < -60.0 { m < -40.0 } else { (b - m).abs() < 0.5 };
+                assert!(close, "type {} at {} Hz: {} dB, bilinear {} dB", ftype, freq, m, b);
+            }
+        }
+
+        // the prototype of the peak, with A of half the gain and the Q
+        // tripled like ZynAddSubFX does
+        let (a, q) = (db_2_rap(6.0), 3.0);
+        let analog = |x: f64| {
+            let num = (1.0 - x * x).powi(2) + (a * x / q).powi(2);
+            let den = (1.0 - x * x).powi(2) + (x / (a * q)).powi(2);
+            10.0 * (num / den).log10()
+        };
+        let bilinear = filter(FilterType::PEAK2, 16000.0, 1.0, 0, 12.0);
+        let peak = matched(FilterType::PEAK2, 16000.0, 1.0, 0, 12.0);
+        assert!((peak.magnitude_db(16000.0) - 12.0).abs() < 0.01);
+        assert!((peak.magnitude_db(24000.0) - analog(1.5)).abs() < 0.01);
+        // cramped down to nothing at Nyquist
+        assert!(bilinear.magnitude_db(24000.0).abs() < 0.01);
+        assert!((peak.magnitude_db(20000.0) - analog(1.25)).abs() < 1.0);
+        assert!((bilinear.magnitude_db(20000.0) - analog(1.25)).abs() > 2.0);
+
+        // the bilinear design gives up this close to Nyquist
+        let bilinear = filter(FilterType::HiShelf, 23800.0, 0.707, 0, 6.0);
+        let shelf = matched(FilterType::HiShelf, 23800.0, 0.707, 0, 6.0);
+        assert!(bilinear.magnitude_db(20000.0).abs() < 0.01);
+        assert!(shelf.magnitude_db(20000.0) > 1.0);
+        assert!(shelf.magnitude_db(100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn design_control() {
+        let host = TestHost::new();
+        let level_at = |design| {
+            let mut eq = eq(&host, FilterType::PEAK2, 16000.0);
+            eq.set_control(PortIndex::EQGain as u32, 12.0);
+            eq.set_control(PortIndex::EQDesign as u32, design);
+            level(&mut eq, 22000.0)
+        };
+        assert!(level_at(1.0) > level_at(0.0) + 3.0);
+    }
+
+    fn sloped(ftype: FilterType, slope: Slope) -> AnalogFilter {
+        let mut f = filter(ftype, 1000.0, 0.707, 0, 0.0);
+        f.set_slope(slope);
+        f.cleanup();
+        f
+    }
+
+    #[test]
+    fn butterworth_slopes() {
+        for order in 1..9 {
+            let low = sloped(FilterType::LPF2, Slope::Butterworth(order));
+            let high = sloped(FilterType::HPF2, Slope::Butterworth(order));
+            assert_eq!(Slope::from_u32(Slope::Butterworth(order).to_u32()), Slope::Butterworth(order));
+            assert!((low.magnitude_db(1000.0) + 3.01).abs() < 0.05, "order {}", order);
+            assert!((high.magnitude_db(1000.0) + 3.01).abs() < 0.05, "order {}", order);
+            assert!(low.magnitude_db(20.0).abs() < 0.01 && high.magnitude_db(20000.0).abs() < 0.01);
+
+            // the analog response, on the frequency axis of the bilinear transform
+            let warp = |freq: f64| (PI as f64 * freq / RATE).tan() / (PI as f64 * 1000.0 / RATE).tan();
+            for &freq in &[100.0, 2000.0, 10000.0] {
+                let expected = -10.0 * (1.0 + warp(freq).powi(2 * order as i32)).log10();
+                assert!((low.magnitude_db(freq) - expected).abs() < 1e-6, "order {} at {} Hz", order, freq);
+            }
+        }
+    }
+
+    #[test]
+    fn linkwitz_riley_sums_flat() {
+        for &order in &[2, 4, 8] {
+            let slope = Slope::LinkwitzRiley(order);
+            assert_eq!(Slope::from_u32(slope.to_u32()), slope);
+            let low = sloped(FilterType::LPF2, slope);
+            let high = sloped(FilterType::HPF2, slope);
+            assert!((low.magnitude_db(1000.0) + 6.02).abs() < 0.05);
+            assert!((high.magnitude_db(1000.0) + 6.02).abs() < 0.05);
+            // the second order high pass is out of phase
+            let sign = if order == 2 { -1.0 } else { 1.0 };
+            for &freq in &[20.0, 300.0, 1000.0, 3000.0, 20000.0] {
+                let sum = low.response(freq) + high.response(freq) * sign;
+                assert!((sum.norm() - 1.0).abs() < 1e-6, "order {} at {} Hz", order, freq);
+            }
+        }
+    }
+
+    #[test]
+    fn chebyshev_ripples() {
+        for &order in &[2, 4, 6, 8] {
+            let slope = Slope::Chebyshev(order);
+            assert_eq!(Slope::from_u32(slope.to_u32()), slope);
+            let low = sloped(FilterType::LPF2, slope);
+            let passband: Vec<f64> = (1..100).map(|i| low.magnitude_db(10.0 * i as f64)).collect();
+            assert!(passband.iter().all(|&db| db < 0.01 && db > -CHEBYSHEV_RIPPLE - 0.01), "order {}", order);
+            assert!(passband.iter().any(|&db| db > -0.01), "order {}", order);
+            assert!((low.magnitude_db(1000.0) + CHEBYSHEV_RIPPLE).abs() < 0.05);
+
+            // steeper than a Butterworth filter of the same order
+            let butterworth = sloped(FilterType::LPF2, Slope::Butterworth(order));
+            if order > 2 {
+                assert!(low.magnitude_db(2000.0) < butterworth.magnitude_db(2000.0) - 5.0);
+            }
+            let high = sloped(FilterType::HPF2, slope);
+            assert!((high.magnitude_db(1000.0) + CHEBYSHEV_RIPPLE).abs() < 0.05);
+        }
+
+        // only the passes have a slope
+        let peak = filter(FilterType::PEAK2, 1000.0, 0.707, 0, 6.0);
+        let mut sloped_peak = filter(FilterType::PEAK2, 1000.0, 0.707, 0, 6.0);
+        sloped_peak.set_slope(Slope::Chebyshev(8));
+        sloped_peak.cleanup();
+        assert_eq!(peak.magnitude_db(1500.0), sloped_peak.magnitude_db(1500.0));
+    }
+
+    #[test]
+    fn slope_control() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::LPF2, 1000.0);
+        eq.set_control(PortIndex::EQSlope as u32, Slope::Butterworth(8).to_u32() as f32);
+        assert!((level(&mut eq, 1000.0) + 3.0).abs() < 0.1);
+        assert!(level(&mut eq, 2000.0) < -45.0);
+    }
+
+    #[test]
+    fn fade_len_paces_the_changes() {
+        let mut f = filter(FilterType::LPF2, 1000.0, 0.707, 0, 0.0);
+        f.set_fade_len(64);
+        let mut block = [0.0; 64];
+        for &freq in &[2000.0, 4000.0, 8000.0] {
+            f.set_values(FilterType::LPF2.to_u32() as f32, freq, 0.707, 0.0, 0.0);
+            f.filter(&mut block);
+        }
+        // every change got through, none is left pending
+        for &freq in &[100.0, 1000.0, 8000.0, 16000.0] {
+            assert_eq!(f.magnitude_db(freq), f.target().magnitude_db(freq));
+        }
+        assert!((f.magnitude_db(8000.0) + 3.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn channels_are_independent() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::LPF2, 1000.0);
+
+        let input = sine(100.0, 512);
+        eq.set_audio_input(PortIndex::EQInputL as u32, &input);
+        eq.set_audio_input(PortIndex::EQInputR as u32, &vec![0.0; 512]);
+        eq.run(input.len());
+
+        assert!(rms(eq.audio_output(PortIndex::EQOutputL as u32)) > 0.1);
+        assert!(eq.audio_output(PortIndex::EQOutputR as u32).iter().all(|x| *x == 0.0));
+    }
+
+    /* The curve events of one block. */
+    fn curve_events(host: &TestHost, eq: &mut Instance) -> Vec<AtomEvent> {
+        let input = sine(1000.0, 64);
+        eq.set_audio_input(PortIndex::EQInputL as u32, &input);
+        eq.set_audio_input(PortIndex::EQInputR as u32, &input);
+        eq.run(input.len());
+        let events = eq.atom_output(PortIndex::EQNotify as u32);
+        for event in &events {
+            assert_eq!(event.object_type(), host.map(CURVE_URI));
+        }
+        events
+    }
+
+    #[test]
+    fn curve_is_sent_on_changes() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::PEAK2, 1000.0);
+        eq.set_control(PortIndex::EQGain as u32, 6.0);
+        eq.connect_atom_output(PortIndex::EQNotify as u32, 4096);
+
+        let events = curve_events(&host, &mut eq);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].float_property(&host, CURVE_MIN_FREQUENCY), Some(20.0));
+        assert_eq!(events[0].float_property(&host, CURVE_MAX_FREQUENCY), Some(20000.0));
+        let curve = events[0].float_vector_property(&host, CURVE_MAGNITUDE).unwrap();
+        // the stages port of this plugin counts the additional stages, not filters
+        let expected = magnitude_curve(&filter(FilterType::PEAK2, 1000.0, 0.707, 1, 6.0),
+            20.0, 20000.0, CURVE_POINTS);
+        assert_eq!(curve.len(), CURVE_POINTS);
+        for (db, &(freq, expected)) in curve.iter().zip(expected.iter()) {
+            assert!((*db as f64 - expected).abs() < 1e-3, "{} Hz: {} dB, expected {} dB", freq, db, expected);
+        }
+
+        assert!(curve_events(&host, &mut eq).is_empty());
+
+        // the curve shows the new settings right away, not the crossfade
+        eq.set_control(PortIndex::EQGain as u32, -6.0);
+        let events = curve_events(&host, &mut eq);
+        assert_eq!(events.len(), 1);
+        let curve = events[0].float_vector_property(&host, CURVE_MAGNITUDE).unwrap();
+        assert!(curve.iter().cloned().fold(0.0, f32::min) < -5.5);
+        eq.set_control(PortIndex::EQGain as u32, 0.0);
+        let events = curve_events(&host, &mut eq);
+        let curve = events[0].float_vector_property(&host, CURVE_MAGNITUDE).unwrap();
+        assert!(curve.iter().all(|db| db.abs() < 1e-3));
+    }
+
+    #[test]
+    fn curve_waits_for_the_output() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::LPF2, 1000.0);
+        assert!(level(&mut eq, 100.0).abs() < 1.0);
+
+        // too small for the curve, the sequence stays empty and the curve due
+        eq.connect_atom_output(PortIndex::EQNotify as u32, 64);
+        assert!(curve_events(&host, &mut eq).is_empty());
+        eq.connect_atom_output(PortIndex::EQNotify as u32, CURVE_BUFFER_SIZE as usize);
+        assert_eq!(curve_events(&host, &mut eq).len(), 1);
+        assert!(curve_events(&host, &mut eq).is_empty());
+
+        let mut eq = self::eq(&host, FilterType::LPF2, 1000.0);
+        assert!(level(&mut eq, 100.0).abs() < 1.0);
+        eq.connect_atom_output(PortIndex::EQNotify as u32, CURVE_BUFFER_SIZE as usize);
+        assert_eq!(curve_events(&host, &mut eq).len(), 1);
     }
 }
 
@@ -30,20 +713,38 @@ mod tests {
    replacing `http:/` with `lv2` any header in the specification bundle can be
    included, in this case `lv2.h`.
 */
-mod analog_eq;
+pub mod analog_eq;
+pub mod apo;
+pub mod channels;
+pub mod crossover;
+pub mod curve;
+pub mod dynamic;
+pub mod formant;
+pub mod linear;
+pub mod paraeq;
+pub mod plot;
+pub mod response;
+pub mod vowel;
 
 extern crate libc;
-extern crate lv2_raw;
+extern crate rustlv2;
 extern crate num;
 
 
-use lv2_raw::*;
+use rustlv2::*;
 use std::f32;
-use std::f64;
-use std::mem::*;
-use libc::{c_char, c_void};
+use std::mem;
+use libc::c_void;
 
 use analog_eq::*;
+use channels::*;
+use curve::*;
+use linear::{LinearPhase, PARTITION};
+
+pub use crossover::Crossover;
+pub use dynamic::DynamicEQ;
+pub use paraeq::ParaEQ;
+pub use vowel::VowelFilter;
 
 /*
    The URI is the identifier for a plugin, and how the host associates this
@@ -52,40 +753,165 @@ use analog_eq::*;
    file is a good convention to follow.  If this URI does not match that used
    in the data files, the host will fail to load the plugin.
 */
-static AMP_URI: &'static [u8] = b"http://example.org/rusteq\0";
+const EQ_URI: &'static [u8] = b"http://example.org/rusteq\0";
 
 /*
    In code, ports are referred to by index.  An enumeration of port indices
    should be defined for readability.
 */
-enum PortIndex {
-    EQInputL  = 0,
-    EQInputR  = 1,
-    EQOutputL = 2,
-    EQOutputR = 3,
-    EQType = 4,
-    EQFreq = 5,
-    EQQ = 6,
-    EQStages = 7,
-    EQGain = 8
+port_index! {
+    enum PortIndex {
+        EQInputL  = 0,
+        EQInputR  = 1,
+        EQOutputL = 2,
+        EQOutputR = 3,
+        EQType = 4,
+        EQFreq = 5,
+        EQQ = 6,
+        EQStages = 7,
+        EQGain = 8,
+        EQMode = 9,
+        EQNotify = 10,
+        EQCategory = 11,
+        EQDesign = 12,
+        EQSlope = 13,
+        EQLinear = 14,
+        EQLatency = 15
+    }
 }
 
-impl PortIndex {
+/*
+   The description of the ports, from which the plugin's .ttl file is
+   generated.
+*/
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::audio_in(PortIndex::EQInputL as u32, "inl", "In L"),
+    PortInfo::audio_in(PortIndex::EQInputR as u32, "inr", "In R"),
+    PortInfo::audio_out(PortIndex::EQOutputL as u32, "outl", "Out L"),
+    PortInfo::audio_out(PortIndex::EQOutputR as u32, "outr", "Out R"),
+    PortInfo::control_in(PortIndex::EQType as u32, "type", "Filter Type", 0.0, 0.0, LAST_FILTER_TYPE)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(FILTER_TYPES),
+    PortInfo::control_in(PortIndex::EQFreq as u32, "frequency", "Frequency", 100.0, 10.0, 20000.0)
+        .unit("units:hz")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"])
+        .scale_points(&[("20", 20.0), ("100", 100.0), ("500", 500.0), ("1000", 1000.0),
+            ("10000", 10000.0), ("20000", 20000.0)]),
+    PortInfo::control_in(PortIndex::EQQ as u32, "q", "Q", 0.707, 0.1, 200.0)
+        .unit("units:coef")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+    PortInfo::control_in(PortIndex::EQStages as u32, "stages", "Stages", 1.0, 1.0, 5.0)
+        .unit("units:coef")
+        .properties(&["lv2:integer"]),
+    PortInfo::control_in(PortIndex::EQGain as u32, "gain", "Gain", 0.0, -20.0, 20.0)
+        .unit("units:db")
+        .properties(&["epp:hasStrictBounds"]),
+    PortInfo::control_in(PortIndex::EQMode as u32, "mode", "Channels", 0.0, 0.0, 4.0)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(&[("Stereo", 0.0), ("Left", 1.0), ("Right", 2.0), ("Mid", 3.0), ("Side", 4.0)]),
+    // the magnitude curve for UIs, see the curve module
+    PortInfo::atom_out(PortIndex::EQNotify as u32, "notify", "Curve")
+        .properties(&["lv2:connectionOptional"])
+        .minimum_size(CURVE_BUFFER_SIZE),
+    PortInfo::control_in(PortIndex::EQCategory as u32, "category", "Filter Category", 0.0, 0.0, 1.0)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(&[("Analog", 0.0), ("State Variable", 1.0)]),
+    // of the analog filter, see analog_eq::Design
+    PortInfo::control_in(PortIndex::EQDesign as u32, "design", "Design", 0.0, 0.0, 1.0)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(DESIGNS),
+    // of the analog low and high passes, see analog_eq::Slope
+    PortInfo::control_in(PortIndex::EQSlope as u32, "slope", "Slope", 0.0, 0.0, LAST_SLOPE)
+        .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+        .scale_points(SLOPES),
+    // see the linear module
+    PortInfo::control_in(PortIndex::EQLinear as u32, "linear_phase", "Linear Phase", 0.0, 0.0, 1.0)
+        .properties(&["lv2:toggled"]),
+    // of the linear phase mode, 0 otherwise
+    PortInfo::control_out(PortIndex::EQLatency as u32, "latency", "Latency")
+        .designation("lv2:latency")
+        .properties(&["lv2:reportsLatency", "lv2:integer"])
+];
+
+/*
+   The filter categories of ZynAddSubFX this plugin has.  Both filters are
+   in series, the one not selected is disabled, so switching crossfades
+   like any other change.
+*/
+const CATEGORY_ANALOG: u32 = 0;
+const CATEGORY_STATE_VARIABLE: u32 = 1;
+
+/* The filters of both channels, the analog and the state variable ones. */
+struct Filters {
+    filter_l: AnalogFilter,
+    filter_r: AnalogFilter,
+    sv_l: SVFilter,
+    sv_r: SVFilter
+}
 
-    fn from_u32(x: u32) -> Option<PortIndex> {
-        match x {
-            0 => Some(PortIndex::EQInputL),
-            1 => Some(PortIndex::EQInputR),
-            2 => Some(PortIndex::EQOutputL),
-            3 => Some(PortIndex::EQOutputR),
-            4 => Some(PortIndex::EQType),
-            5 => Some(PortIndex::EQFreq),
-            6 => Some(PortIndex::EQQ),
-            7 => Some(PortIndex::EQStages),
-            8 => Some(PortIndex::EQGain),
-            _ => None
+impl Filters {
+    fn new(ftype: &FilterType, ffreq: f32, fq: f32, fstages: u8, srate: u32, max_block: usize) -> Filters {
+        Filters {
+            filter_l: AnalogFilter::new(ftype, ffreq, fq, fstages, srate, max_block),
+            filter_r: AnalogFilter::new(ftype, ffreq, fq, fstages, srate, max_block),
+            sv_l: SVFilter::new(ftype, ffreq, fq, fstages, srate, max_block),
+            sv_r: SVFilter::new(ftype, ffreq, fq, fstages, srate, max_block)
         }
     }
+
+    /* Which filters run and how they are designed, crossfaded. */
+    fn set_kind(&mut self, category: f32, design: f32, slope: f32) {
+        let analog = category as u32 != CATEGORY_STATE_VARIABLE;
+        for filter in [&mut self.filter_l, &mut self.filter_r].iter_mut() {
+            filter.set_enabled(analog);
+            filter.set_design(Design::from_u32(design as u32));
+            filter.set_slope(Slope::from_u32(slope as u32));
+        }
+        self.sv_l.set_enabled(!analog);
+        self.sv_r.set_enabled(!analog);
+    }
+
+    /* Set all filters from the control values, crossfaded. */
+    fn set_values(&mut self, ftype: f32, freq: f32, q: f32, stages: f32, gain: f32) {
+        self.filter_l.set_values(ftype, freq, q, stages, gain);
+        self.filter_r.set_values(ftype, freq, q, stages, gain);
+        self.sv_l.set_values(ftype, freq, q, stages, gain);
+        self.sv_r.set_values(ftype, freq, q, stages, gain);
+    }
+
+    fn cleanup(&mut self) {
+        self.filter_l.cleanup();
+        self.filter_r.cleanup();
+        self.sv_l.cleanup();
+        self.sv_r.cleanup();
+    }
+
+    /* The response of one channel, both have the same settings. */
+    fn target(&self) -> [Sections; 2] {
+        [self.filter_l.target(), self.sv_l.target()]
+    }
+
+    /* Filter a block of at most the `max_block` the filters were made for. */
+    fn process(&mut self, mode: ChannelMode, left: &mut [f32], right: &mut [f32]) {
+        let (filter_l, filter_r, sv_l, sv_r) = (&mut self.filter_l, &mut self.filter_r, &mut self.sv_l, &mut self.sv_r);
+        mode.process(left, right,
+            |l| {
+                if !filter_l.is_bypassed() {
+                    filter_l.filter(l);
+                }
+                if !sv_l.is_bypassed() {
+                    sv_l.filter(l);
+                }
+            },
+            |r| {
+                if !filter_r.is_bypassed() {
+                    filter_r.filter(r);
+                }
+                if !sv_r.is_bypassed() {
+                    sv_r.filter(r);
+                }
+            });
+    }
 }
 
 /*
@@ -94,25 +920,59 @@ impl PortIndex {
    every instance method.  In this simple plugin, only port buffers need to be
    stored, since there is no additional instance data.
 */
-#[repr(C)]
-struct EQ{
+pub struct EQ {
     // Port buffers
-    input_l: *const f32,
-    input_r: *const f32,
-    output_l: *mut f32,
-    output_r: *mut f32,
-    ftype: *const f32,
-    freq: *const f32,
-    q: *const f32,
-    stages: *const f32,
-    gain: *const f32,
+    input_l: AudioIn,
+    input_r: AudioIn,
+    output_l: AudioOut,
+    output_r: AudioOut,
+    ftype: ControlIn,
+    freq: ControlIn,
+    q: ControlIn,
+    stages: ControlIn,
+    gain: ControlIn,
+    mode: ControlIn,
+    notify: AtomOut,
+    category: ControlIn,
+    design: ControlIn,
+    slope: ControlIn,
+    linear: ControlIn,
+    latency: ControlOut,
     ftype_c: f32,
     freq_c: f32,
     q_c: f32,
     stages_c: f32,
     gain_c: f32,
-    filter_l: AnalogFilter,
-    filter_r: AnalogFilter
+    category_c: f32,
+    design_c: f32,
+    slope_c: f32,
+    linear_c: f32,
+    mode_c: f32,
+    filters: Filters,
+    /*
+       A channel mode switch starts over with clean filters, which are
+       crossfaded to from the old mode on the filters it had.  A filter
+       which is skipped in one mode would resume with stale state, and one
+       which gets another signal (left, then mid) with the wrong one.
+    */
+    old_filters: Filters,
+    old_mode: ChannelMode,
+    old_l: Vec<f32>,
+    old_r: Vec<f32>,
+    fade_pos: usize,
+    fade_len: usize,
+    linear_phase: LinearPhase,
+    // the mode which runs, and its gain in steps of 1 / PARTITION while it
+    // fades out or in on a switch
+    linear_running: bool,
+    switch_gain: usize,
+    samplerate: f64,
+    // none without urid:map
+    curve: Option<CurvePublisher>,
+    // the controls changed since the curve was last sent
+    curve_due: bool,
+    // the longest block the filters take at once
+    max_block: usize
 }
 
 impl EQ {
@@ -120,171 +980,284 @@ impl EQ {
         ffreq: f32,
         fq: f32,
         fstages: u8,
-        srate: u32) -> EQ {
+        srate: u32,
+        max_block: usize,
+        curve: Option<CurvePublisher>) -> EQ {
         EQ { 
-            input_l: (0 as *const f32),   
-            input_r: (0 as *const f32),   
-            output_l: (0 as *mut f32),
-            output_r: (0 as *mut f32),
-            ftype: (0 as *const f32),
-            freq: (0 as *const f32),
-            q: (0 as *const f32),
-            stages: (0 as *const f32),
-            gain: (0 as *const f32),
+            input_l: AudioIn::new(),
+            input_r: AudioIn::new(),
+            output_l: AudioOut::new(),
+            output_r: AudioOut::new(),
+            ftype: ControlIn::new(),
+            freq: ControlIn::new(),
+            q: ControlIn::new(),
+            stages: ControlIn::new(),
+            gain: ControlIn::new(),
+            mode: ControlIn::new(),
+            notify: AtomOut::new(),
+            category: ControlIn::new(),
+            design: ControlIn::new(),
+            slope: ControlIn::new(),
+            linear: ControlIn::new(),
+            latency: ControlOut::new(),
             ftype_c: ftype.to_u32() as f32,
             freq_c: ffreq,
             q_c: fq,
             stages_c: fstages as f32,
             gain_c: 1.0,
-            filter_l: AnalogFilter::new(ftype, ffreq, fq, fstages, srate),
-            filter_r: AnalogFilter::new(ftype, ffreq, fq, fstages, srate)
+            category_c: CATEGORY_ANALOG as f32,
+            design_c: Design::Bilinear.to_u32() as f32,
+            slope_c: Slope::Stages.to_u32() as f32,
+            linear_c: 0.0,
+            mode_c: ChannelMode::Stereo.to_u32() as f32,
+            filters: Filters::new(ftype, ffreq, fq, fstages, srate, max_block),
+            old_filters: Filters::new(ftype, ffreq, fq, fstages, srate, max_block),
+            old_mode: ChannelMode::Stereo,
+            old_l: vec![0.0; max_block.max(1)],
+            old_r: vec![0.0; max_block.max(1)],
+            fade_pos: 0,
+            fade_len: ((srate as f64 * FADE_TIME) as usize).max(1),
+            linear_phase: LinearPhase::new(),
+            linear_running: false,
+            switch_gain: PARTITION,
+            samplerate: srate as f64,
+            curve: curve,
+            curve_due: true,
+            max_block: max_block
         }
     }
 }
 
 
-/* Define a macro for converting a gain in dB to a coefficient. */
-//#define DB_CO(g) ((g) > -90.0f ? powf(10.0f, (g) * 0.05f) : 0.0f)
+impl Plugin for EQ {
+    const URI: &'static [u8] = EQ_URI;
 
-fn db_co(g: f32) -> f64 {
-    if g > -90.0_f32 {
-        let base = 10.0_f64;
-        base.powf((g as f64) * 0.05_f64)
-    }
-    else {
-        0.0_f64
-    }
-}
-
-struct Descriptor(LV2Descriptor);
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust clone of the ZynAddSubFX analog filters",
+        classes: &["lv2:FilterPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable", "opts:options", "bufsz:boundedBlockLength", "urid:map"],
+        extension_data: &[],
+        supported_options: &["bufsz:maxBlockLength"],
+        ports: PORTS,
+        parameters: &[]
+    };
 
+    fn instantiate(rate: f64, features: &Features) -> Option<EQ> {
+        let max_block = features.max_block_length().unwrap_or(DEFAULT_MAX_BLOCK);
 
-impl Descriptor {
-    pub extern "C" fn activate(_handle: LV2Handle) {}
-    pub extern "C" fn deactivate(_handle: LV2Handle) {}
-
+        Some(EQ::new(&FilterType::LPF1,
+            1000.0,
+            1.0,
+            1,
+            rate as u32,
+            max_block,
+            features.urid_map().map(|map| CurvePublisher::new(&map))))
+    }
 
-    pub extern "C" fn run(handle: LV2Handle, n_seqlen: u32) {
-        let _eq = handle as *mut EQ;
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::EQInputL) => self.input_l.connect(data),
+            Some(PortIndex::EQInputR) => self.input_r.connect(data),
+            Some(PortIndex::EQOutputL) => self.output_l.connect(data),
+            Some(PortIndex::EQOutputR) => self.output_r.connect(data),
+            Some(PortIndex::EQType) => self.ftype.connect(data),
+            Some(PortIndex::EQFreq) => self.freq.connect(data),
+            Some(PortIndex::EQQ) => self.q.connect(data),
+            Some(PortIndex::EQStages) => self.stages.connect(data),
+            Some(PortIndex::EQGain) => self.gain.connect(data),
+            Some(PortIndex::EQMode) => self.mode.connect(data),
+            Some(PortIndex::EQNotify) => self.notify.connect(data),
+            Some(PortIndex::EQCategory) => self.category.connect(data),
+            Some(PortIndex::EQDesign) => self.design.connect(data),
+            Some(PortIndex::EQSlope) => self.slope.connect(data),
+            Some(PortIndex::EQLinear) => self.linear.connect(data),
+            Some(PortIndex::EQLatency) => self.latency.connect(data),
+            None => ()
+        }
+    }
 
-        let mut eq = unsafe { &mut *_eq };
+    /*
+       Forget the control values, the first run then sets up the filters
+       without crossfading from what they did before.
+    */
+    fn activate(&mut self) {
+        self.ftype_c = f32::NAN;
+        self.freq_c = f32::NAN;
+        self.q_c = f32::NAN;
+        self.stages_c = f32::NAN;
+        self.gain_c = f32::NAN;
+        self.category_c = f32::NAN;
+        self.design_c = f32::NAN;
+        self.slope_c = f32::NAN;
+        self.linear_c = f32::NAN;
+        self.mode_c = f32::NAN;
+    }
 
+    fn run(&mut self, n_seqlen: u32) {
         let n = n_seqlen as usize;
 
-        let input_l = unsafe { std::slice::from_raw_parts(eq.input_l, n) };
-        let input_r = unsafe { std::slice::from_raw_parts(eq.input_r, n) };
-        let output_l = unsafe { std::slice::from_raw_parts_mut(eq.output_l, n) };
-        let output_r = unsafe { std::slice::from_raw_parts_mut(eq.output_r, n) };
-        let ftype = unsafe { *eq.ftype }; 
-        let freq = unsafe { *eq.freq };
-        let q = unsafe { *eq.q };
-        let stages = unsafe { *eq.stages };
-        let gain = unsafe { *eq.gain };
-        let ref mut filter_l = eq.filter_l;
-        let ref mut filter_r = eq.filter_r;
+        let input_l = self.input_l.as_slice(n);
+        let input_r = self.input_r.as_slice(n);
+        let output_l = self.output_l.as_mut_slice(n);
+        let output_r = self.output_r.as_mut_slice(n);
+        let ftype = self.ftype.value();
+        let freq = self.freq.value();
+        let q = self.q.value();
+        let stages = self.stages.value();
+        let gain = self.gain.value();
+        let category = self.category.value();
+        let design = self.design.value();
+        let slope = self.slope.value();
+        let linear = self.linear.value();
+        let mode = self.mode.value();
 
         // compare actual control port values with cached values. If something
         // has changed, we need to recalculate the coeffcients
-        if ftype != eq.ftype_c || freq != eq.freq_c || q != eq.q_c 
-            || stages != eq.stages_c || gain != eq.gain_c {
+        let changed = ftype != self.ftype_c || freq != self.freq_c || q != self.q_c
+            || stages != self.stages_c || gain != self.gain_c || category != self.category_c
+            || design != self.design_c || slope != self.slope_c;
+        if changed {
+            let activated = self.ftype_c.is_nan();
+            self.ftype_c = ftype;
+            self.freq_c = freq;
+            self.q_c = q;
+            self.stages_c = stages;
+            self.gain_c = gain;
+            self.category_c = category;
+            self.design_c = design;
+            self.slope_c = slope;
 
-            eq.ftype_c = ftype;
-            eq.freq_c = freq;
-            eq.q_c = q;
-            eq.stages_c = stages;
-            eq.gain_c = gain;
-
-            filter_l.set_values(ftype, freq, q, stages, db_co(gain));
-            filter_r.set_values(ftype, freq, q, stages, db_co(gain));
+            self.filters.set_kind(category, design, slope);
+            self.filters.set_values(ftype, freq, q, stages, gain);
+            if activated {
+                self.filters.cleanup();
+            }
+            self.curve_due = true;
         }
 
-        filter_l.filterout(input_l, output_l);
-        filter_r.filterout(input_r, output_r);
-    }
-
-    pub extern "C" fn connect_port(instance: LV2Handle,
-        port: u32,
-        data : *mut c_void)
-    {
-        let mut eq = unsafe { &mut *(instance as *mut EQ) };
-        let p = PortIndex::from_u32(port);
-
-        println!("connect_port: {}", port);
-
-        match p {
-            Some(PortIndex::EQInputL) => eq.input_l = data as *const f32,
-            Some(PortIndex::EQInputR) => eq.input_r = data as *const f32 ,
-            Some(PortIndex::EQOutputL) => eq.output_l = data as *mut f32 ,
-            Some(PortIndex::EQOutputR) => eq.output_r = data as *mut f32 ,
-            Some(PortIndex::EQType) => eq.ftype = data as *const f32,
-            Some(PortIndex::EQFreq) => eq.freq = data as *const f32,
-            Some(PortIndex::EQQ) => eq.q = data as *const f32,
-            Some(PortIndex::EQStages) => eq.stages = data as *const f32,
-            Some(PortIndex::EQGain) => eq.gain = data as *const f32,
-            None => println!("Not a valid port index: {}", port)
+        // both channels have the same settings
+        if let Some(ref curve) = self.curve {
+            let target = self.filters.target();
+            let filter = if self.curve_due { Some(&target[..]) } else { None };
+            if curve.publish(&mut self.notify, filter) {
+                self.curve_due = false;
+            }
         }
-    }
-
 
-    pub extern "C" fn instantiate(_desc: *const LV2Descriptor,
-        _rate: f64,
-        _bundle_path: *const c_char,
-        _features: *const *const LV2Feature) -> LV2Handle {
-
-            let rate = _rate as u32;
-
-            let ptr: *mut EQ =  unsafe { transmute(Box::new(EQ::new(
-                &FilterType::LPF1,
-                1000.0,
-                1.0,
-                1,
-                rate))) };
-
-            
-
-            return ptr as LV2Handle;
-    }
+        /*
+           The linear phase FIR follows the filters and which channels they
+           filter.  Switching between the modes changes the delay, so the
+           running one fades out to silence and the other one starts over,
+           fading in, each over a partition of the convolution.
+        */
+        let activated = self.linear_c.is_nan();
+        let old_mode = self.mode_c;
+        if changed || linear != self.linear_c || mode != old_mode {
+            self.linear_phase.request();
+        }
+        self.linear_c = linear;
+        self.mode_c = mode;
+        let linear = linear > 0.5;
+        let mode = ChannelMode::from_u32(mode as u32);
+        if activated {
+            self.linear_running = linear;
+            self.switch_gain = PARTITION;
+            self.linear_phase.cleanup();
+            self.fade_pos = self.fade_len;
+        }
+        if linear {
+            let target = self.filters.target();
+            self.linear_phase.design_requested(&target[..], self.samplerate, mode.filtered(),
+                self.linear_running && !activated);
+        }
+        self.linear_phase.advance(n);
+        self.latency.set(if linear { linear::latency() as f32 } else { 0.0 });
 
-    pub extern "C" fn cleanup(handle: LV2Handle) {
-        unsafe { 
-            let _drop: Box<EQ> = transmute(((handle as *mut EQ)));
+        if !activated && !self.linear_running && ChannelMode::from_u32(old_mode as u32) != mode {
+            mem::swap(&mut self.filters, &mut self.old_filters);
+            self.filters.set_kind(category, design, slope);
+            self.filters.set_values(ftype, freq, q, stages, gain);
+            self.filters.cleanup();
+            self.old_mode = ChannelMode::from_u32(old_mode as u32);
+            self.fade_pos = 0;
         }
-    }
 
-    pub extern "C" fn extension_data(_uri: *const u8) -> *const c_void {
-        return 0 as *const c_void
-    }
-}
+        output_l.copy_from_slice(input_l);
+        output_r.copy_from_slice(input_r);
+        let mut done = 0;
+        while done < n {
+            if self.linear_running != linear && self.switch_gain == 0 {
+                self.linear_running = linear;
+                if linear {
+                    self.linear_phase.cleanup();
+                } else {
+                    self.filters.cleanup();
+                    self.fade_pos = self.fade_len;
+                }
+            }
+            let fading_out = self.linear_running != linear;
+            let len = if fading_out { self.switch_gain.min(n - done) } else { n - done };
+            let (out_l, out_r) = (&mut output_l[done..done + len], &mut output_r[done..done + len]);
+            done += len;
 
-static mut DESCRIPTOR: LV2Descriptor = LV2Descriptor {
-    uri: 0 as *const c_char,
-    instantiate: Descriptor::instantiate,
-    connect_port: Descriptor::connect_port,
-    activate: Some(Descriptor::activate),
-    run: Descriptor::run,
-    deactivate: Some(Descriptor::deactivate),
-    cleanup: Descriptor::cleanup,
-    extension_data: Descriptor::extension_data
-};
+            // the input fades in, which also covers the latency of the linear phase
+            if !fading_out && self.switch_gain < PARTITION {
+                for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
+                    self.switch_gain += 1;
+                    let x = self.switch_gain as f32 / PARTITION as f32;
+                    *l *= x;
+                    *r *= x;
+                    if self.switch_gain == PARTITION {
+                        break;
+                    }
+                }
+            }
 
-/*
-   The `lv2_descriptor()` function is the entry point to the plugin library.  The
-   host will load the library and call this function repeatedly with increasing
-   indices to find all the plugins defined in the library.  The index is not an
-   indentifier, the URI of the returned descriptor is used to determine the
-   identify of the plugin.
-
-   This method is in the ``discovery'' threading class, so no other functions
-   or methods in this plugin library will be called concurrently with it.
-*/
+            if self.linear_running {
+                if mode.mid_side() {
+                    ms_encode(out_l, out_r);
+                }
+                self.linear_phase.filter(out_l, out_r);
+                if mode.mid_side() {
+                    ms_decode(out_l, out_r);
+                }
+            } else {
+                // the host may run longer blocks than it announced, or announce none
+                let chunks = out_l.chunks_mut(self.max_block).zip(out_r.chunks_mut(self.max_block));
+                for (out_l, out_r) in chunks {
+                    let fading = self.fade_pos < self.fade_len;
+                    let (old_l, old_r) = (&mut self.old_l[..out_l.len()], &mut self.old_r[..out_r.len()]);
+                    if fading {
+                        old_l.copy_from_slice(out_l);
+                        old_r.copy_from_slice(out_r);
+                        self.old_filters.process(self.old_mode, old_l, old_r);
+                    }
+                    self.filters.process(mode, out_l, out_r);
+                    if fading {
+                        for (i, (l, r)) in out_l.iter_mut().zip(out_r.iter_mut()).enumerate() {
+                            let x = ((self.fade_pos + i + 1) as f32 / self.fade_len as f32).min(1.0);
+                            *l = old_l[i] * (1.0 - x) + *l * x;
+                            *r = old_r[i] * (1.0 - x) + *r * x;
+                        }
+                        self.fade_pos = (self.fade_pos + out_l.len()).min(self.fade_len);
+                    }
+                }
+            }
 
-#[no_mangle]
-pub extern "C" fn lv2_descriptor(index: u32) -> *const LV2Descriptor {
-    match index {
-        0 => unsafe {
-            DESCRIPTOR.uri = AMP_URI.as_ptr() as *const c_char;
-            return &DESCRIPTOR as *const LV2Descriptor },
-        _ => return 0 as *const LV2Descriptor
+            // and the output fades out
+            if fading_out {
+                for (l, r) in out_l.iter_mut().zip(out_r.iter_mut()) {
+                    self.switch_gain -= 1;
+                    let x = self.switch_gain as f32 / PARTITION as f32;
+                    *l *= x;
+                    *r *= x;
+                }
+            }
+        }
     }
 }
 
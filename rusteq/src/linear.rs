@@ -0,0 +1,533 @@
+/*
+   Linear phase filtering: an FIR with the magnitude of a filter's response
+   and no phase distortion, applied by uniformly partitioned FFT convolution
+   (overlap-save with a frequency domain delay line).
+
+   The FIR is designed by frequency sampling: the magnitude at `TAPS`
+   frequencies, made zero phase by the inverse FFT, delayed by half its
+   length and windowed.  The output is delayed by that plus one partition,
+   which the convolution collects before it can filter, see `latency`.
+   With 8192 taps the response is resolved to a few Hz at 48 kHz, very
+   narrow peaks or notches at low frequencies come out wider and shallower.
+*/
+
+use std::f64::consts::PI;
+use num::complex::Complex64;
+
+use response::FrequencyResponse;
+
+
+// length of the FIRs
+pub const TAPS: usize = 8192;
+
+// the samples convolved at once, the FIRs are split into parts this long
+pub const PARTITION: usize = 256;
+
+
+/* Radix 2 FFT of a fixed size, in place. */
+pub struct Fft {
+    twiddles: Vec<Complex64>,
+    reversed: Vec<usize>
+}
+
+impl Fft {
+    /* `n` has to be a power of two. */
+    pub fn new(n: usize) -> Fft {
+        assert!(n.is_power_of_two());
+        let bits = n.trailing_zeros();
+        Fft {
+            twiddles: (0..n / 2).map(|k| Complex64::from_polar(&1.0, &(-2.0 * PI * k as f64 / n as f64))).collect(),
+            reversed: (0..n).map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (usize::BITS - bits) }).collect()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.reversed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reversed.is_empty()
+    }
+
+    pub fn forward(&self, data: &mut [Complex64]) {
+        self.transform(data, false);
+    }
+
+    /* The inverse, scaled by 1 / n so that it undoes `forward`. */
+    pub fn inverse(&self, data: &mut [Complex64]) {
+        self.transform(data, true);
+        let scale = 1.0 / data.len() as f64;
+        for x in data.iter_mut() {
+            *x *= scale;
+        }
+    }
+
+    fn transform(&self, data: &mut [Complex64], inverse: bool) {
+        let n = self.len();
+        assert_eq!(data.len(), n);
+        for i in 0..n {
+            let j = self.reversed[i];
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= n {
+            let half = size / 2;
+            let step = n / size;
+            for start in (0..n).step_by(size) {
+                for k in 0..half {
+                    let w = self.twiddles[k * step];
+                    let w = if inverse { w.conj() } else { w };
+                    let t = data[start + k + half] * w;
+                    data[start + k + half] = data[start + k] - t;
+                    data[start + k] += t;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+
+/*
+   The linear phase FIR with the magnitude of `filter`, `fft.len()` taps
+   long and delayed by half of that, into `fir`.  `spectrum` is the work
+   space, as long as the FFT.
+*/
+pub fn design_fir<R: FrequencyResponse + ?Sized>(filter: &R, samplerate: f64, fft: &Fft,
+    spectrum: &mut [Complex64], fir: &mut [f64]) {
+
+    let n = fft.len();
+    for k in 0..(n / 2 + 1) {
+        let magnitude = filter.response(k as f64 * samplerate / n as f64).norm();
+        spectrum[k] = Complex64::new(magnitude, 0.0);
+        if k > 0 && k < n / 2 {
+            spectrum[n - k] = spectrum[k];
+        }
+    }
+    fft.inverse(spectrum);
+
+    // the periodic Hann window is symmetric around the middle, as the FIR
+    for (i, tap) in fir.iter_mut().enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos();
+        *tap = spectrum[(i + n / 2) % n].re * window;
+    }
+}
+
+/* The FIR which only delays by half its length. */
+pub fn delay_fir(fir: &mut [f64]) {
+    let n = fir.len();
+    for (i, tap) in fir.iter_mut().enumerate() {
+        *tap = if i == n / 2 { 1.0 } else { 0.0 };
+    }
+}
+
+
+/*
+   Convolution with an FIR of `TAPS` taps, in partitions of `PARTITION`
+   samples.  A new FIR is crossfaded to over one partition.
+*/
+pub struct Convolver {
+    fft: Fft,
+    // the spectra of the parts of the FIR, and of the one to fade to
+    filter: Vec<Complex64>,
+    next: Vec<Complex64>,
+    fading: bool,
+    // the spectra of the last input partitions, the newest at `newest`
+    inputs: Vec<Complex64>,
+    newest: usize,
+    // the last two input partitions
+    window: Vec<f64>,
+    // input of the current partition, output of the last one
+    input: Vec<f32>,
+    output: Vec<f32>,
+    pos: usize,
+    sum: Vec<Complex64>,
+    old: Vec<f32>
+}
+
+const PARTS: usize = TAPS / PARTITION;
+const SPECTRUM: usize = 2 * PARTITION;
+
+impl Convolver {
+    pub fn new() -> Convolver {
+        let mut convolver = Convolver {
+            fft: Fft::new(SPECTRUM),
+            filter: vec![Complex64::new(0.0, 0.0); PARTS * SPECTRUM],
+            next: vec![Complex64::new(0.0, 0.0); PARTS * SPECTRUM],
+            fading: false,
+            inputs: vec![Complex64::new(0.0, 0.0); PARTS * SPECTRUM],
+            newest: 0,
+            window: vec![0.0; SPECTRUM],
+            input: vec![0.0; PARTITION],
+            output: vec![0.0; PARTITION],
+            pos: 0,
+            sum: vec![Complex64::new(0.0, 0.0); SPECTRUM],
+            old: vec![0.0; PARTITION]
+        };
+        let mut fir = vec![0.0; TAPS];
+        delay_fir(&mut fir);
+        convolver.set_fir(&fir, false);
+        convolver
+    }
+
+    /* Convolve with `fir` from the next partition on, crossfaded if `fade`. */
+    pub fn set_fir(&mut self, fir: &[f64], fade: bool) {
+        for (part, spectrum) in fir.chunks(PARTITION).zip(self.next.chunks_mut(SPECTRUM)) {
+            for (i, x) in spectrum.iter_mut().enumerate() {
+                *x = Complex64::new(if i < PARTITION { part[i] } else { 0.0 }, 0.0);
+            }
+            self.fft.forward(spectrum);
+        }
+        if fade {
+            self.fading = true;
+        } else {
+            self.filter.copy_from_slice(&self.next);
+            self.fading = false;
+        }
+    }
+
+    /* Silence the input history. */
+    pub fn cleanup(&mut self) {
+        for x in self.inputs.iter_mut() {
+            *x = Complex64::new(0.0, 0.0);
+        }
+        for x in self.window.iter_mut() {
+            *x = 0.0;
+        }
+        for x in self.output.iter_mut() {
+            *x = 0.0;
+        }
+        self.pos = 0;
+        if self.fading {
+            self.filter.copy_from_slice(&self.next);
+            self.fading = false;
+        }
+    }
+
+    /* Filter a block of any length in place. */
+    pub fn filter(&mut self, data: &mut [f32]) {
+        let mut done = 0;
+        while done < data.len() {
+            let len = (PARTITION - self.pos).min(data.len() - done);
+            let (pos, part) = (self.pos, &mut data[done..done + len]);
+            self.input[pos..pos + len].copy_from_slice(part);
+            part.copy_from_slice(&self.output[pos..pos + len]);
+            self.pos += len;
+            done += len;
+            if self.pos == PARTITION {
+                self.partition();
+                self.pos = 0;
+            }
+        }
+    }
+
+    /* Convolve the collected input partition into `output`. */
+    fn partition(&mut self) {
+        self.window.copy_within(PARTITION.., 0);
+        for (w, x) in self.window[PARTITION..].iter_mut().zip(self.input.iter()) {
+            *w = *x as f64;
+        }
+        self.newest = (self.newest + 1) % PARTS;
+        {
+            let spectrum = &mut self.inputs[self.newest * SPECTRUM..(self.newest + 1) * SPECTRUM];
+            for (x, w) in spectrum.iter_mut().zip(self.window.iter()) {
+                *x = Complex64::new(*w, 0.0);
+            }
+            self.fft.forward(spectrum);
+        }
+
+        if self.fading {
+            Convolver::convolve(&self.fft, &self.inputs, self.newest, &self.filter, &mut self.sum, &mut self.old);
+            self.filter.copy_from_slice(&self.next);
+            self.fading = false;
+            Convolver::convolve(&self.fft, &self.inputs, self.newest, &self.filter, &mut self.sum, &mut self.output);
+            for (i, (out, old)) in self.output.iter_mut().zip(self.old.iter()).enumerate() {
+                let x = (i + 1) as f32 / PARTITION as f32;
+                *out = *old * (1.0 - x) + *out * x;
+            }
+        } else {
+            Convolver::convolve(&self.fft, &self.inputs, self.newest, &self.filter, &mut self.sum, &mut self.output);
+        }
+    }
+
+    /*
+       The sum of the input spectra, the newest one first, times the parts
+       of the FIR, back in the time domain.  Of the two partitions the
+       first one is wrapped around, the second one is the output.
+    */
+    fn convolve(fft: &Fft, inputs: &[Complex64], newest: usize, filter: &[Complex64],
+        sum: &mut [Complex64], output: &mut [f32]) {
+
+        for x in sum.iter_mut() {
+            *x = Complex64::new(0.0, 0.0);
+        }
+        for part in 0..PARTS {
+            let slot = (newest + PARTS - part) % PARTS;
+            let input = &inputs[slot * SPECTRUM..(slot + 1) * SPECTRUM];
+            let coeff = &filter[part * SPECTRUM..(part + 1) * SPECTRUM];
+            for ((s, x), c) in sum.iter_mut().zip(input.iter()).zip(coeff.iter()) {
+                *s += *x * *c;
+            }
+        }
+        fft.inverse(sum);
+        for (out, x) in output.iter_mut().zip(sum[PARTITION..].iter()) {
+            *out = x.re as f32;
+        }
+    }
+}
+
+impl Default for Convolver {
+    fn default() -> Convolver {
+        Convolver::new()
+    }
+}
+
+/* The delay of the convolution in samples. */
+pub fn latency() -> u32 {
+    (TAPS / 2 + PARTITION) as u32
+}
+
+
+/*
+   Both channels of the linear phase mode.  A channel which is not filtered
+   is only delayed, so it stays aligned with the other one.  Everything is
+   allocated up front, designing and filtering can run in `run`.
+
+   Designing takes far longer than filtering a block, so a new FIR is only
+   asked for with `request` and designed by `design_requested` at most once
+   per partition, the convolution takes no more of them anyway.
+*/
+pub struct LinearPhase {
+    fft: Fft,
+    spectrum: Vec<Complex64>,
+    fir: Vec<f64>,
+    delay: Vec<f64>,
+    left: Convolver,
+    right: Convolver,
+    // a FIR was asked for, and the samples since the last one, up to a partition
+    requested: bool,
+    since_design: usize
+}
+
+impl LinearPhase {
+    pub fn new() -> LinearPhase {
+        let mut delay = vec![0.0; TAPS];
+        delay_fir(&mut delay);
+        LinearPhase {
+            fft: Fft::new(TAPS),
+            spectrum: vec![Complex64::new(0.0, 0.0); TAPS],
+            fir: vec![0.0; TAPS],
+            delay: delay,
+            left: Convolver::new(),
+            right: Convolver::new(),
+            requested: false,
+            since_design: PARTITION
+        }
+    }
+
+    /*
+       Filter the channels for which `filtered` is true with the magnitude
+       of `target`, crossfaded if `fade`.
+    */
+    pub fn set_target<R: FrequencyResponse + ?Sized>(&mut self, target: &R, samplerate: f64,
+        filtered: (bool, bool), fade: bool) {
+
+        design_fir(target, samplerate, &self.fft, &mut self.spectrum, &mut self.fir);
+        let (left, right) = filtered;
+        self.left.set_fir(if left { &self.fir } else { &self.delay }, fade);
+        self.right.set_fir(if right { &self.fir } else { &self.delay }, fade);
+        self.requested = false;
+        self.since_design = 0;
+    }
+
+    /* Ask for a new FIR, see `design_requested`. */
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /*
+       Like `set_target` if a FIR was asked for and none was designed in the
+       last partition.  Returns whether it designed one.
+    */
+    pub fn design_requested<R: FrequencyResponse + ?Sized>(&mut self, target: &R, samplerate: f64,
+        filtered: (bool, bool), fade: bool) -> bool {
+
+        let due = self.requested && self.since_design >= PARTITION;
+        if due {
+            self.set_target(target, samplerate, filtered, fade);
+        }
+        due
+    }
+
+    /* Count `samples` run by the host, whether they were filtered or not. */
+    pub fn advance(&mut self, samples: usize) {
+        self.since_design = (self.since_design + samples).min(PARTITION);
+    }
+
+    /* Silence the history, the next FIR asked for is designed right away. */
+    pub fn cleanup(&mut self) {
+        self.left.cleanup();
+        self.right.cleanup();
+        self.since_design = PARTITION;
+    }
+
+    pub fn filter(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.left.filter(left);
+        self.right.filter(right);
+    }
+}
+
+impl Default for LinearPhase {
+    fn default() -> LinearPhase {
+        LinearPhase::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use analog_eq::FilterType;
+    use channels::ChannelMode;
+    use tests::{eq, filter, largest_step, level_db, rms, run_stereo, sine, RATE};
+    use PortIndex;
+
+    #[test]
+    fn fft_is_the_dft() {
+        let n = 64;
+        let input: Vec<Complex64> = (0..n).map(|i| Complex64::new((i as f64 * 0.3).sin(), (i * i % 7) as f64)).collect();
+        let fft = Fft::new(n);
+        let mut data = input.clone();
+        fft.forward(&mut data);
+
+        for (k, x) in data.iter().enumerate() {
+            let dft = input.iter().enumerate().fold(Complex64::new(0.0, 0.0), |sum, (i, y)|
+                sum + y * Complex64::from_polar(&1.0, &(-2.0 * ::std::f64::consts::PI * (i * k) as f64 / n as f64)));
+            assert!((x - dft).norm() < 1e-9, "bin {}", k);
+        }
+        fft.inverse(&mut data);
+        assert!(data.iter().zip(&input).all(|(x, y)| (x - y).norm() < 1e-12));
+    }
+
+    fn linear_eq<'h>(host: &'h TestHost, ftype: FilterType, freq: f32, gain: f32) -> Instance<'h> {
+        let mut eq = eq(host, ftype, freq);
+        eq.set_control(PortIndex::EQGain as u32, gain);
+        eq.set_control(PortIndex::EQLinear as u32, 1.0);
+        eq
+    }
+
+    /* Level of a sine after the latency relative to the input in dB */
+    fn linear_level(eq: &mut Instance, freq: f32) -> f32 {
+        let input = sine(freq, 19200);
+        run_stereo(eq, (PortIndex::EQInputL as u32, PortIndex::EQInputR as u32), &input, &input);
+        level_db(eq.audio_output(PortIndex::EQOutputL as u32), &input, 9600)
+    }
+
+    #[test]
+    fn linear_phase_has_the_magnitude() {
+        let host = TestHost::new();
+        for &(ftype, gain) in &[(FilterType::PEAK2, 9.0), (FilterType::LPF2, 0.0), (FilterType::HiShelf, -6.0)] {
+            // as the EQ sets it up
+            let mut expected = filter(ftype, 1000.0, 0.707, 0, 0.0);
+            expected.set_values(ftype.to_u32() as f32, 1000.0, 0.707, 1.0, gain);
+            expected.cleanup();
+            for &freq in &[100.0, 700.0, 1000.0, 1500.0, 5000.0] {
+                let level = linear_level(&mut linear_eq(&host, ftype, 1000.0, gain), freq);
+                let db = expected.magnitude_db(freq as f64) as f32;
+                assert!((level - db).abs() < 0.1, "{:?} at {} Hz: {} dB, expected {}", ftype, freq, level, db);
+            }
+        }
+    }
+
+    #[test]
+    fn latency_is_reported() {
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::PEAK2, 1000.0);
+        linear_level(&mut eq, 1000.0);
+        assert_eq!(eq.control(PortIndex::EQLatency as u32), 0.0);
+
+        eq.set_control(PortIndex::EQLinear as u32, 1.0);
+        linear_level(&mut eq, 1000.0);
+        assert_eq!(eq.control(PortIndex::EQLatency as u32), (TAPS / 2 + PARTITION) as f32);
+    }
+
+    #[test]
+    fn linear_phase_is_symmetric() {
+        let host = TestHost::new();
+        let mut eq = linear_eq(&host, FilterType::PEAK2, 1000.0, 12.0);
+        let mut impulse = vec![0.0; 16384];
+        impulse[0] = 1.0;
+        eq.set_audio_input(PortIndex::EQInputL as u32, &impulse);
+        eq.set_audio_input(PortIndex::EQInputR as u32, &impulse);
+        eq.run(impulse.len());
+
+        let latency = eq.control(PortIndex::EQLatency as u32) as usize;
+        let out = eq.audio_output(PortIndex::EQOutputL as u32);
+        assert!(out[latency] > 1.0);
+        for k in 1..TAPS / 2 {
+            assert!((out[latency + k] - out[latency - k]).abs() < 1e-6, "{} samples apart", k);
+        }
+        assert!(out[..latency - TAPS / 2].iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn linear_phase_delays_unfiltered_channels() {
+        let host = TestHost::new();
+        let mut eq = linear_eq(&host, FilterType::LPF2, 500.0, 0.0);
+        eq.set_control(PortIndex::EQMode as u32, ChannelMode::Left.to_u32() as f32);
+        let input = sine(5000.0, 9600);
+        eq.set_audio_input(PortIndex::EQInputL as u32, &input);
+        eq.set_audio_input(PortIndex::EQInputR as u32, &input);
+        eq.run(input.len());
+
+        let latency = latency() as usize;
+        assert!(rms(&eq.audio_output(PortIndex::EQOutputL as u32)[latency..]) < 0.05);
+        let right = eq.audio_output(PortIndex::EQOutputR as u32);
+        assert!(right[latency..].iter().zip(&input).all(|(o, i)| (o - i).abs() < 1e-5));
+    }
+
+    #[test]
+    fn linear_phase_switches_are_click_free() {
+        // 50 Hz passes the 1 kHz low pass and changes by 0.0065 per sample at most
+        let host = TestHost::new();
+        let mut eq = eq(&host, FilterType::LPF2, 1000.0);
+        let x = sine(50.0, 512 * 24);
+
+        let mut out = Vec::new();
+        for (i, block) in x.chunks(512).enumerate() {
+            eq.set_control(PortIndex::EQLinear as u32, if (2..16).contains(&i) { 1.0 } else { 0.0 });
+            eq.set_audio_input(PortIndex::EQInputL as u32, block);
+            eq.set_audio_input(PortIndex::EQInputR as u32, block);
+            eq.run(block.len());
+            out.extend_from_slice(eq.audio_output(PortIndex::EQOutputL as u32));
+        }
+        // both modes were heard
+        assert!(rms(&out[512 * 14..512 * 16]) > 0.5 && rms(&out[512 * 20..]) > 0.5);
+        assert!(largest_step(&out) < 0.02, "{}", largest_step(&out));
+    }
+
+    #[test]
+    fn linear_phase_designs_once_per_partition() {
+        let mut linear = LinearPhase::new();
+        let peak = filter(FilterType::PEAK2, 1000.0, 1.0, 0, 6.0);
+        let design = |linear: &mut LinearPhase| {
+            linear.request();
+            linear.design_requested(&peak, RATE, (true, true), true)
+        };
+
+        assert!(design(&mut linear));
+        // automated every block of 64 samples
+        for _ in 0..3 {
+            linear.advance(64);
+            assert!(!design(&mut linear));
+        }
+        linear.advance(64);
+        assert!(design(&mut linear));
+        // nothing asked for, nothing to do
+        linear.advance(PARTITION);
+        assert!(!linear.design_requested(&peak, RATE, (true, true), true));
+    }
+}
@@ -0,0 +1,501 @@
+/*
+   A parametric EQ with eight bands in series, each of them one
+   `AnalogFilter` per channel with the controls of the single band EQ plus
+   an enable switch.  A disabled band is crossfaded out like any other
+   change and then skipped.
+
+   After the bands comes a chain of filters which is not held in ports but
+   in the plugin's state, e.g. a room correction imported from a REW or
+   Equalizer APO filter list.  It is saved as the text of such a list, so
+   presets can be written by hand or with the eqapo tool, see the apo
+   module.
+*/
+
+use rustlv2::*;
+use rustlv2::lv2_raw::*;
+use std::f32;
+use std::ffi::CStr;
+use std::ptr;
+use std::str;
+use libc::c_void;
+
+use analog_eq::*;
+use apo;
+
+
+const PARAEQ_URI: &'static [u8] = b"http://example.org/rustparaeq\0";
+
+pub const BANDS: usize = 8;
+
+// the key of the chain in the state, an atom:String of Equalizer APO lines
+pub const FILTERS_KEY: &'static [u8] = b"http://example.org/rusteq#filters\0";
+
+port_index! {
+    pub(crate) enum PortIndex {
+        PEQInputL = 0,
+        PEQInputR = 1,
+        PEQOutputL = 2,
+        PEQOutputR = 3,
+        PEQOutputGain = 4
+    }
+}
+
+/*
+   The controls of band `n` (0 based) follow the fixed ports, BAND_BLOCK of
+   them per band in the order of `BandControl`.  The design controls came
+   later, one per band after all of the other ones, so that the indices of
+   the older ports stay the same.
+*/
+const FIRST_BAND_PORT: u32 = 5;
+const BAND_BLOCK: u32 = 6;
+const FIRST_DESIGN_PORT: u32 = FIRST_BAND_PORT + BANDS as u32 * BAND_BLOCK;
+const BAND_CONTROLS: u32 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandControl {
+    Enable = 0,
+    Type = 1,
+    Freq = 2,
+    Q = 3,
+    Gain = 4,
+    Filters = 5,
+    Design = 6
+}
+
+impl BandControl {
+    fn from_u32(x: u32) -> Option<BandControl> {
+        match x {
+            0 => Some(BandControl::Enable),
+            1 => Some(BandControl::Type),
+            2 => Some(BandControl::Freq),
+            3 => Some(BandControl::Q),
+            4 => Some(BandControl::Gain),
+            5 => Some(BandControl::Filters),
+            6 => Some(BandControl::Design),
+            _ => None
+        }
+    }
+}
+
+pub fn band_port(band: usize, control: BandControl) -> u32 {
+    if control == BandControl::Design {
+        FIRST_DESIGN_PORT + band as u32
+    } else {
+        FIRST_BAND_PORT + band as u32 * BAND_BLOCK + control as u32
+    }
+}
+
+/* The band and control of a band port. */
+fn band_control(port: u32) -> (usize, Option<BandControl>) {
+    if port >= FIRST_DESIGN_PORT {
+        ((port - FIRST_DESIGN_PORT) as usize, Some(BandControl::Design))
+    } else {
+        (((port - FIRST_BAND_PORT) / BAND_BLOCK) as usize,
+            BandControl::from_u32((port - FIRST_BAND_PORT) % BAND_BLOCK))
+    }
+}
+
+/*
+   The ports of one band, with its number as a literal for the symbols and
+   names, its first port index, its default type and frequency and the
+   index of its design port.
+*/
+macro_rules! paraeq_ports {
+    ($($n:tt => ($first:expr, $ftype:expr, $freq:expr, $design:expr)),+) => {
+        &[
+            PortInfo::audio_in(PortIndex::PEQInputL as u32, "inl", "In L"),
+            PortInfo::audio_in(PortIndex::PEQInputR as u32, "inr", "In R"),
+            PortInfo::audio_out(PortIndex::PEQOutputL as u32, "outl", "Out L"),
+            PortInfo::audio_out(PortIndex::PEQOutputR as u32, "outr", "Out R"),
+            PortInfo::control_in(PortIndex::PEQOutputGain as u32, "output_gain", "Output Gain", 0.0, -20.0, 20.0)
+                .unit("units:db")
+                .properties(&["epp:hasStrictBounds"]),
+            $(
+                PortInfo::control_in($first, concat!("enable", $n), concat!("Band ", $n, " Enable"), 1.0, 0.0, 1.0)
+                    .properties(&["lv2:toggled"]),
+                PortInfo::control_in($first + 1, concat!("type", $n), concat!("Band ", $n, " Type"), $ftype, 0.0,
+                    LAST_FILTER_TYPE)
+                    .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+                    .scale_points(FILTER_TYPES),
+                PortInfo::control_in($first + 2, concat!("frequency", $n), concat!("Band ", $n, " Frequency"),
+                    $freq, 10.0, 20000.0)
+                    .unit("units:hz")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 3, concat!("q", $n), concat!("Band ", $n, " Q"), 0.707, 0.1, 200.0)
+                    .unit("units:coef")
+                    .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+                PortInfo::control_in($first + 4, concat!("gain", $n), concat!("Band ", $n, " Gain"), 0.0, -20.0, 20.0)
+                    .unit("units:db")
+                    .properties(&["epp:hasStrictBounds"]),
+                PortInfo::control_in($first + 5, concat!("filters", $n), concat!("Band ", $n, " Filters"), 1.0, 1.0, 5.0)
+                    .properties(&["epp:hasStrictBounds", "lv2:integer"]),
+            )+
+            $(
+                PortInfo::control_in($design, concat!("design", $n), concat!("Band ", $n, " Design"), 0.0, 0.0, 1.0)
+                    .properties(&["epp:hasStrictBounds", "lv2:integer", "lv2:enumeration"])
+                    .scale_points(DESIGNS),
+            )+
+        ]
+    }
+}
+
+// shelves at the ends, peaks in between
+const PORTS: &'static [PortInfo] = paraeq_ports! {
+    1 => (5, 7.0, 50.0, 53),
+    2 => (11, 6.0, 100.0, 54),
+    3 => (17, 6.0, 200.0, 55),
+    4 => (23, 6.0, 500.0, 56),
+    5 => (29, 6.0, 1000.0, 57),
+    6 => (35, 6.0, 2000.0, 58),
+    7 => (41, 6.0, 5000.0, 59),
+    8 => (47, 8.0, 10000.0, 60)
+};
+
+
+struct Band {
+    controls: [ControlIn; BAND_CONTROLS as usize],
+    // the control values the filters were set up with
+    cached: [f32; BAND_CONTROLS as usize],
+    filter_l: AnalogFilter,
+    filter_r: AnalogFilter
+}
+
+impl Band {
+    fn new(srate: u32, max_block: usize) -> Band {
+        Band {
+            controls: [ControlIn::new(), ControlIn::new(), ControlIn::new(),
+                ControlIn::new(), ControlIn::new(), ControlIn::new(), ControlIn::new()],
+            cached: [f32::NAN; BAND_CONTROLS as usize],
+            filter_l: AnalogFilter::new(&FilterType::PEAK2, 1000.0, 0.707, 0, srate, max_block),
+            filter_r: AnalogFilter::new(&FilterType::PEAK2, 1000.0, 0.707, 0, srate, max_block)
+        }
+    }
+
+    fn value(&self, control: BandControl) -> f32 {
+        self.controls[control as usize].value()
+    }
+
+    /* Follow changed controls, right away if `activated`. */
+    fn update(&mut self, activated: bool) {
+        let values = [
+            self.value(BandControl::Enable), self.value(BandControl::Type), self.value(BandControl::Freq),
+            self.value(BandControl::Q), self.value(BandControl::Gain), self.value(BandControl::Filters),
+            self.value(BandControl::Design)
+        ];
+        if values == self.cached {
+            return;
+        }
+        self.cached = values;
+
+        let ftype = values[BandControl::Type as usize];
+        let freq = values[BandControl::Freq as usize];
+        let q = values[BandControl::Q as usize];
+        // the port counts filters, unlike the stages port of the EQ the
+        // filter counts the additional stages
+        let stages = (values[BandControl::Filters as usize] - 1.0).max(0.0);
+        let gain = values[BandControl::Gain as usize];
+        let enabled = values[BandControl::Enable as usize] > 0.0;
+        let design = Design::from_u32(values[BandControl::Design as usize] as u32);
+
+        for filter in [&mut self.filter_l, &mut self.filter_r].iter_mut() {
+            filter.set_enabled(enabled);
+            filter.set_design(design);
+            filter.set_values(ftype, freq, q, stages, gain);
+            if activated {
+                filter.cleanup();
+            }
+        }
+    }
+}
+
+
+/* A band of the chain, the filters of the left and the right channel. */
+struct ChainBand {
+    filter_l: AnalogFilter,
+    filter_r: AnalogFilter
+}
+
+impl ChainBand {
+    fn new(band: &apo::Band, srate: u32, max_block: usize) -> ChainBand {
+        let filter = || {
+            let mut filter = AnalogFilter::new(&band.ftype, band.freq, band.q, 0, srate, max_block);
+            filter.set_values(band.ftype.to_u32() as f32, band.freq, band.q, 0.0, band.gain);
+            filter.cleanup();
+            filter
+        };
+        ChainBand { filter_l: filter(), filter_r: filter() }
+    }
+}
+
+struct StateUris {
+    filters: LV2Urid,
+    atom_string: LV2Urid
+}
+
+
+pub struct ParaEQ {
+    input_l: AudioIn,
+    input_r: AudioIn,
+    output_l: AudioOut,
+    output_r: AudioOut,
+    output_gain: ControlIn,
+    // the output gain at the end of the last block
+    output_gain_c: f32,
+    bands: Vec<Band>,
+    activated: bool,
+    chain: apo::Chain,
+    chain_bands: Vec<ChainBand>,
+    // of the chain, linear
+    preamp: f32,
+    // none without urid:map, then there is no state
+    uris: Option<StateUris>,
+    srate: u32,
+    max_block: usize
+}
+
+impl ParaEQ {
+    fn new(srate: u32, max_block: usize, uris: Option<StateUris>) -> ParaEQ {
+        ParaEQ {
+            input_l: AudioIn::new(),
+            input_r: AudioIn::new(),
+            output_l: AudioOut::new(),
+            output_r: AudioOut::new(),
+            output_gain: ControlIn::new(),
+            output_gain_c: 1.0,
+            bands: (0..BANDS).map(|_| Band::new(srate, max_block)).collect(),
+            activated: false,
+            chain: apo::Chain::default(),
+            chain_bands: Vec::new(),
+            preamp: 1.0,
+            uris: uris,
+            srate: srate,
+            max_block: max_block
+        }
+    }
+
+    /* Filter with `chain` after the bands, the disabled bands of it are skipped. */
+    fn set_chain(&mut self, chain: apo::Chain) {
+        self.chain_bands = chain.bands.iter()
+            .filter(|band| band.enabled)
+            .map(|band| ChainBand::new(band, self.srate, self.max_block))
+            .collect();
+        self.preamp = db_co(chain.preamp) as f32;
+        self.chain = chain;
+    }
+}
+
+
+impl Plugin for ParaEQ {
+    const URI: &'static [u8] = PARAEQ_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust 8 band parametric EQ",
+        classes: &["lv2:EQPlugin", "lv2:ParaEQPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable", "opts:options", "bufsz:boundedBlockLength", "urid:map"],
+        extension_data: &["state:interface"],
+        supported_options: &["bufsz:maxBlockLength"],
+        ports: PORTS,
+        parameters: &[]
+    };
+
+    fn instantiate(rate: f64, features: &Features) -> Option<ParaEQ> {
+        let max_block = features.max_block_length().unwrap_or(DEFAULT_MAX_BLOCK);
+
+        let uris = features.urid_map().map(|map| StateUris {
+            filters: map.map(FILTERS_KEY),
+            atom_string: map.map(LV2_ATOM__STRING)
+        });
+
+        Some(ParaEQ::new(rate as u32, max_block, uris))
+    }
+
+    fn extension_data(uri: &CStr) -> *const c_void {
+        if uri.to_bytes_with_nul() == LV2_STATE__INTERFACE {
+            STATE.as_ptr()
+        } else {
+            ptr::null()
+        }
+    }
+
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::PEQInputL) => self.input_l.connect(data),
+            Some(PortIndex::PEQInputR) => self.input_r.connect(data),
+            Some(PortIndex::PEQOutputL) => self.output_l.connect(data),
+            Some(PortIndex::PEQOutputR) => self.output_r.connect(data),
+            Some(PortIndex::PEQOutputGain) => self.output_gain.connect(data),
+            None => {
+                let (band, control) = band_control(port);
+                if let (Some(band), Some(control)) = (self.bands.get_mut(band), control) {
+                    band.controls[control as usize].connect(data);
+                }
+            }
+        }
+    }
+
+    /* Like the single band EQ, the first run sets up the filters without crossfade. */
+    fn activate(&mut self) {
+        self.activated = true;
+    }
+
+    fn run(&mut self, n_samples: u32) {
+        let n = n_samples as usize;
+
+        let output_l = self.output_l.as_mut_slice(n);
+        let output_r = self.output_r.as_mut_slice(n);
+        output_l.copy_from_slice(self.input_l.as_slice(n));
+        output_r.copy_from_slice(self.input_r.as_slice(n));
+
+        for band in self.bands.iter_mut() {
+            band.update(self.activated);
+            if !band.filter_l.is_bypassed() {
+                band.filter_l.filter(output_l);
+                band.filter_r.filter(output_r);
+            }
+        }
+        for band in self.chain_bands.iter_mut() {
+            band.filter_l.filter(output_l);
+            band.filter_r.filter(output_r);
+        }
+
+        // ramp the output gain over the block
+        let gain = db_co(self.output_gain.value()) as f32 * self.preamp;
+        if self.activated {
+            self.output_gain_c = gain;
+            self.activated = false;
+        }
+        let step = (gain - self.output_gain_c) / n.max(1) as f32;
+        for (i, (l, r)) in output_l.iter_mut().zip(output_r.iter_mut()).enumerate() {
+            let g = self.output_gain_c + step * (i + 1) as f32;
+            *l *= g;
+            *r *= g;
+        }
+        self.output_gain_c = gain;
+    }
+}
+
+
+/* The chain is saved with the session, as Equalizer APO lines. */
+static STATE: StateInterface = StateInterface::new::<ParaEQ>();
+
+impl State for ParaEQ {
+    fn save(&self, store: &mut StateStore, _features: &Features) -> Result<(), StateError> {
+        let uris = self.uris.as_ref().ok_or(StateError::NoFeature)?;
+        let mut text = apo::write(&self.chain).into_bytes();
+        text.push(0);
+        store.store(uris.filters, uris.atom_string, &text)
+    }
+
+    /* A state without a chain, e.g. of an older version, clears it. */
+    fn restore(&mut self, retrieve: &StateRetrieve, _features: &Features) -> Result<(), StateError> {
+        let chain = {
+            let uris = self.uris.as_ref().ok_or(StateError::NoFeature)?;
+            match retrieve.retrieve(uris.filters) {
+                Some((vtype, value)) if vtype == uris.atom_string => {
+                    let text = str::from_utf8(value).map_err(|_| StateError::BadType)?;
+                    apo::parse(text.trim_end_matches('\0')).map_err(|_| StateError::BadType)?
+                },
+                Some(_) => return Err(StateError::BadType),
+                None => apo::Chain::default()
+            }
+        };
+        self.set_chain(chain);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use tests::{instantiate_with_defaults, level_db, run_stereo, sine};
+
+    /* The parametric EQ with all controls at their defaults. */
+    fn paraeq(host: &TestHost) -> Instance<'_> {
+        instantiate_with_defaults::<ParaEQ>(host, "http://example.org/rustparaeq")
+    }
+
+    fn paraeq_level(eq: &mut Instance, freq: f32) -> f32 {
+        let input = sine(freq, 9600);
+        eq.activate();
+        run_stereo(eq, (PortIndex::PEQInputL as u32, PortIndex::PEQInputR as u32), &input, &input);
+        level_db(eq.audio_output(PortIndex::PEQOutputL as u32), &input, 4800)
+    }
+
+    #[test]
+    fn paraeq_is_flat_by_default() {
+        let host = TestHost::new();
+        for &freq in &[30.0, 100.0, 1000.0, 15000.0] {
+            assert!(paraeq_level(&mut paraeq(&host), freq).abs() < 0.01, "{} Hz", freq);
+        }
+    }
+
+    #[test]
+    fn paraeq_bands_in_series() {
+        let host = TestHost::new();
+        let mut eq = paraeq(&host);
+        for &band in &[3, 4] {
+            eq.set_control(band_port(band, BandControl::Freq), 1000.0);
+            eq.set_control(band_port(band, BandControl::Gain), 6.0);
+        }
+        let level = paraeq_level(&mut eq, 1000.0);
+        assert!((level - 12.0).abs() < 0.5, "{} dB", level);
+    }
+
+    #[test]
+    fn paraeq_filters_in_series() {
+        let host = TestHost::new();
+        let level = |filters: f32| {
+            let mut eq = paraeq(&host);
+            eq.set_control(band_port(4, BandControl::Type), FilterType::LPF2.to_u32() as f32);
+            eq.set_control(band_port(4, BandControl::Freq), 1000.0);
+            eq.set_control(band_port(4, BandControl::Filters), filters);
+            paraeq_level(&mut eq, 4000.0)
+        };
+        let single = level(1.0);
+        assert!(single < -20.0, "{} dB", single);
+        assert!((level(2.0) - 2.0 * single).abs() < 0.5, "{} dB", level(2.0));
+    }
+
+    #[test]
+    fn paraeq_bypass_and_output_gain() {
+        let host = TestHost::new();
+        let mut eq = paraeq(&host);
+        eq.set_control(band_port(4, BandControl::Enable), 0.0);
+        eq.set_control(band_port(4, BandControl::Gain), 12.0);
+        eq.set_control(PortIndex::PEQOutputGain as u32, -6.0);
+        assert!((paraeq_level(&mut eq, 1000.0) + 6.0).abs() < 0.01);
+    }
+
+    fn filters_state(host: &TestHost, text: &str) -> SavedState {
+        let mut state = SavedState::default();
+        let mut value = text.as_bytes().to_vec();
+        value.push(0);
+        state.values.insert(host.map(FILTERS_KEY), (host.map(LV2_ATOM__STRING), value));
+        state
+    }
+
+    #[test]
+    fn paraeq_filters_from_state() {
+        let host = TestHost::new();
+        let mut eq = paraeq(&host);
+        eq.restore_state(&filters_state(&host, "Preamp: -3 dB\nFilter 1: ON PK Fc 1000 Hz Gain 9 dB Q 2")).unwrap();
+        assert!((paraeq_level(&mut eq, 1000.0) - 6.0).abs() < 0.05);
+        assert!((paraeq_level(&mut eq, 100.0) + 3.0).abs() < 0.05);
+
+        // saved as it was loaded
+        let state = eq.save_state().unwrap().unwrap();
+        let (vtype, value) = &state.values[&host.map(FILTERS_KEY)];
+        assert_eq!(*vtype, host.map(LV2_ATOM__STRING));
+        assert_eq!(value.as_slice(), &b"Preamp: -3 dB\nFilter 1: ON PK Fc 1000 Hz Gain 9 dB Q 2\n\0"[..]);
+
+        // a state from before the chain, or a broken one
+        eq.restore_state(&SavedState::default()).unwrap();
+        assert!(paraeq_level(&mut eq, 1000.0).abs() < 0.01);
+        assert_eq!(eq.restore_state(&filters_state(&host, "Filter 1: ON PK")), Err(StateError::BadType));
+    }
+}
@@ -0,0 +1,387 @@
+/*
+   Drawing a magnitude curve (see `response::magnitude_curve`) as SVG or
+   PNG, with a log frequency axis and a dB grid, e.g. for the documentation
+   or to compare the curves of two versions:
+
+       let curve = magnitude_curve(&filter, 20.0, 20000.0, 500);
+       let plot = Plot::new(800, 400, 20.0, 20000.0).fit(&curve);
+       fs::write("curve.svg", plot.svg(&curve))?;
+
+   The PNG is written uncompressed, the labels use a tiny built in font, so
+   nothing but the standard library is needed.
+*/
+
+use std::fmt::Write;
+
+
+const MARGIN_LEFT: f64 = 50.0;
+const MARGIN_RIGHT: f64 = 15.0;
+const MARGIN_TOP: f64 = 10.0;
+const MARGIN_BOTTOM: f64 = 30.0;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const GRID: [u8; 3] = [221, 221, 221];
+// the 0 dB line and the frame
+const AXIS: [u8; 3] = [136, 136, 136];
+const LABEL: [u8; 3] = [68, 68, 68];
+const CURVE: [u8; 3] = [204, 0, 0];
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plot {
+    pub width: u32,
+    pub height: u32,
+    // frequency axis in Hz
+    pub from: f64,
+    pub to: f64,
+    // dB axis
+    pub min_db: f64,
+    pub max_db: f64
+}
+
+impl Plot {
+    /* A plot from -24 to 24 dB, see `fit` for a range following the curve. */
+    pub fn new(width: u32, height: u32, from: f64, to: f64) -> Plot {
+        Plot { width: width, height: height, from: from, to: to, min_db: -24.0, max_db: 24.0 }
+    }
+
+    pub fn range(mut self, min_db: f64, max_db: f64) -> Plot {
+        self.min_db = min_db;
+        self.max_db = max_db;
+        self
+    }
+
+    /*
+       Make the dB range hold the curve, at least -12 to 12 dB and at most
+       down to -120 dB, in steps of 6 dB.
+    */
+    pub fn fit(self, curve: &[(f64, f64)]) -> Plot {
+        let min = curve.iter().map(|p| p.1).fold(-12.0, f64::min).max(-120.0);
+        let max = curve.iter().map(|p| p.1).fold(12.0, f64::max);
+        self.range((min / 6.0).floor() * 6.0, (max / 6.0).ceil() * 6.0)
+    }
+
+    fn x(&self, freq: f64) -> f64 {
+        let width = self.width as f64 - MARGIN_LEFT - MARGIN_RIGHT;
+        MARGIN_LEFT + (freq / self.from).ln() / (self.to / self.from).ln() * width
+    }
+
+    fn y(&self, db: f64) -> f64 {
+        let height = self.height as f64 - MARGIN_TOP - MARGIN_BOTTOM;
+        let db = db.max(self.min_db).min(self.max_db);
+        MARGIN_TOP + (self.max_db - db) / (self.max_db - self.min_db) * height
+    }
+
+    /* 1, 2 and 5 times the powers of ten within the frequency axis. */
+    fn frequency_grid(&self) -> Vec<f64> {
+        let mut grid = Vec::new();
+        let mut decade = 10f64.powf(self.from.log10().floor());
+        while decade <= self.to {
+            for &m in &[1.0, 2.0, 5.0] {
+                let freq = m * decade;
+                if freq >= self.from * 0.999 && freq <= self.to * 1.001 {
+                    grid.push(freq);
+                }
+            }
+            decade *= 10.0;
+        }
+        grid
+    }
+
+    fn db_grid(&self) -> Vec<f64> {
+        let range = self.max_db - self.min_db;
+        let step = if range <= 48.0 { 6.0 } else if range <= 96.0 { 12.0 } else { 24.0 };
+        let mut grid = Vec::new();
+        let mut db = (self.min_db / step).ceil() * step;
+        while db <= self.max_db {
+            grid.push(db);
+            db += step;
+        }
+        grid
+    }
+
+    fn left(&self) -> f64 {
+        MARGIN_LEFT
+    }
+
+    fn right(&self) -> f64 {
+        self.width as f64 - MARGIN_RIGHT
+    }
+
+    fn top(&self) -> f64 {
+        MARGIN_TOP
+    }
+
+    fn bottom(&self) -> f64 {
+        self.height as f64 - MARGIN_BOTTOM
+    }
+
+    pub fn svg(&self, curve: &[(f64, f64)]) -> String {
+        let mut svg = String::new();
+
+        writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+            viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"11\">",
+            self.width, self.height).unwrap();
+        writeln!(svg, "<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>", self.width, self.height,
+            hex(BACKGROUND)).unwrap();
+
+        for freq in self.frequency_grid() {
+            let x = self.x(freq);
+            writeln!(svg, "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{0:.1}\" y2=\"{2:.1}\" stroke=\"{3}\"/>",
+                x, self.top(), self.bottom(), hex(GRID)).unwrap();
+            writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" fill=\"{}\">{}</text>",
+                x, self.bottom() + 15.0, hex(LABEL), frequency_label(freq)).unwrap();
+        }
+        for db in self.db_grid() {
+            let y = self.y(db);
+            let color = if db == 0.0 { AXIS } else { GRID };
+            writeln!(svg, "<line x1=\"{0:.1}\" y1=\"{1:.1}\" x2=\"{2:.1}\" y2=\"{1:.1}\" stroke=\"{3}\"/>",
+                self.left(), y, self.right(), hex(color)).unwrap();
+            writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" fill=\"{}\">{}</text>",
+                self.left() - 5.0, y + 4.0, hex(LABEL), db).unwrap();
+        }
+        writeln!(svg, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"none\" stroke=\"{}\"/>",
+            self.left(), self.top(), self.right() - self.left(), self.bottom() - self.top(), hex(AXIS)).unwrap();
+        writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" fill=\"{}\">Hz</text>",
+            self.right(), self.height as f64 - 2.0, hex(LABEL)).unwrap();
+        writeln!(svg, "<text x=\"4\" y=\"{:.1}\" fill=\"{}\">dB</text>", self.top() + 4.0, hex(LABEL)).unwrap();
+
+        let points: Vec<String> = curve.iter()
+            .map(|&(freq, db)| format!("{:.1},{:.1}", self.x(freq), self.y(db)))
+            .collect();
+        writeln!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+            points.join(" "), hex(CURVE)).unwrap();
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn png(&self, curve: &[(f64, f64)]) -> Vec<u8> {
+        let mut canvas = Canvas::new(self.width as usize, self.height as usize);
+
+        for freq in self.frequency_grid() {
+            let x = self.x(freq);
+            canvas.line(x, self.top(), x, self.bottom(), GRID, 1);
+            let label = frequency_label(freq);
+            canvas.text(x - text_width(&label) / 2.0, self.bottom() + 6.0, &label, LABEL);
+        }
+        for db in self.db_grid() {
+            let y = self.y(db);
+            canvas.line(self.left(), y, self.right(), y, if db == 0.0 { AXIS } else { GRID }, 1);
+            let label = format!("{}", db);
+            canvas.text(self.left() - 5.0 - text_width(&label), y - 5.0, &label, LABEL);
+        }
+        canvas.line(self.left(), self.top(), self.right(), self.top(), AXIS, 1);
+        canvas.line(self.left(), self.bottom(), self.right(), self.bottom(), AXIS, 1);
+        canvas.line(self.left(), self.top(), self.left(), self.bottom(), AXIS, 1);
+        canvas.line(self.right(), self.top(), self.right(), self.bottom(), AXIS, 1);
+
+        for pair in curve.windows(2) {
+            canvas.line(self.x(pair[0].0), self.y(pair[0].1), self.x(pair[1].0), self.y(pair[1].1), CURVE, 2);
+        }
+        canvas.png()
+    }
+}
+
+fn hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/* 20, 500, 1k, 2k, 10k */
+fn frequency_label(freq: f64) -> String {
+    if freq >= 1000.0 {
+        format!("{}k", freq / 1000.0)
+    } else {
+        format!("{}", freq)
+    }
+}
+
+
+/* An RGB image to draw the PNG on. */
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>
+}
+
+// the glyphs of the labels are 3x5 pixels, drawn twice the size
+const FONT_SCALE: usize = 2;
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas { width: width, height: height, pixels: vec![BACKGROUND; width * height] }
+    }
+
+    fn set(&mut self, x: isize, y: isize, color: [u8; 3]) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /* A line `thickness` pixels wide. */
+    fn line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, color: [u8; 3], thickness: isize) {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+        for i in 0..steps + 1 {
+            let t = i as f64 / steps as f64;
+            let x = (x0 + (x1 - x0) * t).round() as isize;
+            let y = (y0 + (y1 - y0) * t).round() as isize;
+            for dx in 0..thickness {
+                for dy in 0..thickness {
+                    self.set(x + dx, y + dy, color);
+                }
+            }
+        }
+    }
+
+    /* `text` with its top left corner at `x`, `y`. */
+    fn text(&mut self, x: f64, y: f64, text: &str, color: [u8; 3]) {
+        let (x, y) = (x.round() as isize, y.round() as isize);
+        for (n, c) in text.chars().enumerate() {
+            let rows = glyph(c);
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (4 >> col) == 0 {
+                        continue;
+                    }
+                    for sx in 0..FONT_SCALE {
+                        for sy in 0..FONT_SCALE {
+                            self.set(x + ((n * 4 + col) * FONT_SCALE + sx) as isize,
+                                y + (row * FONT_SCALE + sy) as isize, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn png(&self) -> Vec<u8> {
+        // every row starts with filter type 0 (none)
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            raw.push(0);
+            for pixel in row {
+                raw.extend_from_slice(pixel);
+            }
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bit RGB, deflate, no filters, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn text_width(text: &str) -> f64 {
+    (text.chars().count() * 4 * FONT_SCALE) as f64
+}
+
+/* The rows of a glyph, three bits each, the leftmost pixel in bit 2. */
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 2, 2],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        '-' => [0, 0, 7, 0, 0],
+        '.' => [0, 0, 0, 0, 2],
+        'k' => [4, 5, 6, 5, 5],
+        _ => [0; 5]
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(name);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/* A zlib stream of uncompressed deflate blocks. */
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (n, block) in blocks.iter().enumerate() {
+        out.push(if n + 1 == blocks.len() { 1 } else { 0 });
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analog_eq::FilterType;
+    use response::magnitude_curve;
+    use tests::filter;
+
+    #[test]
+    fn plot_fits_the_curve() {
+        let curve = magnitude_curve(&filter(FilterType::PEAK2, 1000.0, 2.0, 0, 16.0), 20.0, 20000.0, 200);
+        let plot = Plot::new(800, 400, 20.0, 20000.0).fit(&curve);
+        assert_eq!((plot.min_db, plot.max_db), (-12.0, 18.0));
+
+        let svg = plot.svg(&curve);
+        assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+        for label in &[">20<", ">100<", ">1k<", ">20k<", ">-12<", ">0<", ">18<"] {
+            assert!(svg.contains(label), "{}", label);
+        }
+        let points = svg.split("<polyline points=\"").nth(1).unwrap().split('"').next().unwrap();
+        assert_eq!(points.split(' ').count(), 200);
+    }
+
+    #[test]
+    fn plot_png() {
+        let curve = magnitude_curve(&filter(FilterType::LPF2, 1000.0, 0.707, 0, 0.0), 20.0, 20000.0, 200);
+        let png = Plot::new(300, 200, 20.0, 20000.0).png(&curve);
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 1, 44, 0, 0, 0, 200]);
+        // IEND with its well known CRC
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+        // the stored image data: zlib header, one block header per 64k, adler32
+        let raw: usize = (300 * 3 + 1) * 200;
+        let idat = 2 + raw.div_ceil(0xffff) * 5 + raw + 4;
+        assert_eq!(&png[33..41], [&(idat as u32).to_be_bytes()[..], b"IDAT"].concat().as_slice());
+    }
+}
@@ -0,0 +1,149 @@
+/*
+   The frequency response of filters, computed from their coefficients, so
+   curves can be drawn or checked without running audio through them.
+
+       let filter = AnalogFilter::new(&FilterType::PEAK2, 1000.0, 1.0, 0, 48000, 64);
+       let gain = filter.magnitude_db(1000.0);
+       let curve = magnitude_curve(&filter, 20.0, 20000.0, 100);
+
+   Several filters in series are a slice: `[&low, &high][..].magnitude_db(f)`.
+   Frequencies are in Hz.
+*/
+
+use num::complex::Complex64;
+
+
+// magnitudes are not reported below this, e.g. in the zero of a notch
+pub const MIN_DB: f64 = -200.0;
+
+pub trait FrequencyResponse {
+    /* The complex response at `freq`. */
+    fn response(&self, freq: f64) -> Complex64;
+
+    /* The group delay at `freq` in seconds. */
+    fn group_delay(&self, freq: f64) -> f64;
+
+    fn magnitude_db(&self, freq: f64) -> f64 {
+        let magnitude = self.response(freq).norm();
+        if magnitude > 0.0 {
+            (20.0 * magnitude.log10()).max(MIN_DB)
+        } else {
+            MIN_DB
+        }
+    }
+
+    /* The phase at `freq` in radians, between -pi and pi. */
+    fn phase(&self, freq: f64) -> f64 {
+        self.response(freq).arg()
+    }
+}
+
+impl<R: FrequencyResponse + ?Sized> FrequencyResponse for &R {
+    fn response(&self, freq: f64) -> Complex64 {
+        (**self).response(freq)
+    }
+
+    fn group_delay(&self, freq: f64) -> f64 {
+        (**self).group_delay(freq)
+    }
+}
+
+/* Filters in series: the responses multiply, the delays add up. */
+impl<R: FrequencyResponse> FrequencyResponse for [R] {
+    fn response(&self, freq: f64) -> Complex64 {
+        self.iter().fold(Complex64::new(1.0, 0.0), |h, filter| h * filter.response(freq))
+    }
+
+    fn group_delay(&self, freq: f64) -> f64 {
+        self.iter().map(|filter| filter.group_delay(freq)).sum()
+    }
+}
+
+
+/* `n` frequencies from `from` to `to`, evenly spaced on a log scale. */
+pub fn log_frequencies(from: f64, to: f64, n: usize) -> Vec<f64> {
+    (0..n).map(|i| log_frequency(from, to, n, i)).collect()
+}
+
+/* The `i`th of `log_frequencies(from, to, n)`, without allocating. */
+pub fn log_frequency(from: f64, to: f64, n: usize, i: usize) -> f64 {
+    if n < 2 {
+        return from;
+    }
+    from * ((to / from).ln() * i as f64 / (n - 1) as f64).exp()
+}
+
+/* The magnitude in dB at `n` log spaced frequencies, as (frequency, dB). */
+pub fn magnitude_curve<R: FrequencyResponse + ?Sized>(filter: &R, from: f64, to: f64, n: usize) -> Vec<(f64, f64)> {
+    log_frequencies(from, to, n).into_iter()
+        .map(|freq| (freq, filter.magnitude_db(freq)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analog_eq::FilterType;
+    use std::f32::consts::PI;
+    use tests::{filter, level_db, sine};
+
+    #[test]
+    fn response_matches_audio() {
+        for ftype in 0..14 {
+            let mut f = filter(FilterType::from_u32(ftype), 1000.0, 2.0, 1, 6.0);
+            for &freq in &[200.0, 1000.0, 3000.0] {
+                let input = sine(freq, 9600);
+                let mut out = vec![0.0; input.len()];
+                f.filterout(&input, &mut out);
+
+                let measured = level_db(&out, &input, 4800);
+                let predicted = f.magnitude_db(freq as f64) as f32;
+                // the zero of the notch is below what f32 audio can measure
+                let close = if predicted < -60.0 { measured < -60.0 } else { (measured - predicted).abs() < 0.1 };
+                assert!(close, "type {} at {} Hz: {} dB, predicted {} dB", ftype, freq, measured, predicted);
+            }
+        }
+    }
+
+    #[test]
+    fn group_delay_is_phase_slope() {
+        for ftype in 0..14 {
+            let f = filter(FilterType::from_u32(ftype), 1000.0, 2.0, 0, 6.0);
+            for &freq in &[100.0, 700.0, 5000.0] {
+                // -dphase/domega, with the phase unwrapped over the small step
+                let step = 0.01;
+                let mut dphase = f.phase(freq + step) - f.phase(freq - step);
+                if dphase > PI as f64 { dphase -= 2.0 * PI as f64 }
+                if dphase < -PI as f64 { dphase += 2.0 * PI as f64 }
+                let slope = -dphase / (2.0 * PI as f64 * 2.0 * step);
+
+                assert!((f.group_delay(freq) - slope).abs() < 1e-6, "type {} at {} Hz", ftype, freq);
+            }
+        }
+    }
+
+    #[test]
+    fn cascade_response() {
+        let low = filter(FilterType::LoShelf, 100.0, 0.707, 0, 6.0);
+        let peak = filter(FilterType::PEAK2, 1000.0, 1.0, 1, -3.0);
+        let both = [&low, &peak];
+
+        for &freq in &[50.0, 1000.0, 10000.0] {
+            assert!((both[..].magnitude_db(freq) - low.magnitude_db(freq) - peak.magnitude_db(freq)).abs() < 1e-9);
+            assert!((both[..].group_delay(freq) - low.group_delay(freq) - peak.group_delay(freq)).abs() < 1e-12);
+        }
+        assert!((both[..].magnitude_db(10.0) - 6.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn log_spaced_curve() {
+        let f = filter(FilterType::LPF2, 1000.0, 0.707, 0, 0.0);
+        let curve = magnitude_curve(&f, 20.0, 20000.0, 31);
+
+        assert_eq!(curve.len(), 31);
+        assert!((curve[0].0 - 20.0).abs() < 1e-9 && (curve[30].0 - 20000.0).abs() < 1e-6);
+        assert!((curve[10].0 - 200.0).abs() < 1e-9 && (curve[20].0 - 2000.0).abs() < 1e-6);
+        assert!(curve[0].1.abs() < 0.01 && (curve[20].1 + 12.0).abs() < 0.5);
+        assert!(curve.windows(2).all(|w| w[1].1 <= w[0].1));
+    }
+}
@@ -0,0 +1,155 @@
+/*
+   A vowel filter for talk box like effects: the formant filter of
+   ZynAddSubFX on both channels.  Sweeping the vowel control morphs the
+   formants from A over E, I and O to U.
+*/
+
+use rustlv2::*;
+use libc::c_void;
+
+use formant::*;
+
+
+const VOWEL_URI: &'static [u8] = b"http://example.org/rustvowel\0";
+
+port_index! {
+    pub(crate) enum PortIndex {
+        VowelInputL = 0,
+        VowelInputR = 1,
+        VowelOutputL = 2,
+        VowelOutputR = 3,
+        VowelPosition = 4,
+        VowelShift = 5,
+        VowelQ = 6,
+        VowelSmoothing = 7
+    }
+}
+
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::audio_in(PortIndex::VowelInputL as u32, "inl", "In L"),
+    PortInfo::audio_in(PortIndex::VowelInputR as u32, "inr", "In R"),
+    PortInfo::audio_out(PortIndex::VowelOutputL as u32, "outl", "Out L"),
+    PortInfo::audio_out(PortIndex::VowelOutputR as u32, "outr", "Out R"),
+    PortInfo::control_in(PortIndex::VowelPosition as u32, "vowel", "Vowel", 0.0, 0.0, 4.0)
+        .properties(&["epp:hasStrictBounds"])
+        .scale_points(&[("A", 0.0), ("E", 1.0), ("I", 2.0), ("O", 3.0), ("U", 4.0)]),
+    PortInfo::control_in(PortIndex::VowelShift as u32, "shift", "Formant Shift", 0.0, -12.0, 12.0)
+        .unit("units:semitone12TET")
+        .properties(&["epp:hasStrictBounds"]),
+    PortInfo::control_in(PortIndex::VowelQ as u32, "q", "Q", 1.0, 0.25, 4.0)
+        .unit("units:coef")
+        .properties(&["epp:hasStrictBounds", "epp:logarithmic"]),
+    PortInfo::control_in(PortIndex::VowelSmoothing as u32, "smoothing", "Smoothing", 50.0, 0.0, 1000.0)
+        .unit("units:ms")
+        .properties(&["epp:hasStrictBounds"])
+];
+
+
+pub struct VowelFilter {
+    input_l: AudioIn,
+    input_r: AudioIn,
+    output_l: AudioOut,
+    output_r: AudioOut,
+    position: ControlIn,
+    shift: ControlIn,
+    q: ControlIn,
+    smoothing: ControlIn,
+    filter_l: FormantFilter,
+    filter_r: FormantFilter,
+    activated: bool
+}
+
+impl Plugin for VowelFilter {
+    const URI: &'static [u8] = VOWEL_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust vowel filter",
+        classes: &["lv2:FilterPlugin"],
+        project: "http://example.org",
+        maintainer: Some("Michael Oswald"),
+        license: "http://opensource.org/licenses/isc",
+        required_features: &[],
+        optional_features: &["lv2:hardRTCapable"],
+        extension_data: &[],
+        supported_options: &[],
+        ports: PORTS,
+        parameters: &[]
+    };
+
+    fn instantiate(rate: f64, _features: &Features) -> Option<VowelFilter> {
+        Some(VowelFilter {
+            input_l: AudioIn::new(),
+            input_r: AudioIn::new(),
+            output_l: AudioOut::new(),
+            output_r: AudioOut::new(),
+            position: ControlIn::new(),
+            shift: ControlIn::new(),
+            q: ControlIn::new(),
+            smoothing: ControlIn::new(),
+            filter_l: FormantFilter::new(rate as u32),
+            filter_r: FormantFilter::new(rate as u32),
+            activated: false
+        })
+    }
+
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::VowelInputL) => self.input_l.connect(data),
+            Some(PortIndex::VowelInputR) => self.input_r.connect(data),
+            Some(PortIndex::VowelOutputL) => self.output_l.connect(data),
+            Some(PortIndex::VowelOutputR) => self.output_r.connect(data),
+            Some(PortIndex::VowelPosition) => self.position.connect(data),
+            Some(PortIndex::VowelShift) => self.shift.connect(data),
+            Some(PortIndex::VowelQ) => self.q.connect(data),
+            Some(PortIndex::VowelSmoothing) => self.smoothing.connect(data),
+            None => ()
+        }
+    }
+
+    /* The first run starts at the vowel set then, without gliding to it. */
+    fn activate(&mut self) {
+        self.activated = true;
+    }
+
+    fn run(&mut self, n_samples: u32) {
+        let n = n_samples as usize;
+
+        let position = self.position.value();
+        let shift = self.shift.value();
+        let q = self.q.value();
+        let smoothing = self.smoothing.value() as f64 / 1000.0;
+        for filter in [&mut self.filter_l, &mut self.filter_r].iter_mut() {
+            if self.activated {
+                filter.cleanup();
+            }
+            filter.set_target(position, shift, q);
+            filter.set_smoothing(smoothing);
+        }
+        self.activated = false;
+
+        self.filter_l.filterout(self.input_l.as_slice(n), self.output_l.as_mut_slice(n));
+        self.filter_r.filterout(self.input_r.as_slice(n), self.output_r.as_mut_slice(n));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use super::*;
+    use tests::{instantiate_with_defaults, level_db, run_stereo, sine};
+
+    #[test]
+    fn vowel_plugin() {
+        let host = TestHost::new();
+        let mut plugin = instantiate_with_defaults::<VowelFilter>(&host, "http://example.org/rustvowel");
+        plugin.activate();
+
+        let input = sine(650.0, 4800);
+        run_stereo(&mut plugin, (PortIndex::VowelInputL as u32, PortIndex::VowelInputR as u32),
+            &input, &vec![0.0; input.len()]);
+        let level = level_db(plugin.audio_output(PortIndex::VowelOutputL as u32), &input, 2400);
+        // A, its first formant and a bit of the second
+        assert!(level.abs() < 1.0, "{} dB", level);
+        assert!(plugin.audio_output(PortIndex::VowelOutputR as u32).iter().all(|x| *x == 0.0));
+    }
+}
@@ -0,0 +1,30 @@
+/*
+   Compiles the C part of the test host, the variadic functions of its
+   log:log feature, with the C compiler and archiver of the toolchain.
+*/
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap_or_else(|err| panic!("{:?}: {}", command, err));
+    assert!(status.success(), "{:?}: {}", command, status);
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/host_log.c");
+    if env::var_os("CARGO_FEATURE_TESTHOST").is_none() {
+        return;
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let object = out.join("host_log.o");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let ar = env::var("AR").unwrap_or_else(|_| "ar".to_string());
+    run(Command::new(cc).args(["-c", "-fPIC", "-O2", "-o"]).arg(&object).arg("src/host_log.c"));
+    run(Command::new(ar).arg("crs").arg(out.join("libtesthost_log.a")).arg(&object));
+
+    println!("cargo:rustc-link-search=native={}", out.display());
+    println!("cargo:rustc-link-lib=static=testhost_log");
+}
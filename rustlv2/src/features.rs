@@ -0,0 +1,70 @@
+use lv2_raw::*;
+use libc::{c_char, c_void};
+use std::ffi::CStr;
+
+
+/*
+   The NULL terminated feature array passed to `instantiate`.  Feature URIs
+   are given as the NUL terminated byte strings used throughout `lv2_raw`.
+*/
+pub struct Features {
+    features: *const *const LV2Feature
+}
+
+impl Features {
+    pub(crate) unsafe fn from_raw(features: *const *const LV2Feature) -> Features {
+        Features { features: features }
+    }
+
+    /*
+       Return the data for the feature `uri`, or `None` if the host did not
+       provide it.
+    */
+    pub fn data(&self, uri: &[u8]) -> Option<*mut c_void> {
+        if self.features.is_null() {
+            return None;
+        }
+
+        let uri = CStr::from_bytes_with_nul(uri).ok()?;
+        let mut i = 0;
+        unsafe {
+            loop {
+                let feature = *self.features.offset(i);
+                if feature.is_null() {
+                    return None;
+                }
+                if CStr::from_ptr((*feature).uri) == uri {
+                    return Some((*feature).data);
+                }
+                i += 1;
+            }
+        }
+    }
+
+    pub fn urid_map(&self) -> Option<UridMap> {
+        let uri = [LV2_URID__MAP.as_bytes(), b"\0"].concat();
+        match self.data(&uri) {
+            Some(data) if !data.is_null() => Some(UridMap { map: data as *const LV2UridMap }),
+            _ => None
+        }
+    }
+}
+
+
+/*
+   The host's `urid:map` feature.  `uri` must be NUL terminated.  Mapping is
+   not real-time safe, so all URIDs should be mapped in `instantiate` and
+   cached.
+*/
+#[derive(Clone, Copy)]
+pub struct UridMap {
+    map: *const LV2UridMap
+}
+
+impl UridMap {
+    pub fn map(&self, uri: &[u8]) -> LV2Urid {
+        unsafe {
+            ((*self.map).map)((*self.map).handle, uri.as_ptr() as *const c_char)
+        }
+    }
+}
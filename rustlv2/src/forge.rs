@@ -0,0 +1,187 @@
+/*
+   Writing events to an atom output port.  The host puts the capacity of the
+   buffer into the size of the sequence atom before every run, the plugin
+   replaces it with a sequence holding its events, at least an empty one.
+
+       let mut seq = forge.sequence(&mut self.notify);
+       seq.object(0, self.curve_type, |obj| {
+           obj.float(self.min_freq, 20.0);
+           obj.float_vector(self.magnitude, 64, |i| magnitude(i));
+       });
+
+   Nothing is allocated, an event that does not fit into the buffer is left
+   out.  Bodies are padded to 64 bits as the atom spec requires.
+*/
+
+use lv2_raw::*;
+use std::mem;
+
+use features::UridMap;
+use ports::AtomOut;
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Forge {
+    atom_float: LV2Urid,
+    atom_int: LV2Urid,
+    atom_object: LV2Urid,
+    atom_sequence: LV2Urid,
+    atom_vector: LV2Urid
+}
+
+impl Forge {
+    pub fn new(map: &UridMap) -> Forge {
+        Forge {
+            atom_float: map.map(LV2_ATOM__FLOAT),
+            atom_int: map.map(LV2_ATOM__INT),
+            atom_object: map.map(LV2_ATOM__OBJECT),
+            atom_sequence: map.map(LV2_ATOM__SEQUENCE),
+            atom_vector: map.map(LV2_ATOM__VECTOR)
+        }
+    }
+
+    /*
+       Start the sequence of this run on `port`, replacing what the host put
+       there.  `None` if the port is not connected or too small even for an
+       empty sequence.
+    */
+    pub fn sequence<'a>(&self, port: &'a mut AtomOut) -> Option<Sequence<'a>> {
+        let buf = port.as_mut_bytes()?;
+        if buf.len() < mem::size_of::<LV2AtomSequence>() {
+            return None;
+        }
+
+        let mut seq = Sequence { forge: *self, buf: buf, len: 0 };
+        seq.put_u32(0);
+        seq.put_u32(self.atom_sequence);
+        // frames as time unit, no padding
+        seq.put_u32(0);
+        seq.put_u32(0);
+        seq.finish();
+        Some(seq)
+    }
+}
+
+
+pub struct Sequence<'a> {
+    forge: Forge,
+    buf: &'a mut [u8],
+    // bytes written, including the atom header
+    len: usize
+}
+
+impl<'a> Sequence<'a> {
+    /*
+       Append an atom:Object of type `otype` at `frames`, `props` adds its
+       properties.  Returns false if the event did not fit and was left out.
+    */
+    pub fn object<F>(&mut self, frames: i64, otype: LV2Urid, props: F) -> bool
+        where F: FnOnce(&mut Object) {
+
+        let start = self.len;
+        let mut obj = Object { writer: Writer { buf: &mut *self.buf, len: start, overflow: false },
+            forge: self.forge };
+
+        obj.writer.put(&frames.to_ne_bytes());
+        let size_at = obj.writer.len;
+        obj.writer.put_u32(0);
+        obj.writer.put_u32(self.forge.atom_object);
+        // blank id
+        obj.writer.put_u32(0);
+        obj.writer.put_u32(otype);
+        props(&mut obj);
+
+        let Writer { len, overflow, .. } = obj.writer;
+        if overflow {
+            return false;
+        }
+        let body = (len - size_at - mem::size_of::<LV2Atom>()) as u32;
+        self.buf[size_at..size_at + 4].copy_from_slice(&body.to_ne_bytes());
+        self.len = len;
+        self.finish();
+        true
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.buf[self.len..self.len + 4].copy_from_slice(&value.to_ne_bytes());
+        self.len += 4;
+    }
+
+    // the sequence size counts everything after its atom header
+    fn finish(&mut self) {
+        let size = (self.len - mem::size_of::<LV2Atom>()) as u32;
+        self.buf[0..4].copy_from_slice(&size.to_ne_bytes());
+    }
+}
+
+
+/* The properties of an object being written. */
+pub struct Object<'b> {
+    writer: Writer<'b>,
+    forge: Forge
+}
+
+impl<'b> Object<'b> {
+    pub fn float(&mut self, key: LV2Urid, value: f32) {
+        self.property(key, 4, self.forge.atom_float);
+        self.writer.put(&value.to_ne_bytes());
+        self.writer.pad();
+    }
+
+    pub fn int(&mut self, key: LV2Urid, value: i32) {
+        self.property(key, 4, self.forge.atom_int);
+        self.writer.put(&value.to_ne_bytes());
+        self.writer.pad();
+    }
+
+    /* An atom:Vector of `n` floats, element `i` is `value(i)`. */
+    pub fn float_vector<F>(&mut self, key: LV2Urid, n: usize, mut value: F)
+        where F: FnMut(usize) -> f32 {
+
+        let size = mem::size_of::<LV2AtomVectorBody>() + n * 4;
+        self.property(key, size as u32, self.forge.atom_vector);
+        self.writer.put_u32(4);
+        self.writer.put_u32(self.forge.atom_float);
+        for i in 0..n {
+            self.writer.put(&value(i).to_ne_bytes());
+        }
+        self.writer.pad();
+    }
+
+    fn property(&mut self, key: LV2Urid, size: u32, vtype: LV2Urid) {
+        self.writer.put_u32(key);
+        // no context
+        self.writer.put_u32(0);
+        self.writer.put_u32(size);
+        self.writer.put_u32(vtype);
+    }
+}
+
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    // set once something did not fit, everything after is dropped
+    overflow: bool
+}
+
+impl<'b> Writer<'b> {
+    fn put(&mut self, bytes: &[u8]) {
+        if self.overflow || self.len + bytes.len() > self.buf.len() {
+            self.overflow = true;
+            return;
+        }
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.put(&value.to_ne_bytes());
+    }
+
+    fn pad(&mut self) {
+        while !self.overflow && !self.len.is_multiple_of(8) {
+            self.put(&[0]);
+        }
+    }
+}
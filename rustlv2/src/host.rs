@@ -0,0 +1,632 @@
+/*
+   A minimal in-process LV2 host for tests and for lv2check.
+
+   It hands a plugin's `LV2Descriptor` the features a real host would offer
+   (`urid:map`, `log:log` and optionally `options:options`), owns the buffers for all connected ports and runs
+   the plugin block by block, so the plugins can be tested end to end without
+   a DAW:
+
+       let host = TestHost::new();
+       let mut amp = host.instantiate(lv2_descriptor, "http://example.org/rustamp", 48000.0).unwrap();
+       amp.set_control(0, 0.0);
+       amp.set_audio_input(1, &input);
+       amp.connect_audio_output(2);
+       amp.activate();
+       amp.run(input.len());
+       let output = amp.audio_output(2);
+*/
+
+use lv2_raw::*;
+use libc::{c_char, c_int, c_void};
+use log::*;
+use options::*;
+use patch::*;
+use state::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+
+
+/*
+   The URID table behind the `urid:map` feature.  URIDs are simply the
+   position in the table plus one, as 0 is reserved.
+*/
+struct UridTable {
+    uris: RefCell<Vec<CString>>
+}
+
+impl UridTable {
+    fn map(&self, uri: &CStr) -> LV2Urid {
+        let mut uris = self.uris.borrow_mut();
+        match uris.iter().position(|u| u.as_c_str() == uri) {
+            Some(pos) => (pos + 1) as LV2Urid,
+            None => {
+                uris.push(uri.to_owned());
+                uris.len() as LV2Urid
+            }
+        }
+    }
+}
+
+extern "C" fn map_uri(handle: LV2UridMapHandle, uri: *const c_char) -> LV2Urid {
+    let table = unsafe { &*(handle as *const UridTable) };
+    table.map(unsafe { CStr::from_ptr(uri) })
+}
+
+
+/*
+   The messages written through `log:log`, with their type.  Plugins log
+   from their own threads, hence the mutex.
+*/
+type LogMessages = Mutex<Vec<(LV2Urid, String)>>;
+
+/*
+   The handle of the log feature.  Stable Rust can not define variadic
+   functions, so printf and vprintf are written in C (host_log.c), they
+   format the message and pass it on to `log_message`.
+*/
+#[repr(C)]
+struct LogHandle {
+    message: extern "C" fn(data: *mut c_void, ltype: LV2Urid, msg: *const c_char),
+    data: *mut c_void
+}
+
+extern "C" {
+    fn testhost_log_printf(handle: LV2LogHandle, ltype: LV2Urid, fmt: *const c_char, ...) -> c_int;
+    fn testhost_log_vprintf(handle: LV2LogHandle, ltype: LV2Urid, fmt: *const c_char, args: *mut c_void) -> c_int;
+}
+
+extern "C" fn log_message(data: *mut c_void, ltype: LV2Urid, msg: *const c_char) {
+    let messages = unsafe { &*(data as *const LogMessages) };
+    let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy().into_owned();
+    messages.lock().unwrap().push((ltype, msg));
+}
+
+
+pub struct TestHost {
+    urids: Box<UridTable>,
+    map: Box<LV2UridMap>,
+    messages: Box<LogMessages>,
+    // only pointed to by `log`
+    _log_handle: Box<LogHandle>,
+    log: Box<LV2LogLog>,
+    // the options array and the values it points to
+    options: Vec<LV2OptionsOption>,
+    max_block_length: Box<i32>,
+    features: Vec<LV2Feature>,
+    feature_uris: Vec<CString>
+}
+
+impl TestHost {
+    /* A host offering all features it knows about. */
+    pub fn new() -> TestHost {
+        let mut host = TestHost::bare();
+        let map = &*host.map as *const LV2UridMap as *mut c_void;
+        host.add_feature(LV2_URID__MAP.as_bytes(), map);
+        let log = &*host.log as *const LV2LogLog as *mut c_void;
+        host.add_feature(LV2_LOG__LOG, log);
+        host
+    }
+
+    /*
+       A host which also tells the plugin through `options:options` that it
+       never runs more than `length` samples at once.
+    */
+    pub fn with_max_block_length(length: i32) -> TestHost {
+        let mut host = TestHost::new();
+        *host.max_block_length = length;
+        host.options.push(LV2OptionsOption {
+            context: LV2_OPTIONS_INSTANCE,
+            subject: 0,
+            key: host.map(LV2_BUF_SIZE__MAX_BLOCK_LENGTH),
+            size: mem::size_of::<i32>() as u32,
+            otype: host.map(LV2_ATOM__INT),
+            value: &*host.max_block_length as *const i32 as *const c_void
+        });
+        host.options.push(LV2OptionsOption {
+            context: LV2_OPTIONS_INSTANCE, subject: 0, key: 0, size: 0, otype: 0, value: ptr::null()
+        });
+
+        let options = host.options.as_ptr() as *mut c_void;
+        host.add_feature(LV2_OPTIONS__OPTIONS, options);
+        host
+    }
+
+    /*
+       A host which offers no features at all, e.g. to check that a plugin
+       refuses to instantiate without a required feature.
+    */
+    pub fn bare() -> TestHost {
+        let urids = Box::new(UridTable { uris: RefCell::new(Vec::new()) });
+        let map = Box::new(LV2UridMap {
+            handle: &*urids as *const UridTable as LV2UridMapHandle,
+            map: map_uri
+        });
+        let messages: Box<LogMessages> = Box::new(Mutex::new(Vec::new()));
+        let log_handle = Box::new(LogHandle {
+            message: log_message,
+            data: &*messages as *const LogMessages as *mut c_void
+        });
+        let log = Box::new(LV2LogLog {
+            handle: &*log_handle as *const LogHandle as LV2LogHandle,
+            printf: testhost_log_printf,
+            vprintf: testhost_log_vprintf as *const c_void
+        });
+
+        TestHost {
+            urids: urids,
+            map: map,
+            messages: messages,
+            _log_handle: log_handle,
+            log: log,
+            options: Vec::new(),
+            max_block_length: Box::new(0),
+            features: Vec::new(),
+            feature_uris: Vec::new()
+        }
+    }
+
+    /*
+       Offer an additional feature.  `data` has to stay valid for as long as
+       the host is used.
+    */
+    pub fn add_feature(&mut self, uri: &[u8], data: *mut c_void) {
+        let uri = CString::new(uri.split(|b| *b == 0).next().unwrap_or(uri)).unwrap();
+        self.features.push(LV2Feature { uri: uri.as_ptr(), data: data });
+        self.feature_uris.push(uri);
+    }
+
+    /* Map a URI (with or without trailing NUL) the way the plugin sees it. */
+    pub fn map(&self, uri: &[u8]) -> LV2Urid {
+        let uri = CString::new(uri.split(|b| *b == 0).next().unwrap_or(uri)).unwrap();
+        self.urids.map(&uri)
+    }
+
+    /*
+       The messages logged so far, as the URI of their type (e.g.
+       `LV2_LOG__NOTE`) and the text.  Messages from `run` are only written
+       by the logger thread, they are complete once the instance is dropped.
+    */
+    pub fn log_messages(&self) -> Vec<(LV2Urid, String)> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /*
+       Look up the plugin `uri` through the library's `lv2_descriptor()` entry
+       point, like a host does after loading the library, and instantiate it.
+    */
+    pub fn instantiate(&self, entry: extern "C" fn(u32) -> *const LV2Descriptor,
+        uri: &str, rate: f64) -> Option<Instance<'_>> {
+
+        let descriptor = find_descriptor(entry, uri)?;
+        let features = self.feature_array();
+
+        let bundle = CString::new("/tmp/").unwrap();
+        let handle = (descriptor.instantiate)(descriptor, rate, bundle.as_ptr(), features.as_ptr());
+
+        if handle.is_null() {
+            None
+        } else {
+            Some(Instance {
+                host: self,
+                descriptor: descriptor,
+                handle: handle,
+                active: false,
+                buffers: HashMap::new()
+            })
+        }
+    }
+
+    /* The NULL terminated feature array handed to the plugin. */
+    pub(crate) fn feature_array(&self) -> Vec<*const LV2Feature> {
+        let mut features: Vec<*const LV2Feature> = self.features.iter()
+            .map(|f| f as *const LV2Feature)
+            .collect();
+        features.push(ptr::null());
+        features
+    }
+}
+
+fn find_descriptor(entry: extern "C" fn(u32) -> *const LV2Descriptor,
+    uri: &str) -> Option<&'static LV2Descriptor> {
+
+    let mut index = 0;
+    loop {
+        let descriptor = entry(index);
+        if descriptor.is_null() {
+            return None;
+        }
+        // descriptors are static data of the plugin library
+        let descriptor = unsafe { &*descriptor };
+        if unsafe { CStr::from_ptr(descriptor.uri) }.to_bytes() == uri.as_bytes() {
+            return Some(descriptor);
+        }
+        index += 1;
+    }
+}
+
+impl Default for TestHost {
+    fn default() -> TestHost {
+        TestHost::new()
+    }
+}
+
+
+/* An event to be put into an atom sequence input. */
+pub struct AtomEvent {
+    pub frames: i64,
+    pub mytype: LV2Urid,
+    pub body: Vec<u8>
+}
+
+/* The value of an object property, URIs are mapped when the event is built. */
+#[derive(Clone, Copy, Debug)]
+pub enum AtomValue<'a> {
+    Float(f32),
+    Int(i32),
+    Urid(&'a [u8])
+}
+
+impl AtomEvent {
+    pub fn midi(host: &TestHost, frames: i64, msg: &[u8]) -> AtomEvent {
+        AtomEvent {
+            frames: frames,
+            mytype: host.map(LV2_MIDI__MIDIEVENT),
+            body: msg.to_vec()
+        }
+    }
+
+    /* An atom:Object of type `otype`. */
+    pub fn object(host: &TestHost, frames: i64, otype: &[u8], props: &[(&[u8], AtomValue)]) -> AtomEvent {
+        let mut body = Vec::new();
+
+        push_u32(&mut body, 0);
+        push_u32(&mut body, host.map(otype));
+        for &(key, value) in props {
+            push_u32(&mut body, host.map(key));
+            push_u32(&mut body, 0);
+            push_u32(&mut body, 4);
+            match value {
+                AtomValue::Float(value) => {
+                    push_u32(&mut body, host.map(LV2_ATOM__FLOAT));
+                    body.extend_from_slice(&value.to_ne_bytes());
+                },
+                AtomValue::Int(value) => {
+                    push_u32(&mut body, host.map(LV2_ATOM__INT));
+                    body.extend_from_slice(&value.to_ne_bytes());
+                },
+                AtomValue::Urid(uri) => {
+                    push_u32(&mut body, host.map(LV2_ATOM__URID));
+                    push_u32(&mut body, host.map(uri));
+                }
+            }
+            pad(&mut body);
+        }
+
+        AtomEvent {
+            frames: frames,
+            mytype: host.map(LV2_ATOM__OBJECT),
+            body: body
+        }
+    }
+
+    /*
+       An object whose properties are all atom:Float, like the time:Position
+       objects sent by hosts.
+    */
+    pub fn float_object(host: &TestHost, frames: i64, otype: &[u8], props: &[(&[u8], f32)]) -> AtomEvent {
+        let props: Vec<(&[u8], AtomValue)> = props.iter()
+            .map(|&(key, value)| (key, AtomValue::Float(value)))
+            .collect();
+        AtomEvent::object(host, frames, otype, &props)
+    }
+
+    /* A patch:Set message setting `property` to `value`. */
+    pub fn patch_set(host: &TestHost, frames: i64, property: &[u8], value: AtomValue) -> AtomEvent {
+        AtomEvent::object(host, frames, LV2_PATCH__SET,
+            &[(LV2_PATCH__PROPERTY, AtomValue::Urid(property)), (LV2_PATCH__VALUE, value)])
+    }
+
+    /* The type of an atom:Object event. */
+    pub fn object_type(&self) -> LV2Urid {
+        read_u32(&self.body, 4)
+    }
+
+    /* Type and body of the property `key` of an atom:Object event. */
+    pub fn property(&self, host: &TestHost, key: &[u8]) -> Option<(LV2Urid, &[u8])> {
+        let key = host.map(key);
+        let mut pos = mem::size_of::<LV2AtomObjectBody>();
+        while pos < self.body.len() {
+            let size = read_u32(&self.body, pos + 8) as usize;
+            let value = pos + mem::size_of::<LV2AtomPropertyBody>();
+            if read_u32(&self.body, pos) == key {
+                return Some((read_u32(&self.body, pos + 12), &self.body[value..value + size]));
+            }
+            pos = (value + size).next_multiple_of(8);
+        }
+        None
+    }
+
+    pub fn float_property(&self, host: &TestHost, key: &[u8]) -> Option<f32> {
+        match self.property(host, key) {
+            Some((vtype, body)) if vtype == host.map(LV2_ATOM__FLOAT) => Some(f32::from_bits(read_u32(body, 0))),
+            _ => None
+        }
+    }
+
+    /* The elements of an atom:Vector of floats. */
+    pub fn float_vector_property(&self, host: &TestHost, key: &[u8]) -> Option<Vec<f32>> {
+        match self.property(host, key) {
+            Some((vtype, body)) if vtype == host.map(LV2_ATOM__VECTOR)
+                && read_u32(body, 4) == host.map(LV2_ATOM__FLOAT) => {
+
+                Some(body[mem::size_of::<LV2AtomVectorBody>()..].chunks(4)
+                    .map(|bytes| f32::from_bits(read_u32(bytes, 0)))
+                    .collect())
+            },
+            _ => None
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[pos..pos + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(8) {
+        buf.push(0);
+    }
+}
+
+
+enum Buffer {
+    Control(Box<f32>),
+    Audio(Vec<f32>),
+    AudioOut(Vec<f32>),
+    // u64 keeps the sequence 64-bit aligned, as required by the atom spec
+    Atom(Vec<u64>),
+    AtomOut(Vec<u64>)
+}
+
+impl Buffer {
+    fn as_ptr(&mut self) -> *mut c_void {
+        match *self {
+            Buffer::Control(ref mut value) => &mut **value as *mut f32 as *mut c_void,
+            Buffer::Audio(ref mut data) => data.as_mut_ptr() as *mut c_void,
+            Buffer::AudioOut(ref mut data) => data.as_mut_ptr() as *mut c_void,
+            Buffer::Atom(ref mut data) => data.as_mut_ptr() as *mut c_void,
+            Buffer::AtomOut(ref mut data) => data.as_mut_ptr() as *mut c_void
+        }
+    }
+}
+
+
+/*
+   A plugin instance together with the buffers of its ports.  Ports are
+   identified by their index, buffers are (re)connected before every run.
+*/
+pub struct Instance<'h> {
+    host: &'h TestHost,
+    descriptor: &'static LV2Descriptor,
+    handle: LV2Handle,
+    active: bool,
+    buffers: HashMap<u32, Buffer>
+}
+
+impl<'h> Instance<'h> {
+    pub fn set_control(&mut self, port: u32, value: f32) {
+        self.buffers.insert(port, Buffer::Control(Box::new(value)));
+    }
+
+    pub fn set_audio_input(&mut self, port: u32, data: &[f32]) {
+        self.buffers.insert(port, Buffer::Audio(data.to_vec()));
+    }
+
+    /* Connect an audio output, it is sized to the block length in `run`. */
+    pub fn connect_audio_output(&mut self, port: u32) {
+        self.buffers.insert(port, Buffer::AudioOut(Vec::new()));
+    }
+
+    /* Fill an atom sequence input with `events`, which must be sorted by time. */
+    pub fn set_atom_input(&mut self, port: u32, events: &[AtomEvent]) {
+        let mut seq = Vec::new();
+
+        push_u32(&mut seq, 0);
+        push_u32(&mut seq, self.host.map(LV2_ATOM__SEQUENCE));
+        push_u32(&mut seq, 0);
+        push_u32(&mut seq, 0);
+        for ev in events {
+            seq.extend_from_slice(&ev.frames.to_ne_bytes());
+            push_u32(&mut seq, ev.body.len() as u32);
+            push_u32(&mut seq, ev.mytype);
+            seq.extend_from_slice(&ev.body);
+            pad(&mut seq);
+        }
+
+        // atom size counts the body, i.e. everything after the atom header
+        let size = (seq.len() - mem::size_of::<LV2Atom>()) as u32;
+        seq[0..4].copy_from_slice(&size.to_ne_bytes());
+
+        let mut data = vec![0u64; seq.len() / 8];
+        for (word, bytes) in data.iter_mut().zip(seq.chunks(8)) {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(bytes);
+            *word = u64::from_ne_bytes(b);
+        }
+        self.buffers.insert(port, Buffer::Atom(data));
+    }
+
+    /*
+       Connect an atom output of `capacity` bytes.  Like a host, `run` hands
+       it over as an empty atom:Chunk of that size.
+    */
+    pub fn connect_atom_output(&mut self, port: u32, capacity: usize) {
+        self.buffers.insert(port, Buffer::AtomOut(vec![0u64; capacity.div_ceil(8)]));
+    }
+
+    /* The events the plugin wrote to an atom output in the last run. */
+    pub fn atom_output(&self, port: u32) -> Vec<AtomEvent> {
+        let data = match self.buffers.get(&port) {
+            Some(Buffer::AtomOut(data)) => data,
+            _ => panic!("port {} is not a connected atom output", port)
+        };
+        let bytes: Vec<u8> = data.iter().flat_map(|word| word.to_ne_bytes()).collect();
+        assert_eq!(read_u32(&bytes, 4), self.host.map(LV2_ATOM__SEQUENCE), "the output is no sequence");
+
+        let end = mem::size_of::<LV2Atom>() + read_u32(&bytes, 0) as usize;
+        let mut events = Vec::new();
+        let mut pos = mem::size_of::<LV2AtomSequence>();
+        while pos < end {
+            let mut frames = [0u8; 8];
+            frames.copy_from_slice(&bytes[pos..pos + 8]);
+            let size = read_u32(&bytes, pos + 8) as usize;
+            let body = pos + 16;
+            events.push(AtomEvent {
+                frames: i64::from_ne_bytes(frames),
+                mytype: read_u32(&bytes, pos + 12),
+                body: bytes[body..body + size].to_vec()
+            });
+            pos = (body + size).next_multiple_of(8);
+        }
+        events
+    }
+
+    pub fn control(&self, port: u32) -> f32 {
+        match self.buffers.get(&port) {
+            Some(Buffer::Control(value)) => **value,
+            _ => panic!("port {} is not a connected control port", port)
+        }
+    }
+
+    pub fn audio_output(&self, port: u32) -> &[f32] {
+        match self.buffers.get(&port) {
+            Some(Buffer::AudioOut(data)) => data,
+            _ => panic!("port {} is not a connected audio output", port)
+        }
+    }
+
+    pub fn activate(&mut self) {
+        if let Some(activate) = self.descriptor.activate {
+            activate(self.handle);
+        }
+        self.active = true;
+    }
+
+    pub fn deactivate(&mut self) {
+        if let Some(deactivate) = self.descriptor.deactivate {
+            deactivate(self.handle);
+        }
+        self.active = false;
+    }
+
+    /*
+       Run one block of `n_samples`.  Audio inputs must hold at least that
+       many samples, outputs are resized to the block length.
+    */
+    pub fn run(&mut self, n_samples: usize) {
+        for (port, buffer) in self.buffers.iter_mut() {
+            match *buffer {
+                Buffer::Audio(ref data) => assert!(data.len() >= n_samples,
+                    "audio input {} holds less than {} samples", port, n_samples),
+                Buffer::AudioOut(ref mut data) => data.resize(n_samples, 0.0),
+                Buffer::AtomOut(ref mut data) => {
+                    let capacity = (data.len() * 8 - mem::size_of::<LV2Atom>()) as u64;
+                    let chunk = self.host.map(LV2_ATOM__CHUNK) as u64;
+                    // size and type of the atom header, in native byte order
+                    data[0] = if cfg!(target_endian = "little") {
+                        capacity | chunk << 32
+                    } else {
+                        capacity << 32 | chunk
+                    };
+                },
+                _ => ()
+            }
+            (self.descriptor.connect_port)(self.handle, *port, buffer.as_ptr());
+        }
+        (self.descriptor.run)(self.handle, n_samples as u32);
+    }
+
+    pub fn extension_data(&self, uri: &[u8]) -> *const c_void {
+        (self.descriptor.extension_data)(uri.as_ptr())
+    }
+
+    /* Save the state via the state interface, `None` if there is none. */
+    pub fn save_state(&self) -> Option<Result<SavedState, StateError>> {
+        let interface = self.extension_data(LV2_STATE__INTERFACE) as *const LV2StateInterface;
+        if interface.is_null() {
+            return None;
+        }
+
+        let mut state = SavedState { values: HashMap::new() };
+        let features = self.host.feature_array();
+        let status = unsafe { ((*interface).save)(self.handle, store_value,
+            &mut state as *mut SavedState as LV2StateHandle, 0, features.as_ptr()) };
+        Some(match status {
+            LV2_STATE_SUCCESS => Ok(state),
+            _ => Err(StateError::from_status(status))
+        })
+    }
+
+    pub fn restore_state(&mut self, state: &SavedState) -> Result<(), StateError> {
+        let interface = self.extension_data(LV2_STATE__INTERFACE) as *const LV2StateInterface;
+        assert!(!interface.is_null(), "the plugin has no state interface");
+
+        let features = self.host.feature_array();
+        let status = unsafe { ((*interface).restore)(self.handle, retrieve_value,
+            state as *const SavedState as LV2StateHandle, 0, features.as_ptr()) };
+        match status {
+            LV2_STATE_SUCCESS => Ok(()),
+            _ => Err(StateError::from_status(status))
+        }
+    }
+}
+
+impl<'h> Drop for Instance<'h> {
+    fn drop(&mut self) {
+        if self.active {
+            self.deactivate();
+        }
+        (self.descriptor.cleanup)(self.handle);
+    }
+}
+
+
+/* The state saved from an instance: atom type and value per key. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SavedState {
+    pub values: HashMap<LV2Urid, (LV2Urid, Vec<u8>)>
+}
+
+extern "C" fn store_value(handle: LV2StateHandle, key: u32, value: *const c_void, size: usize,
+    vtype: u32, _flags: u32) -> LV2StateStatus {
+
+    let state = unsafe { &mut *(handle as *mut SavedState) };
+    let value = unsafe { ::std::slice::from_raw_parts(value as *const u8, size) };
+    state.values.insert(key, (vtype, value.to_vec()));
+    LV2_STATE_SUCCESS
+}
+
+extern "C" fn retrieve_value(handle: LV2StateHandle, key: u32, size: *mut usize, vtype: *mut u32,
+    flags: *mut u32) -> *const c_void {
+
+    let state = unsafe { &*(handle as *const SavedState) };
+    match state.values.get(&key) {
+        Some(&(t, ref value)) => unsafe {
+            *size = value.len();
+            *vtype = t;
+            *flags = LV2_STATE_IS_POD | LV2_STATE_IS_PORTABLE;
+            value.as_ptr() as *const c_void
+        },
+        None => ptr::null()
+    }
+}
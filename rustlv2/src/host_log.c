@@ -0,0 +1,47 @@
+/*
+   The log:log feature of the test host (see host.rs).  printf and vprintf
+   are variadic, which stable Rust can not define, so they format the
+   message here and hand it to the host's Rust callback.
+*/
+
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct {
+    void (*message)(void* data, uint32_t type, const char* msg);
+    void* data;
+} TestHostLog;
+
+int
+testhost_log_vprintf(void* handle, uint32_t type, const char* fmt, va_list args)
+{
+    TestHostLog* log = (TestHostLog*)handle;
+    va_list copy;
+    va_copy(copy, args);
+    int len = vsnprintf(NULL, 0, fmt, copy);
+    va_end(copy);
+    if (len < 0) {
+        return len;
+    }
+
+    char* msg = (char*)malloc((size_t)len + 1);
+    if (!msg) {
+        return -1;
+    }
+    vsnprintf(msg, (size_t)len + 1, fmt, args);
+    log->message(log->data, type, msg);
+    free(msg);
+    return len;
+}
+
+int
+testhost_log_printf(void* handle, uint32_t type, const char* fmt, ...)
+{
+    va_list args;
+    va_start(args, fmt);
+    int len = testhost_log_vprintf(handle, type, fmt, args);
+    va_end(args);
+    return len;
+}
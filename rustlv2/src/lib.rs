@@ -0,0 +1,370 @@
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy;
+
+    impl Plugin for Dummy {
+        const URI: &'static [u8] = b"http://example.org/dummy\0";
+
+        const INFO: PluginInfo = PluginInfo {
+            name: "Dummy",
+            classes: &[],
+            project: "http://example.org",
+            maintainer: None,
+            license: "http://opensource.org/licenses/isc",
+            required_features: &[],
+            optional_features: &[],
+            extension_data: &[],
+            supported_options: &[],
+            ports: &[
+                PortInfo::audio_out(0, "outl", "Out L"),
+                PortInfo::audio_out(1, "outl", "Out L")
+            ],
+            parameters: &[]
+        };
+
+        fn instantiate(_rate: f64, _features: &Features) -> Option<Dummy> {
+            Some(Dummy)
+        }
+
+        fn connect_port(&mut self, _port: u32, _data: *mut c_void) {}
+
+        fn run(&mut self, _n_samples: u32) {}
+    }
+
+    #[test]
+    fn ttl_names() {
+        assert_eq!(ttl::plugin_uri::<Dummy>(), "http://example.org/dummy");
+        assert_eq!(ttl::ttl_file::<Dummy>(), "dummy.ttl");
+    }
+
+    #[test]
+    fn duplicate_symbol() {
+        assert_eq!(ttl::check::<Dummy>(), Err("port symbol \"outl\" is used twice".to_string()));
+    }
+
+    /* The number of logger threads, once it is `expected` (a thread names itself after it started). */
+    #[cfg(feature = "testhost")]
+    fn log_threads(expected: usize) -> usize {
+        let count = || std::fs::read_dir("/proc/self/task").unwrap()
+            .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|name| name == "lv2 log\n")
+            .count();
+        for _ in 0..100 {
+            if count() == expected {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        count()
+    }
+
+    #[cfg(feature = "testhost")]
+    #[test]
+    fn loggers_share_one_thread() {
+        let host = host::TestHost::new();
+        let array = host.feature_array();
+        let features = unsafe { Features::from_raw(array.as_ptr()) };
+
+        let loggers: Vec<log::Logger> = (0..20).map(|_| log::Logger::new(&features, "test")).collect();
+        assert_eq!(log_threads(1), 1);
+        for (i, logger) in loggers.iter().enumerate() {
+            logger.rt(log::Level::Error, "message", Some(i as i64));
+        }
+        drop(loggers);
+        assert_eq!(log_threads(0), 0);
+        assert_eq!(host.log_messages().len(), 20);
+        assert_eq!(host.log_messages()[3], (host.map(log::LV2_LOG__ERROR), "test: message 3\n".to_string()));
+    }
+
+    #[cfg(feature = "testhost")]
+    #[test]
+    fn host_log_formats() {
+        let host = host::TestHost::new();
+        let array = host.feature_array();
+        let features = unsafe { Features::from_raw(array.as_ptr()) };
+        let log = features.data(log::LV2_LOG__LOG).unwrap() as *const log::LV2LogLog;
+
+        let note = host.map(log::LV2_LOG__NOTE);
+        unsafe {
+            ((*log).printf)((*log).handle, note, b"%s %d %.1f\n\0".as_ptr() as *const libc::c_char,
+                b"x\0".as_ptr(), 42 as libc::c_int, 0.5f64);
+        }
+        assert_eq!(host.log_messages(), vec![(note, "x 42 0.5\n".to_string())]);
+    }
+}
+
+
+/*
+   Common glue for the plugins in this repository.
+
+   Every plugin used to carry its own copy of the descriptor, the
+   `lv2_descriptor()` entry point and the unsafe instantiate/cleanup code.
+   This crate holds that code once: a plugin implements the `Plugin` trait
+   with its DSP logic, and the `lv2_descriptors!` macro generates the C entry
+   point for one or more plugin types.
+*/
+
+extern crate libc;
+pub extern crate lv2_raw;
+
+mod features;
+mod forge;
+mod log;
+mod options;
+mod patch;
+mod ports;
+mod state;
+#[cfg(feature = "testhost")]
+pub mod host;
+pub mod ttl;
+
+pub use features::*;
+pub use forge::*;
+pub use log::*;
+pub use options::*;
+pub use patch::*;
+pub use ports::*;
+pub use state::*;
+pub use ttl::{ParameterInfo, PluginInfo, PortInfo, PortKind};
+
+use lv2_raw::*;
+use libc::{c_char, c_void};
+use std::ffi::CStr;
+use std::ptr;
+
+
+/*
+   The DSP side of a plugin.  All methods are called by the generated
+   descriptor functions, so a plugin never has to deal with the raw handle.
+
+   `URI` must be NUL terminated, since it is handed to the host as is.  If it
+   does not match the URI used in the data files, the host will fail to load
+   the plugin.  The data files are therefore generated from `URI` and the
+   description in `INFO`, see the `ttl` module.
+*/
+pub trait Plugin: Sized {
+    const URI: &'static [u8];
+
+    const INFO: PluginInfo;
+
+    /*
+       Create a new instance.  Returning `None` signals the host that the
+       instantiation failed, e.g. because a required feature is missing.
+    */
+    fn instantiate(rate: f64, features: &Features) -> Option<Self>;
+
+    /*
+       Connect a port to a host buffer.  `data` is usually handed on to one of
+       the port types of this crate, which take care of accessing it.
+    */
+    fn connect_port(&mut self, port: u32, data: *mut c_void);
+
+    fn activate(&mut self) {}
+
+    fn run(&mut self, n_samples: u32);
+
+    fn deactivate(&mut self) {}
+
+    fn extension_data(_uri: &CStr) -> *const c_void {
+        ptr::null()
+    }
+}
+
+
+/*
+   An `LV2Descriptor` which can be put into a static.  The descriptor only
+   contains pointers to static data and functions, so sharing it between
+   threads is fine.
+*/
+pub struct Descriptor(pub LV2Descriptor);
+
+unsafe impl Sync for Descriptor {}
+
+impl Descriptor {
+    pub const fn new<P: Plugin>() -> Descriptor {
+        Descriptor(LV2Descriptor {
+            uri: P::URI.as_ptr() as *const c_char,
+            instantiate: instantiate::<P>,
+            connect_port: connect_port::<P>,
+            activate: Some(activate::<P>),
+            run: run::<P>,
+            deactivate: Some(deactivate::<P>),
+            cleanup: cleanup::<P>,
+            extension_data: extension_data::<P>
+        })
+    }
+}
+
+/*
+   Look up the descriptor for `index`, returning NULL past the end of the
+   list as required by `lv2_descriptor()`.
+*/
+pub fn descriptor_at(descriptors: &'static [Descriptor], index: u32) -> *const LV2Descriptor {
+    match descriptors.get(index as usize) {
+        Some(d) => &d.0,
+        None => ptr::null()
+    }
+}
+
+
+extern "C" fn instantiate<P: Plugin>(_desc: *const LV2Descriptor,
+    rate: f64,
+    _bundle_path: *const c_char,
+    features: *const *const LV2Feature) -> LV2Handle {
+
+    let features = unsafe { Features::from_raw(features) };
+
+    match P::instantiate(rate, &features) {
+        Some(plugin) => Box::into_raw(Box::new(plugin)) as LV2Handle,
+        None => ptr::null_mut()
+    }
+}
+
+/*
+   The host guarantees that the handle passed to the instance functions is
+   one returned from `instantiate`, so it always points to a boxed `P`.
+*/
+unsafe fn plugin<'a, P: Plugin>(handle: LV2Handle) -> &'a mut P {
+    &mut *(handle as *mut P)
+}
+
+extern "C" fn connect_port<P: Plugin>(handle: LV2Handle, port: u32, data: *mut c_void) {
+    unsafe { plugin::<P>(handle) }.connect_port(port, data);
+}
+
+extern "C" fn activate<P: Plugin>(handle: LV2Handle) {
+    unsafe { plugin::<P>(handle) }.activate();
+}
+
+extern "C" fn run<P: Plugin>(handle: LV2Handle, n_samples: u32) {
+    unsafe { plugin::<P>(handle) }.run(n_samples);
+}
+
+extern "C" fn deactivate<P: Plugin>(handle: LV2Handle) {
+    unsafe { plugin::<P>(handle) }.deactivate();
+}
+
+extern "C" fn cleanup<P: Plugin>(handle: LV2Handle) {
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle as *mut P) });
+    }
+}
+
+extern "C" fn extension_data<P: Plugin>(uri: *const u8) -> *const c_void {
+    if uri.is_null() {
+        return ptr::null();
+    }
+    P::extension_data(unsafe { CStr::from_ptr(uri as *const c_char) })
+}
+
+
+/*
+   The `lv2_descriptor()` function is the entry point to the plugin library.  The
+   host will load the library and call this function repeatedly with increasing
+   indices to find all the plugins defined in the library.  The index is not an
+   indentifier, the URI of the returned descriptor is used to determine the
+   identify of the plugin.
+
+   This method is in the ``discovery'' threading class, so no other functions
+   or methods in this plugin library will be called concurrently with it.
+
+   The macro takes the plugin types in the order they are reported to the host:
+
+       lv2_descriptors!(Amp);
+*/
+#[macro_export]
+macro_rules! lv2_descriptors {
+    ($($plugin:ty),+ $(,)*) => {
+        #[no_mangle]
+        pub extern "C" fn lv2_descriptor(index: u32) -> *const $crate::lv2_raw::LV2Descriptor {
+            static DESCRIPTORS: &'static [$crate::Descriptor] = &[
+                $($crate::Descriptor::new::<$plugin>()),+
+            ];
+            $crate::descriptor_at(DESCRIPTORS, index)
+        }
+    }
+}
+
+/*
+   A complete bundle: the `lv2_descriptor()` entry point for the plugins,
+   `bundle_files()`, which generates the data files of the bundle (the
+   manifest and one .ttl per plugin) as pairs of file name and content, and
+   `bundle_plugins()`, the URI and description of every plugin.  The plugins
+   are checked with `ttl::check` before the files are generated.
+
+       lv2_bundle!("librustplugins.so"; Amp, EQ);
+*/
+#[macro_export]
+macro_rules! lv2_bundle {
+    ($binary:expr; $($plugin:ty),+ $(,)*) => {
+        $crate::lv2_descriptors!($($plugin),+);
+
+        pub fn bundle_files() -> Result<Vec<(String, String)>, String> {
+            let mut plugins = Vec::new();
+            let mut files = Vec::new();
+            $(
+                let uri = $crate::ttl::plugin_uri::<$plugin>();
+                $crate::ttl::check::<$plugin>().map_err(|e| format!("{}: {}", uri, e))?;
+                plugins.push((uri, $crate::ttl::ttl_file::<$plugin>()));
+                files.push(($crate::ttl::ttl_file::<$plugin>(), $crate::ttl::plugin::<$plugin>()));
+            )+
+            files.insert(0, ("manifest.ttl".to_string(), $crate::ttl::manifest($binary, &plugins)));
+            Ok(files)
+        }
+
+        pub fn bundle_plugins() -> Vec<(&'static str, &'static $crate::PluginInfo)> {
+            vec![$(($crate::ttl::plugin_uri::<$plugin>(), &<$plugin as $crate::Plugin>::INFO)),+]
+        }
+    }
+}
+
+/*
+   In code, ports are referred to by index.  This macro defines the
+   enumeration of port indices together with the `from_u32` conversion used
+   in `connect_port`:
+
+       port_index! {
+           enum PortIndex {
+               AmpGain = 0,
+               AmpInput = 1
+           }
+       }
+
+   A visibility in front of `enum` is passed on, e.g. for tests outside the
+   module of the plugin.
+*/
+#[macro_export]
+macro_rules! port_index {
+    ($vis:vis enum $name:ident { $($port:ident = $idx:expr),+ $(,)* }) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        $vis enum $name {
+            $($port = $idx),+
+        }
+
+        impl $name {
+            #[allow(dead_code)]
+            fn from_u32(x: u32) -> Option<$name> {
+                match x {
+                    $(x if x == $idx => Some($name::$port),)+
+                    _ => None
+                }
+            }
+        }
+    }
+}
+
+
+/* Convert a gain in dB to a coefficient. */
+//#define DB_CO(g) ((g) > -90.0f ? powf(10.0f, (g) * 0.05f) : 0.0f)
+
+pub fn db_co(g: f32) -> f64 {
+    if g > -90.0_f32 {
+        let base = 10.0_f64;
+        base.powf((g as f64) * 0.05_f64)
+    }
+    else {
+        0.0_f64
+    }
+}
@@ -0,0 +1,286 @@
+/*
+   Logging via the host's `log:log` feature.
+
+   Messages are only written if the host offers `log:log` (and `urid:map`,
+   which is needed for the message types), otherwise logging is silent.
+   Messages above `MAX_LEVEL` are compiled out; the level is chosen at build
+   time with the `log-trace` (everything) and `log-quiet` (errors and
+   warnings only) features, the default is up to notes.
+
+   The host's printf may block, so it must not be called from `run`.  Real
+   time code uses `rt` instead: the message (a static string with an optional
+   number) is put into a lock-free ring buffer, and a logger thread hands it
+   on to the host.  Anything else may call `log` directly.  All instances
+   share one logger thread, which runs while any of them logs to a host.
+*/
+
+use lv2_raw::*;
+use libc::{c_char, c_int, c_void};
+use std::cell::UnsafeCell;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use Features;
+
+
+pub const LV2_LOG__LOG: &'static [u8] = b"http://lv2plug.in/ns/ext/log#log\0";
+pub const LV2_LOG__ERROR: &'static [u8] = b"http://lv2plug.in/ns/ext/log#Error\0";
+pub const LV2_LOG__WARNING: &'static [u8] = b"http://lv2plug.in/ns/ext/log#Warning\0";
+pub const LV2_LOG__NOTE: &'static [u8] = b"http://lv2plug.in/ns/ext/log#Note\0";
+pub const LV2_LOG__TRACE: &'static [u8] = b"http://lv2plug.in/ns/ext/log#Trace\0";
+
+pub type LV2LogHandle = *mut c_void;
+
+#[repr(C)]
+pub struct LV2LogLog {
+    pub handle: LV2LogHandle,
+    pub printf: unsafe extern "C" fn(handle: LV2LogHandle, ltype: LV2Urid, fmt: *const c_char, ...) -> c_int,
+    // takes a va_list, which can not be built in Rust
+    pub vprintf: *const c_void
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error = 0,
+    Warning = 1,
+    Note = 2,
+    Trace = 3
+}
+
+#[cfg(feature = "log-trace")]
+pub const MAX_LEVEL: Level = Level::Trace;
+#[cfg(all(feature = "log-quiet", not(feature = "log-trace")))]
+pub const MAX_LEVEL: Level = Level::Warning;
+#[cfg(not(any(feature = "log-trace", feature = "log-quiet")))]
+pub const MAX_LEVEL: Level = Level::Note;
+
+
+/* The host's log feature, with the message types already mapped. */
+#[derive(Clone, Copy)]
+struct HostLog {
+    log: *const LV2LogLog,
+    types: [LV2Urid; 4]
+}
+
+// the log feature may be called from any thread
+unsafe impl Send for HostLog {}
+unsafe impl Sync for HostLog {}
+
+impl HostLog {
+    fn print(&self, level: Level, prefix: &str, msg: &str) {
+        let line = format!("{}: {}\n", prefix, msg);
+        if let Ok(line) = CString::new(line) {
+            unsafe {
+                ((*self.log).printf)((*self.log).handle, self.types[level as usize],
+                    b"%s\0".as_ptr() as *const c_char, line.as_ptr());
+            }
+        }
+    }
+}
+
+
+#[derive(Clone, Copy)]
+struct Record {
+    level: Level,
+    msg: &'static str,
+    value: Option<i64>
+}
+
+const RING_SIZE: usize = 256;
+
+/*
+   A single producer, single consumer ring buffer.  `run` is the only
+   producer, the consumer is whoever holds the lock on `LOGGERS`.  One slot
+   is always left empty to tell a full buffer from an empty one.
+*/
+struct Ring {
+    records: Vec<UnsafeCell<Record>>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+    dropped: AtomicUsize
+}
+
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn new() -> Ring {
+        let empty = Record { level: Level::Trace, msg: "", value: None };
+        Ring {
+            records: (0..RING_SIZE).map(|_| UnsafeCell::new(empty)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0)
+        }
+    }
+
+    fn push(&self, record: Record) {
+        let write = self.write.load(Ordering::Relaxed);
+        let next = (write + 1) % RING_SIZE;
+        if next == self.read.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        unsafe { *self.records[write].get() = record; }
+        self.write.store(next, Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<Record> {
+        let read = self.read.load(Ordering::Relaxed);
+        if read == self.write.load(Ordering::Acquire) {
+            return None;
+        }
+        let record = unsafe { *self.records[read].get() };
+        self.read.store((read + 1) % RING_SIZE, Ordering::Release);
+        Some(record)
+    }
+}
+
+
+/* The queue of one logger, with where its messages go. */
+struct Channel {
+    host: HostLog,
+    prefix: &'static str,
+    ring: Ring
+}
+
+impl Channel {
+    /* Hand the queued messages on to the host, only with the lock on `LOGGERS`. */
+    fn drain(&self) {
+        while let Some(record) = self.ring.pop() {
+            match record.value {
+                Some(value) => self.host.print(record.level, self.prefix, &format!("{} {}", record.msg, value)),
+                None => self.host.print(record.level, self.prefix, record.msg)
+            }
+        }
+        let dropped = self.ring.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            self.host.print(Level::Warning, self.prefix, &format!("{} log messages dropped", dropped));
+        }
+    }
+}
+
+/*
+   The channels of all loggers and the thread draining them, which is
+   started with the first channel and stopped with the last one, so no
+   thread runs once the plugins are gone and the library may be unloaded.
+*/
+struct Loggers {
+    channels: Vec<Arc<Channel>>,
+    thread: Option<(thread::JoinHandle<()>, Arc<AtomicBool>)>
+}
+
+static LOGGERS: Mutex<Loggers> = Mutex::new(Loggers { channels: Vec::new(), thread: None });
+
+// a panic while printing leaves nothing inconsistent behind
+fn loggers() -> MutexGuard<'static, Loggers> {
+    LOGGERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+
+pub struct Logger {
+    prefix: &'static str,
+    host: Option<HostLog>,
+    channel: Option<Arc<Channel>>
+}
+
+impl Logger {
+    /*
+       A logger for the plugin `prefix`, which is put in front of every
+       message.  Call from `instantiate`, it starts the logger thread if the
+       host offers `log:log` and the thread is not running yet.
+    */
+    pub fn new(features: &Features, prefix: &'static str) -> Logger {
+        let host = match (features.data(LV2_LOG__LOG), features.urid_map()) {
+            (Some(log), Some(map)) if !log.is_null() => Some(HostLog {
+                log: log as *const LV2LogLog,
+                types: [map.map(LV2_LOG__ERROR), map.map(LV2_LOG__WARNING),
+                        map.map(LV2_LOG__NOTE), map.map(LV2_LOG__TRACE)]
+            }),
+            _ => None
+        };
+
+        let channel = host.map(|host| {
+            let channel = Arc::new(Channel { host: host, prefix: prefix, ring: Ring::new() });
+            let mut loggers = loggers();
+            loggers.channels.push(channel.clone());
+            if loggers.thread.is_none() {
+                let stop = Arc::new(AtomicBool::new(false));
+                let thread_stop = stop.clone();
+                loggers.thread = thread::Builder::new()
+                    .name("lv2 log".to_string())
+                    .spawn(move || drain(&thread_stop))
+                    .ok()
+                    .map(|thread| (thread, stop));
+            }
+            channel
+        });
+
+        Logger { prefix: prefix, host: host, channel: channel }
+    }
+
+    /* A logger which drops everything. */
+    pub fn silent() -> Logger {
+        Logger { prefix: "", host: None, channel: None }
+    }
+
+    /* Log directly, not real-time safe. */
+    pub fn log(&self, level: Level, msg: &str) {
+        if level <= MAX_LEVEL {
+            if let Some(ref host) = self.host {
+                host.print(level, self.prefix, msg);
+            }
+        }
+    }
+
+    /* Log from real-time code, `value` is appended to the message. */
+    pub fn rt(&self, level: Level, msg: &'static str, value: Option<i64>) {
+        if level <= MAX_LEVEL {
+            if let Some(ref channel) = self.channel {
+                channel.ring.push(Record { level: level, msg: msg, value: value });
+            }
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Logger {
+        Logger::silent()
+    }
+}
+
+/*
+   Writes the queued messages, and stops the logger thread if this was the
+   last logger.
+*/
+impl Drop for Logger {
+    fn drop(&mut self) {
+        let channel = match self.channel.take() {
+            Some(channel) => channel,
+            None => return
+        };
+        let thread = {
+            let mut loggers = loggers();
+            loggers.channels.retain(|c| !Arc::ptr_eq(c, &channel));
+            channel.drain();
+            if loggers.channels.is_empty() { loggers.thread.take() } else { None }
+        };
+        if let Some((thread, stop)) = thread {
+            stop.store(true, Ordering::Release);
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn drain(stop: &AtomicBool) {
+    while !stop.load(Ordering::Acquire) {
+        for channel in loggers().channels.iter() {
+            channel.drain();
+        }
+        thread::park_timeout(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,88 @@
+/*
+   The LV2 Options extension, through which the host passes settings like
+   the block sizes it is going to use to `instantiate`.
+
+   `lv2_raw` has neither the options nor the buf-size URIs, so they are
+   defined here.
+*/
+
+use lv2_raw::*;
+use libc::c_void;
+
+use Features;
+
+
+pub const LV2_OPTIONS__OPTIONS: &'static [u8] = b"http://lv2plug.in/ns/ext/options#options\0";
+
+pub const LV2_BUF_SIZE__MAX_BLOCK_LENGTH: &'static [u8] = b"http://lv2plug.in/ns/ext/buf-size#maxBlockLength\0";
+pub const LV2_BUF_SIZE__BOUNDED_BLOCK_LENGTH: &'static [u8] = b"http://lv2plug.in/ns/ext/buf-size#boundedBlockLength\0";
+
+pub type LV2OptionsContext = u32;
+
+pub const LV2_OPTIONS_INSTANCE: LV2OptionsContext = 0;
+
+/* One option, the array passed by the host ends with a zeroed one. */
+#[repr(C)]
+pub struct LV2OptionsOption {
+    pub context: LV2OptionsContext,
+    pub subject: u32,
+    pub key: LV2Urid,
+    pub size: u32,
+    pub otype: LV2Urid,
+    pub value: *const c_void
+}
+
+
+/* The option array of the `options:options` feature. */
+#[derive(Clone, Copy)]
+pub struct Options {
+    options: *const LV2OptionsOption
+}
+
+impl Options {
+    /* The value and atom type of the instance option `key`. */
+    pub fn get(&self, key: LV2Urid) -> Option<(LV2Urid, *const c_void)> {
+        let mut option = self.options;
+        unsafe {
+            while (*option).key != 0 {
+                if (*option).key == key && (*option).context == LV2_OPTIONS_INSTANCE
+                    && !(*option).value.is_null() {
+                    return Some(((*option).otype, (*option).value));
+                }
+                option = option.offset(1);
+            }
+        }
+        None
+    }
+
+    pub fn get_i32(&self, key: LV2Urid, atom_int: LV2Urid) -> Option<i32> {
+        match self.get(key) {
+            Some((otype, value)) if otype == atom_int => Some(unsafe { *(value as *const i32) }),
+            _ => None
+        }
+    }
+}
+
+
+impl Features {
+    pub fn options(&self) -> Option<Options> {
+        match self.data(LV2_OPTIONS__OPTIONS) {
+            Some(data) if !data.is_null() => Some(Options { options: data as *const LV2OptionsOption }),
+            _ => None
+        }
+    }
+
+    /*
+       The `bufsz:maxBlockLength` option: the largest number of samples the
+       host passes to `run`, if it tells.
+    */
+    pub fn max_block_length(&self) -> Option<usize> {
+        let map = self.urid_map()?;
+        let length = self.options()?.get_i32(map.map(LV2_BUF_SIZE__MAX_BLOCK_LENGTH), map.map(LV2_ATOM__INT))?;
+        if length > 0 {
+            Some(length as usize)
+        } else {
+            None
+        }
+    }
+}
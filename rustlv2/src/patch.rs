@@ -0,0 +1,10 @@
+/*
+   URIs of the LV2 Patch extension, which `lv2_raw` does not provide.  A
+   patch:Set message on a plugin's control port changes one of its
+   parameters, e.g. a setting that is not a port but part of its state.
+*/
+
+pub const LV2_PATCH__MESSAGE: &'static [u8] = b"http://lv2plug.in/ns/ext/patch#Message\0";
+pub const LV2_PATCH__SET: &'static [u8] = b"http://lv2plug.in/ns/ext/patch#Set\0";
+pub const LV2_PATCH__PROPERTY: &'static [u8] = b"http://lv2plug.in/ns/ext/patch#property\0";
+pub const LV2_PATCH__VALUE: &'static [u8] = b"http://lv2plug.in/ns/ext/patch#value\0";
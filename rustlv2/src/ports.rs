@@ -0,0 +1,163 @@
+use lv2_raw::*;
+use libc::c_void;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+
+/*
+   Port buffers.  The host connects each port to a buffer via `connect_port`
+   and guarantees that it stays valid and holds at least `n_samples` values
+   for the duration of `run`, which is what the accessors below rely on.
+   They must therefore only be used from within `run`.
+*/
+
+pub struct AudioIn(*const f32);
+
+impl AudioIn {
+    pub fn new() -> AudioIn {
+        AudioIn(ptr::null())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *const f32;
+    }
+
+    pub fn as_slice(&self, n_samples: usize) -> &[f32] {
+        unsafe { slice::from_raw_parts(self.0, n_samples) }
+    }
+}
+
+impl Default for AudioIn {
+    fn default() -> AudioIn {
+        AudioIn::new()
+    }
+}
+
+pub struct AudioOut(*mut f32);
+
+impl AudioOut {
+    pub fn new() -> AudioOut {
+        AudioOut(ptr::null_mut())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *mut f32;
+    }
+
+    pub fn as_mut_slice(&mut self, n_samples: usize) -> &mut [f32] {
+        unsafe { slice::from_raw_parts_mut(self.0, n_samples) }
+    }
+}
+
+impl Default for AudioOut {
+    fn default() -> AudioOut {
+        AudioOut::new()
+    }
+}
+
+pub struct ControlIn(*const f32);
+
+impl ControlIn {
+    pub fn new() -> ControlIn {
+        ControlIn(ptr::null())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *const f32;
+    }
+
+    pub fn value(&self) -> f32 {
+        unsafe { *self.0 }
+    }
+}
+
+impl Default for ControlIn {
+    fn default() -> ControlIn {
+        ControlIn::new()
+    }
+}
+
+pub struct ControlOut(*mut f32);
+
+impl ControlOut {
+    pub fn new() -> ControlOut {
+        ControlOut(ptr::null_mut())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *mut f32;
+    }
+
+    pub fn set(&mut self, value: f32) {
+        unsafe { *self.0 = value }
+    }
+}
+
+impl Default for ControlOut {
+    fn default() -> ControlOut {
+        ControlOut::new()
+    }
+}
+
+pub struct AtomIn(*mut LV2AtomSequence);
+
+impl AtomIn {
+    pub fn new() -> AtomIn {
+        AtomIn(ptr::null_mut())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *mut LV2AtomSequence;
+    }
+
+    /*
+       The atom sequence is walked with the `lv2_raw` atom utilities, which
+       work on raw pointers.
+    */
+    pub fn as_ptr(&self) -> *mut LV2AtomSequence {
+        self.0
+    }
+}
+
+impl Default for AtomIn {
+    fn default() -> AtomIn {
+        AtomIn::new()
+    }
+}
+
+/*
+   An atom:Sequence output.  The port is optional for a plugin that declares
+   it lv2:connectionOptional, so it may stay unconnected.
+*/
+pub struct AtomOut(*mut LV2AtomSequence);
+
+impl AtomOut {
+    pub fn new() -> AtomOut {
+        AtomOut(ptr::null_mut())
+    }
+
+    pub fn connect(&mut self, data: *mut c_void) {
+        self.0 = data as *mut LV2AtomSequence;
+    }
+
+    /*
+       The whole buffer including the sequence header, its capacity is the
+       atom size set by the host.  `None` if the port is not connected.
+    */
+    pub fn as_mut_bytes(&mut self) -> Option<&mut [u8]> {
+        if self.0.is_null() {
+            return None;
+        }
+        unsafe {
+            let capacity = (*self.0).atom.size as usize + mem::size_of::<LV2Atom>();
+            Some(slice::from_raw_parts_mut(self.0 as *mut u8, capacity))
+        }
+    }
+}
+
+impl Default for AtomOut {
+    fn default() -> AtomOut {
+        AtomOut::new()
+    }
+}
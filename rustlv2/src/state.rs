@@ -0,0 +1,198 @@
+/*
+   The LV2 State extension, which lets the host save and restore the state
+   of a plugin that is not held in ports, e.g. with a session.
+
+   A plugin implements `State` and hands out a static `StateInterface` from
+   its `extension_data`:
+
+       static STATE: StateInterface = StateInterface::new::<Metro>();
+
+       fn extension_data(uri: &CStr) -> *const c_void {
+           if uri.to_bytes_with_nul() == LV2_STATE__INTERFACE {
+               STATE.as_ptr()
+           } else {
+               ptr::null()
+           }
+       }
+
+   Values are stored as plain data with the atom type given by the plugin,
+   e.g. an atom:Float as the native bytes of an `f32`.
+*/
+
+use lv2_raw::*;
+use libc::c_void;
+use std::slice;
+
+use Plugin;
+use Features;
+
+
+pub const LV2_STATE__INTERFACE: &'static [u8] = b"http://lv2plug.in/ns/ext/state#interface\0";
+
+pub type LV2StateHandle = *mut c_void;
+pub type LV2StateStatus = u32;
+
+pub const LV2_STATE_SUCCESS: LV2StateStatus = 0;
+
+pub const LV2_STATE_IS_POD: u32 = 1;
+pub const LV2_STATE_IS_PORTABLE: u32 = 2;
+
+pub type LV2StateStoreFunction = extern "C" fn(handle: LV2StateHandle, key: u32,
+    value: *const c_void, size: usize, vtype: u32, flags: u32) -> LV2StateStatus;
+
+pub type LV2StateRetrieveFunction = extern "C" fn(handle: LV2StateHandle, key: u32,
+    size: *mut usize, vtype: *mut u32, flags: *mut u32) -> *const c_void;
+
+#[repr(C)]
+pub struct LV2StateInterface {
+    pub save: extern "C" fn(instance: LV2Handle, store: LV2StateStoreFunction,
+        handle: LV2StateHandle, flags: u32, features: *const *const LV2Feature) -> LV2StateStatus,
+    pub restore: extern "C" fn(instance: LV2Handle, retrieve: LV2StateRetrieveFunction,
+        handle: LV2StateHandle, flags: u32, features: *const *const LV2Feature) -> LV2StateStatus
+}
+
+
+/* The error codes of LV2_State_Status. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StateError {
+    Unknown = 1,
+    BadType = 2,
+    BadFlags = 3,
+    NoFeature = 4,
+    NoProperty = 5,
+    NoSpace = 6
+}
+
+impl StateError {
+    pub(crate) fn from_status(status: LV2StateStatus) -> StateError {
+        match status {
+            2 => StateError::BadType,
+            3 => StateError::BadFlags,
+            4 => StateError::NoFeature,
+            5 => StateError::NoProperty,
+            6 => StateError::NoSpace,
+            _ => StateError::Unknown
+        }
+    }
+}
+
+
+/*
+   `save` and `restore` are called by the host from a non-realtime thread,
+   never concurrently with `run`, so they may allocate and change the
+   instance directly.
+*/
+pub trait State: Plugin {
+    fn save(&self, store: &mut StateStore, features: &Features) -> Result<(), StateError>;
+
+    fn restore(&mut self, retrieve: &StateRetrieve, features: &Features) -> Result<(), StateError>;
+}
+
+
+pub struct StateStore {
+    store: LV2StateStoreFunction,
+    handle: LV2StateHandle
+}
+
+impl StateStore {
+    /* Store `value` of the atom type `vtype` under `key`. */
+    pub fn store(&mut self, key: LV2Urid, vtype: LV2Urid, value: &[u8]) -> Result<(), StateError> {
+        let status = (self.store)(self.handle, key, value.as_ptr() as *const c_void, value.len(),
+            vtype, LV2_STATE_IS_POD | LV2_STATE_IS_PORTABLE);
+        if status == LV2_STATE_SUCCESS {
+            Ok(())
+        } else {
+            Err(StateError::from_status(status))
+        }
+    }
+
+    pub fn store_f32(&mut self, key: LV2Urid, atom_float: LV2Urid, value: f32) -> Result<(), StateError> {
+        self.store(key, atom_float, &value.to_ne_bytes())
+    }
+
+    pub fn store_i32(&mut self, key: LV2Urid, atom_int: LV2Urid, value: i32) -> Result<(), StateError> {
+        self.store(key, atom_int, &value.to_ne_bytes())
+    }
+}
+
+pub struct StateRetrieve {
+    retrieve: LV2StateRetrieveFunction,
+    handle: LV2StateHandle
+}
+
+impl StateRetrieve {
+    /* The atom type and the data stored under `key`. */
+    pub fn retrieve(&self, key: LV2Urid) -> Option<(LV2Urid, &[u8])> {
+        let mut size = 0;
+        let mut vtype = 0;
+        let mut flags = 0;
+        let value = (self.retrieve)(self.handle, key, &mut size, &mut vtype, &mut flags);
+        if value.is_null() {
+            None
+        } else {
+            Some((vtype, unsafe { slice::from_raw_parts(value as *const u8, size) }))
+        }
+    }
+
+    pub fn retrieve_f32(&self, key: LV2Urid, atom_float: LV2Urid) -> Result<f32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.retrieve_sized(key, atom_float, 4)?);
+        Ok(f32::from_ne_bytes(bytes))
+    }
+
+    pub fn retrieve_i32(&self, key: LV2Urid, atom_int: LV2Urid) -> Result<i32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.retrieve_sized(key, atom_int, 4)?);
+        Ok(i32::from_ne_bytes(bytes))
+    }
+
+    fn retrieve_sized(&self, key: LV2Urid, vtype: LV2Urid, size: usize) -> Result<&[u8], StateError> {
+        match self.retrieve(key) {
+            Some((t, value)) if t == vtype && value.len() == size => Ok(value),
+            Some(_) => Err(StateError::BadType),
+            None => Err(StateError::NoProperty)
+        }
+    }
+}
+
+
+/* An `LV2StateInterface` for a plugin type, to be put into a static. */
+pub struct StateInterface(pub LV2StateInterface);
+
+impl StateInterface {
+    pub const fn new<P: State>() -> StateInterface {
+        StateInterface(LV2StateInterface {
+            save: save::<P>,
+            restore: restore::<P>
+        })
+    }
+
+    pub fn as_ptr(&'static self) -> *const c_void {
+        &self.0 as *const LV2StateInterface as *const c_void
+    }
+}
+
+fn status(result: Result<(), StateError>) -> LV2StateStatus {
+    match result {
+        Ok(()) => LV2_STATE_SUCCESS,
+        Err(err) => err as LV2StateStatus
+    }
+}
+
+extern "C" fn save<P: State>(instance: LV2Handle, store: LV2StateStoreFunction,
+    handle: LV2StateHandle, _flags: u32, features: *const *const LV2Feature) -> LV2StateStatus {
+
+    let plugin = unsafe { &*(instance as *const P) };
+    let features = unsafe { Features::from_raw(features) };
+    let mut store = StateStore { store: store, handle: handle };
+    status(plugin.save(&mut store, &features))
+}
+
+extern "C" fn restore<P: State>(instance: LV2Handle, retrieve: LV2StateRetrieveFunction,
+    handle: LV2StateHandle, _flags: u32, features: *const *const LV2Feature) -> LV2StateStatus {
+
+    let plugin = unsafe { &mut *(instance as *mut P) };
+    let features = unsafe { Features::from_raw(features) };
+    let retrieve = StateRetrieve { retrieve: retrieve, handle: handle };
+    status(plugin.restore(&retrieve, &features))
+}
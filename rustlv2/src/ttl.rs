@@ -0,0 +1,349 @@
+/*
+   Plugin and port descriptions.
+
+   The data files of a plugin (the manifest and the plugin .ttl) are
+   generated from this metadata, which every plugin declares next to its
+   `PortIndex`.  Like that the URI, the port indices and the symbols the host
+   reads can not disagree with the code any more.
+
+   Classes, features, units and port properties are given as prefixed names
+   (`lv2:FilterPlugin`, `urid:map`, `units:db`, `epp:logarithmic`).  All
+   prefixes of `PREFIXES` can be used.
+*/
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use Plugin;
+
+
+pub const PREFIXES: &'static [(&'static str, &'static str)] = &[
+    ("atom", "http://lv2plug.in/ns/ext/atom#"),
+    ("bufsz", "http://lv2plug.in/ns/ext/buf-size#"),
+    ("doap", "http://usefulinc.com/ns/doap#"),
+    ("epp", "http://lv2plug.in/ns/ext/port-props#"),
+    ("foaf", "http://xmlns.com/foaf/0.1/"),
+    ("log", "http://lv2plug.in/ns/ext/log#"),
+    ("lv2", "http://lv2plug.in/ns/lv2core#"),
+    ("midi", "http://lv2plug.in/ns/ext/midi#"),
+    ("opts", "http://lv2plug.in/ns/ext/options#"),
+    ("patch", "http://lv2plug.in/ns/ext/patch#"),
+    ("rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#"),
+    ("rdfs", "http://www.w3.org/2000/01/rdf-schema#"),
+    ("rsz", "http://lv2plug.in/ns/ext/resize-port#"),
+    ("state", "http://lv2plug.in/ns/ext/state#"),
+    ("time", "http://lv2plug.in/ns/ext/time#"),
+    ("units", "http://lv2plug.in/ns/extensions/units#"),
+    ("urid", "http://lv2plug.in/ns/ext/urid#")
+];
+
+
+pub struct PluginInfo {
+    pub name: &'static str,
+    // additional classes besides lv2:Plugin
+    pub classes: &'static [&'static str],
+    pub project: &'static str,
+    pub maintainer: Option<&'static str>,
+    pub license: &'static str,
+    pub required_features: &'static [&'static str],
+    pub optional_features: &'static [&'static str],
+    // e.g. state:interface
+    pub extension_data: &'static [&'static str],
+    // options read from opts:options, e.g. bufsz:maxBlockLength
+    pub supported_options: &'static [&'static str],
+    pub ports: &'static [PortInfo],
+    // settings which are not ports, set with patch:Set
+    pub parameters: &'static [ParameterInfo]
+}
+
+pub struct ParameterInfo {
+    pub uri: &'static str,
+    pub label: &'static str,
+    // atom:Float or atom:Int
+    pub range: &'static str,
+    // default, minimum, maximum
+    pub values: (f32, f32, f32)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortKind {
+    AudioIn,
+    AudioOut,
+    ControlIn,
+    ControlOut,
+    // an atom:Sequence input
+    AtomIn,
+    AtomOut
+}
+
+pub struct PortInfo {
+    pub index: u32,
+    pub symbol: &'static str,
+    pub name: &'static str,
+    pub kind: PortKind,
+    // default, minimum, maximum
+    pub range: Option<(f32, f32, f32)>,
+    pub unit: Option<&'static str>,
+    pub properties: &'static [&'static str],
+    pub scale_points: &'static [(&'static str, f32)],
+    // event types of an atom port
+    pub supports: &'static [&'static str],
+    pub designation: Option<&'static str>,
+    // buffer size in bytes an atom port needs at least
+    pub minimum_size: Option<u32>
+}
+
+impl PortInfo {
+    const fn new(index: u32, symbol: &'static str, name: &'static str, kind: PortKind) -> PortInfo {
+        PortInfo {
+            index: index,
+            symbol: symbol,
+            name: name,
+            kind: kind,
+            range: None,
+            unit: None,
+            properties: &[],
+            scale_points: &[],
+            supports: &[],
+            designation: None,
+            minimum_size: None
+        }
+    }
+
+    pub const fn audio_in(index: u32, symbol: &'static str, name: &'static str) -> PortInfo {
+        PortInfo::new(index, symbol, name, PortKind::AudioIn)
+    }
+
+    pub const fn audio_out(index: u32, symbol: &'static str, name: &'static str) -> PortInfo {
+        PortInfo::new(index, symbol, name, PortKind::AudioOut)
+    }
+
+    pub const fn control_in(index: u32, symbol: &'static str, name: &'static str,
+        default: f32, minimum: f32, maximum: f32) -> PortInfo {
+
+        let mut port = PortInfo::new(index, symbol, name, PortKind::ControlIn);
+        port.range = Some((default, minimum, maximum));
+        port
+    }
+
+    pub const fn control_out(index: u32, symbol: &'static str, name: &'static str) -> PortInfo {
+        PortInfo::new(index, symbol, name, PortKind::ControlOut)
+    }
+
+    pub const fn atom_in(index: u32, symbol: &'static str, name: &'static str) -> PortInfo {
+        PortInfo::new(index, symbol, name, PortKind::AtomIn)
+    }
+
+    pub const fn atom_out(index: u32, symbol: &'static str, name: &'static str) -> PortInfo {
+        PortInfo::new(index, symbol, name, PortKind::AtomOut)
+    }
+
+    pub const fn unit(mut self, unit: &'static str) -> PortInfo {
+        self.unit = Some(unit);
+        self
+    }
+
+    pub const fn properties(mut self, properties: &'static [&'static str]) -> PortInfo {
+        self.properties = properties;
+        self
+    }
+
+    pub const fn scale_points(mut self, points: &'static [(&'static str, f32)]) -> PortInfo {
+        self.scale_points = points;
+        self
+    }
+
+    pub const fn supports(mut self, types: &'static [&'static str]) -> PortInfo {
+        self.supports = types;
+        self
+    }
+
+    pub const fn designation(mut self, designation: &'static str) -> PortInfo {
+        self.designation = Some(designation);
+        self
+    }
+
+    pub const fn minimum_size(mut self, bytes: u32) -> PortInfo {
+        self.minimum_size = Some(bytes);
+        self
+    }
+}
+
+
+/* The plugin URI without the trailing NUL. */
+pub fn plugin_uri<P: Plugin>() -> &'static str {
+    let uri = P::URI.split(|b| *b == 0).next().unwrap();
+    ::std::str::from_utf8(uri).expect("plugin URI is not UTF-8")
+}
+
+/* The name of the .ttl file describing a plugin: the last part of its URI. */
+pub fn ttl_file<P: Plugin>() -> String {
+    let uri = plugin_uri::<P>();
+    format!("{}.ttl", uri.rsplit('/').next().unwrap())
+}
+
+/*
+   Check what the host would choke on: port indices have to be 0..n in the
+   order of the ports and symbols have to be unique valid C identifiers.
+*/
+pub fn check<P: Plugin>() -> Result<(), String> {
+    let info = &P::INFO;
+    let mut symbols = HashSet::new();
+
+    for (i, port) in info.ports.iter().enumerate() {
+        if port.index != i as u32 {
+            return Err(format!("port \"{}\" has index {}, expected {}", port.symbol, port.index, i));
+        }
+        if !is_symbol(port.symbol) {
+            return Err(format!("port {} has the invalid symbol \"{}\"", port.index, port.symbol));
+        }
+        if !symbols.insert(port.symbol) {
+            return Err(format!("port symbol \"{}\" is used twice", port.symbol));
+        }
+    }
+    Ok(())
+}
+
+fn is_symbol(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+
+/*
+   The manifest of a bundle.  It only tells the host which plugins are in
+   the bundle, in which library, and where to find their descriptions.
+*/
+pub fn manifest(binary: &str, plugins: &[(&str, String)]) -> String {
+    let mut ttl = String::new();
+
+    writeln!(ttl, "@prefix lv2:  <http://lv2plug.in/ns/lv2core#> .").unwrap();
+    writeln!(ttl, "@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .").unwrap();
+    for &(uri, ref file) in plugins {
+        writeln!(ttl).unwrap();
+        writeln!(ttl, "<{}>", uri).unwrap();
+        writeln!(ttl, "    a lv2:Plugin ;").unwrap();
+        writeln!(ttl, "    lv2:binary <{}> ;", binary).unwrap();
+        writeln!(ttl, "    rdfs:seeAlso <{}> .", file).unwrap();
+    }
+    ttl
+}
+
+/* The description of a plugin with all its ports. */
+pub fn plugin<P: Plugin>() -> String {
+    let info = &P::INFO;
+    let mut ttl = String::new();
+
+    for &(prefix, uri) in PREFIXES {
+        writeln!(ttl, "@prefix {}: <{}> .", prefix, uri).unwrap();
+    }
+    writeln!(ttl).unwrap();
+    writeln!(ttl, "<{}>", plugin_uri::<P>()).unwrap();
+
+    let mut lines = Vec::new();
+    let mut classes = String::from("a lv2:Plugin");
+    for class in info.classes {
+        write!(classes, " ,\n        {}", class).unwrap();
+    }
+    lines.push(classes);
+    lines.push(format!("doap:name {}", literal(info.name)));
+    if let Some(maintainer) = info.maintainer {
+        lines.push(format!("doap:maintainer [ foaf:name {} ]", literal(maintainer)));
+    }
+    lines.push(format!("doap:license <{}>", info.license));
+    lines.push(format!("lv2:project <{}>", info.project));
+    for feature in info.required_features {
+        lines.push(format!("lv2:requiredFeature {}", feature));
+    }
+    for feature in info.optional_features {
+        lines.push(format!("lv2:optionalFeature {}", feature));
+    }
+    for extension in info.extension_data {
+        lines.push(format!("lv2:extensionData {}", extension));
+    }
+    for option in info.supported_options {
+        lines.push(format!("opts:supportedOption {}", option));
+    }
+    for parameter in info.parameters {
+        lines.push(format!("patch:writable <{}>", parameter.uri));
+    }
+    if !info.ports.is_empty() {
+        let ports: Vec<String> = info.ports.iter().map(port).collect();
+        lines.push(format!("lv2:port [\n{}\n    ]", ports.join("\n    ] , [\n")));
+    }
+
+    for line in &lines[..lines.len() - 1] {
+        writeln!(ttl, "    {} ;", line).unwrap();
+    }
+    writeln!(ttl, "    {} .", lines[lines.len() - 1]).unwrap();
+
+    for parameter in info.parameters {
+        let (default, minimum, maximum) = parameter.values;
+        writeln!(ttl).unwrap();
+        writeln!(ttl, "<{}>", parameter.uri).unwrap();
+        writeln!(ttl, "    a lv2:Parameter ;").unwrap();
+        writeln!(ttl, "    rdfs:label {} ;", literal(parameter.label)).unwrap();
+        writeln!(ttl, "    rdfs:range {} ;", parameter.range).unwrap();
+        writeln!(ttl, "    lv2:default {:?} ;", default).unwrap();
+        writeln!(ttl, "    lv2:minimum {:?} ;", minimum).unwrap();
+        writeln!(ttl, "    lv2:maximum {:?} .", maximum).unwrap();
+    }
+    ttl
+}
+
+fn port(port: &PortInfo) -> String {
+    let mut lines = Vec::new();
+
+    lines.push(match port.kind {
+        PortKind::AudioIn => "a lv2:AudioPort ,\n            lv2:InputPort".to_string(),
+        PortKind::AudioOut => "a lv2:AudioPort ,\n            lv2:OutputPort".to_string(),
+        PortKind::ControlIn => "a lv2:InputPort ,\n            lv2:ControlPort".to_string(),
+        PortKind::ControlOut => "a lv2:OutputPort ,\n            lv2:ControlPort".to_string(),
+        PortKind::AtomIn => "a lv2:InputPort ,\n            atom:AtomPort".to_string(),
+        PortKind::AtomOut => "a lv2:OutputPort ,\n            atom:AtomPort".to_string()
+    });
+    if port.kind == PortKind::AtomIn || port.kind == PortKind::AtomOut {
+        lines.push("atom:bufferType atom:Sequence".to_string());
+    }
+    for supports in port.supports {
+        lines.push(format!("atom:supports {}", supports));
+    }
+    if let Some(designation) = port.designation {
+        lines.push(format!("lv2:designation {}", designation));
+    }
+    if let Some(bytes) = port.minimum_size {
+        lines.push(format!("rsz:minimumSize {}", bytes));
+    }
+    lines.push(format!("lv2:index {}", port.index));
+    lines.push(format!("lv2:symbol {}", literal(port.symbol)));
+    lines.push(format!("lv2:name {}", literal(port.name)));
+    if let Some((default, minimum, maximum)) = port.range {
+        lines.push(format!("lv2:default {:?}", default));
+        lines.push(format!("lv2:minimum {:?}", minimum));
+        lines.push(format!("lv2:maximum {:?}", maximum));
+    }
+    if let Some(unit) = port.unit {
+        lines.push(format!("units:unit {}", unit));
+    }
+    for property in port.properties {
+        lines.push(format!("lv2:portProperty {}", property));
+    }
+    if !port.scale_points.is_empty() {
+        let points: Vec<String> = port.scale_points.iter()
+            .map(|&(label, value)| format!(
+                "            rdfs:label {} ;\n            rdf:value {:?}", literal(label), value))
+            .collect();
+        lines.push(format!("lv2:scalePoint [\n{}\n        ]", points.join("\n        ] , [\n")));
+    }
+
+    let lines: Vec<String> = lines.iter().map(|l| format!("        {}", l)).collect();
+    lines.join(" ;\n")
+}
+
+fn literal(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -1,7 +1,157 @@
 #[cfg(test)]
 mod tests {
+    use rustlv2::host::*;
+    use super::*;
+
+    // the plugin is exported by the bundle, the tests need their own entry point
+    lv2_descriptors!(Metro);
+
+    const RATE: f64 = 48000.0;
+
+    fn position(host: &TestHost, frames: i64, speed: f32, bpm: f32, bar_beat: f32) -> AtomEvent {
+        AtomEvent::float_object(host, frames, LV2_TIME__POSITION,
+            &[(LV2_TIME__SPEED, speed),
+              (LV2_TIME__BEATSPERMINUTE, bpm),
+              (LV2_TIME__BARBEAT, bar_beat)])
+    }
+
+    fn metro<'h>(host: &'h TestHost) -> Instance<'h> {
+        let mut metro = host.instantiate(lv2_descriptor, "http://example.org/rustmetro", RATE).unwrap();
+        metro.connect_audio_output(PortIndex::MetroOut as u32);
+        metro.set_atom_input(PortIndex::MetroControl as u32, &[]);
+        metro.activate();
+        metro
+    }
+
+    #[test]
+    fn descriptor() {
+        let host = TestHost::new();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rustmetro", RATE).is_some());
+        assert!(lv2_descriptor(1).is_null());
+    }
+
+    #[test]
+    fn requires_urid_map() {
+        let host = TestHost::bare();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rustmetro", RATE).is_none());
+    }
+
+    #[test]
+    fn silent_while_stopped() {
+        let host = TestHost::new();
+        let mut metro = metro(&host);
+
+        metro.run(1024);
+        assert!(metro.audio_output(PortIndex::MetroOut as u32).iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn clicks_on_every_beat() {
+        let host = TestHost::new();
+        let mut metro = metro(&host);
+
+        // at 120 bpm a beat is half a second
+        let beat = (RATE / 2.0) as usize;
+        let click = (RATE * 0.08) as usize;
+
+        metro.set_atom_input(PortIndex::MetroControl as u32, &[position(&host, 0, 1.0, 120.0, 0.0)]);
+        metro.run(beat);
+        let out = metro.audio_output(PortIndex::MetroOut as u32).to_vec();
+        assert!(out[..click].iter().any(|x| x.abs() > 0.1));
+        assert!(out[click + 1..].iter().all(|x| *x == 0.0));
+
+        metro.set_atom_input(PortIndex::MetroControl as u32, &[]);
+        metro.run(beat);
+        let out = metro.audio_output(PortIndex::MetroOut as u32);
+        assert!(out[..click].iter().any(|x| x.abs() > 0.1));
+        assert!(out[click + 1..].iter().all(|x| *x == 0.0));
+    }
+
     #[test]
-    fn it_works() {
+    fn tempo_change() {
+        let host = TestHost::new();
+        let mut metro = metro(&host);
+
+        // at 240 bpm there are two clicks within half a second
+        let beat = (RATE / 4.0) as usize;
+        metro.set_atom_input(PortIndex::MetroControl as u32, &[position(&host, 0, 1.0, 240.0, 0.0)]);
+        metro.run(2 * beat);
+        let out = metro.audio_output(PortIndex::MetroOut as u32);
+        assert!(out[beat..beat + 100].iter().any(|x| *x != 0.0));
+    }
+
+    /* Transport rolling, but without a tempo from the host */
+    fn rolling(host: &TestHost) -> AtomEvent {
+        AtomEvent::float_object(host, 0, LV2_TIME__POSITION,
+            &[(LV2_TIME__SPEED, 1.0), (LV2_TIME__BARBEAT, 0.0)])
+    }
+
+    #[test]
+    fn internal_tempo() {
+        let host = TestHost::new();
+        let mut metro = metro(&host);
+
+        let beat = (RATE / 4.0) as usize;
+        metro.set_atom_input(PortIndex::MetroControl as u32,
+            &[AtomEvent::patch_set(&host, 0, METRO_TEMPO, AtomValue::Float(240.0)), rolling(&host)]);
+        metro.run(2 * beat);
+        let out = metro.audio_output(PortIndex::MetroOut as u32);
+        assert!(out[beat..beat + 100].iter().any(|x| *x != 0.0));
+    }
+
+    #[test]
+    fn click_sounds_differ() {
+        let host = TestHost::new();
+        let mut high = metro(&host);
+        let mut low = metro(&host);
+
+        high.set_atom_input(PortIndex::MetroControl as u32, &[rolling(&host)]);
+        high.run(1024);
+        low.set_atom_input(PortIndex::MetroControl as u32,
+            &[AtomEvent::patch_set(&host, 0, METRO_SOUND, AtomValue::Int(1)), rolling(&host)]);
+        low.run(1024);
+
+        assert!(low.audio_output(PortIndex::MetroOut as u32).iter().any(|x| *x != 0.0));
+        assert!(high.audio_output(PortIndex::MetroOut as u32) != low.audio_output(PortIndex::MetroOut as u32));
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let host = TestHost::new();
+        let settings = [
+            AtomEvent::patch_set(&host, 0, METRO_TEMPO, AtomValue::Float(180.0)),
+            AtomEvent::patch_set(&host, 0, METRO_SOUND, AtomValue::Int(2))
+        ];
+
+        let mut metro1 = metro(&host);
+        metro1.set_atom_input(PortIndex::MetroControl as u32, &settings);
+        metro1.run(64);
+        let state = metro1.save_state().unwrap().unwrap();
+
+        // the reference is set up with patch:Set, the other one restored
+        let mut reference = metro(&host);
+        reference.set_atom_input(PortIndex::MetroControl as u32,
+            &[AtomEvent::patch_set(&host, 0, METRO_TEMPO, AtomValue::Float(180.0)),
+              AtomEvent::patch_set(&host, 0, METRO_SOUND, AtomValue::Int(2)),
+              rolling(&host)]);
+        reference.run(RATE as usize);
+
+        let mut metro2 = host.instantiate(lv2_descriptor, "http://example.org/rustmetro", RATE).unwrap();
+        metro2.restore_state(&state).unwrap();
+        metro2.connect_audio_output(PortIndex::MetroOut as u32);
+        metro2.set_atom_input(PortIndex::MetroControl as u32, &[rolling(&host)]);
+        metro2.activate();
+        metro2.run(RATE as usize);
+
+        assert_eq!(reference.audio_output(PortIndex::MetroOut as u32),
+                   metro2.audio_output(PortIndex::MetroOut as u32));
+    }
+
+    #[test]
+    fn restore_without_state() {
+        let host = TestHost::new();
+        let mut metro = metro(&host);
+        assert_eq!(metro.restore_state(&SavedState::default()), Err(StateError::NoProperty));
     }
 }
 
@@ -33,15 +183,17 @@ mod tests {
 
 extern crate libc;
 extern crate lv2_raw;
+extern crate rustlv2;
 extern crate num;
 
 
 use lv2_raw::*;
+use rustlv2::*;
 use std::f32;
 use std::f64;
-use std::mem::*;
-use libc::{c_char, c_void};
-use std::ffi::*;
+use libc::c_void;
+use std::ffi::CStr;
+use std::ptr;
 use std::f64::consts::PI;
 
 /*
@@ -51,7 +203,14 @@ use std::f64::consts::PI;
    file is a good convention to follow.  If this URI does not match that used
    in the data files, the host will fail to load the plugin.
 */
-static MIDIGATE_URI: &'static [u8] = b"http://example.org/rustmetro\0";
+const METRO_URI: &'static [u8] = b"http://example.org/rustmetro\0";
+
+/*
+   The settings of the metronome which are not ports.  They are changed with
+   patch:Set messages on the control port and saved with the state.
+*/
+const METRO_TEMPO: &'static [u8] = b"http://example.org/rustmetro#tempo\0";
+const METRO_SOUND: &'static [u8] = b"http://example.org/rustmetro#sound\0";
 
 /*
    In code, ports are referred to by index.  An enumeration of port indices
@@ -61,54 +220,98 @@ static MIDIGATE_URI: &'static [u8] = b"http://example.org/rustmetro\0";
 struct MetroURIs {
     atom_blank: LV2Urid, 
     atom_float: LV2Urid,
+    atom_int: LV2Urid,
     atom_object: LV2Urid,
-    atom_path: LV2Urid,
-    atom_resource: LV2Urid,
-    atom_sequence: LV2Urid,
+    atom_urid: LV2Urid,
+    metro_sound: LV2Urid,
+    metro_tempo: LV2Urid,
+    patch_property: LV2Urid,
+    patch_set: LV2Urid,
+    patch_value: LV2Urid,
     time_position: LV2Urid,
     time_bar_beat: LV2Urid,
     time_beats_per_minute: LV2Urid,
     time_speed: LV2Urid
 }
 
-impl MetroURIs {
-    pub fn new() -> MetroURIs {
-        MetroURIs {
-            atom_blank: 0,
-            atom_float: 0,
-            atom_object: 0,
-            atom_path: 0,
-            atom_resource: 0,
-            atom_sequence: 0,
-            time_position: 0,
-            time_bar_beat: 0,
-            time_beats_per_minute: 0,
-            time_speed: 0
-        }
+port_index! {
+    enum PortIndex {
+        MetroControl  = 0,
+        MetroOut = 1
     }
 }
 
+/*
+   The description of the ports, from which the plugin's .ttl file is
+   generated.  Since the control port supports time:Position, the host knows
+   to deliver time and tempo information.
+*/
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::atom_in(PortIndex::MetroControl as u32, "control", "Control")
+        .supports(&["time:Position", "patch:Message"]),
+    PortInfo::audio_out(PortIndex::MetroOut as u32, "out", "Out")
+];
+
+const PARAMETERS: &'static [ParameterInfo] = &[
+    ParameterInfo {
+        uri: "http://example.org/rustmetro#tempo",
+        label: "Tempo",
+        range: "atom:Float",
+        values: (120.0, MIN_TEMPO as f32, MAX_TEMPO as f32)
+    },
+    ParameterInfo {
+        uri: "http://example.org/rustmetro#sound",
+        label: "Click sound",
+        range: "atom:Int",
+        values: (0.0, 0.0, 2.0)
+    }
+];
+
+const MIN_TEMPO: f64 = 20.0;
+const MAX_TEMPO: f64 = 300.0;
 
-enum PortIndex {
-    MetroControl  = 0,
-    MetroOut = 1
+enum State {
+    StateAttack,
+    StateDecay,
+    StateOff
 }
 
-impl PortIndex {
+/* The click sounds, the waveforms are computed in instantiate. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sound {
+    High = 0,
+    Low = 1,
+    Square = 2
+}
 
-    fn from_u32(x: u32) -> Option<PortIndex> {
+impl Sound {
+    fn from_i32(x: i32) -> Option<Sound> {
         match x {
-            0 => Some(PortIndex::MetroControl),
-            1 => Some(PortIndex::MetroOut),
+            0 => Some(Sound::High),
+            1 => Some(Sound::Low),
+            2 => Some(Sound::Square),
             _ => None
         }
     }
 }
 
-enum State {
-    StateAttack,
-    StateDecay,
-    StateOff
+fn click_wave(sound: Sound, rate: f64) -> Vec<f32> {
+    let freq = match sound {
+        Sound::High | Sound::Square => 440.0 * 2.0,
+        Sound::Low => 440.0
+    };
+    let amp = 0.5;
+
+    let mut data = vec![0.0; (rate / freq) as usize];
+
+    for (i, it) in data.iter_mut().enumerate() {
+        let x = ((i as f64) * 2.0 * PI * freq / rate).sin();
+        *it = match sound {
+            Sound::Square => (x.signum() * amp * 0.5) as f32,
+            _ => (x * amp) as f32
+        };
+    }
+    data
 }
 
 
@@ -119,21 +322,20 @@ enum State {
    stored, since there is no additional instance data.
 */
 
-#[repr(C)]
 struct Ports {
-    control: *mut LV2AtomSequence,
-    output: *mut f32
+    control: AtomIn,
+    output: AudioOut
 }
 
 
-#[repr(C)]
-struct Metro {
-    map: *const LV2UridMap,
+pub struct Metro {
     uris: MetroURIs,
 
     ports: Ports,
     
     rate: f64,
+    // the internal tempo, used until the host sends its tempo
+    tempo: f64,
     bpm: f64,
     speed: f64,
 
@@ -141,7 +343,8 @@ struct Metro {
 
     state: State,
 
-    wave: Vec<f32>,
+    sound: Sound,
+    waves: Vec<Vec<f32>>,
     wave_offset: usize,
 
     attack_len: u32,
@@ -151,21 +354,19 @@ struct Metro {
 
 
 impl Metro {
-    fn new(m: *const LV2UridMap, 
-           u: MetroURIs, 
+    fn new(u: MetroURIs, 
            rate: f64, 
            bpm: f64,
-           wav: Vec<f32>) -> Metro {
+           waves: Vec<Vec<f32>>) -> Metro {
         Metro { 
-            map: m,
-
             uris: u,
 
             ports: Ports {
-                control: (0 as *mut LV2AtomSequence),
-                output: (0 as *mut f32) },
+                control: AtomIn::new(),
+                output: AudioOut::new() },
 
             rate: rate,
+            tempo: bpm,
             bpm: bpm,
             speed: 0.0,
 
@@ -174,30 +375,32 @@ impl Metro {
 
             state: State::StateOff,
 
-            wave: wav,
+            sound: Sound::High,
+            waves: waves,
 
             attack_len: (rate * 0.005) as u32,
             decay_len: (rate * 0.075) as u32
         }
     }
 
-    pub fn play(&mut self, begin: u32, end: u32) -> () {
+    fn play(&mut self, begin: u32, end: u32) -> () {
         let frames_per_beat = (60.0 / self.bpm * self.rate) as u32;
 
-        let mut out = unsafe { std::slice::from_raw_parts_mut(self.ports.output.offset(begin as isize), 
-                        (end - begin) as usize) };
+        let out = &mut self.ports.output.as_mut_slice(end as usize)[begin as usize..];
 
         if self.speed == 0.0 {
-            for it in &mut out[..] {
+            for it in out {
                 *it = 0.0;
-                return;
             }
+            return;
         }
 
-        for it in &mut out[..] {
+        let wave = &self.waves[self.sound as usize];
+
+        for it in out {
             match self.state {
                 State::StateAttack => {
-                    *it = self.wave[self.wave_offset] * (self.elapsed_len as f32) 
+                    *it = wave[self.wave_offset] * (self.elapsed_len as f32) 
                             / (self.attack_len as f32);
                     if self.elapsed_len >= self.attack_len {
                         self.state = State::StateDecay;
@@ -206,7 +409,7 @@ impl Metro {
                 State::StateDecay => {
                     let d = (self.elapsed_len as f32 - self.attack_len as f32) 
                             / self.decay_len as f32;
-                    *it = self.wave[self.wave_offset] * (1.0 - d);
+                    *it = wave[self.wave_offset] * (1.0 - d);
                     if self.elapsed_len >= (self.attack_len + self.decay_len) {
                         self.state = State::StateOff;
                     }
@@ -214,7 +417,7 @@ impl Metro {
                 State::StateOff => *it = 0.0,
             }
 
-            self.wave_offset = (self.wave_offset + 1) % self.wave.len();
+            self.wave_offset = (self.wave_offset + 1) % wave.len();
 
             self.elapsed_len += 1;
             if self.elapsed_len == frames_per_beat {
@@ -225,21 +428,73 @@ impl Metro {
     }
 
 
-    pub fn update_position(&mut self, obj: *mut LV2AtomObject) -> () {
+    fn set_tempo(&mut self, tempo: f64) {
+        if tempo.is_finite() {
+            self.tempo = tempo.clamp(MIN_TEMPO, MAX_TEMPO);
+            self.bpm = self.tempo;
+        }
+    }
+
+    fn set_sound(&mut self, sound: Sound) {
+        self.sound = sound;
+        self.wave_offset = 0;
+    }
+
+    /* Handle a patch:Set message for one of the parameters. */
+    fn set_parameter(&mut self, obj: *mut LV2AtomObject) -> () {
         let uris = &self.uris;
 
+        let mut property = 0;
+        let mut value: *mut LV2Atom = ptr::null_mut();
 
-        let mut beat: *mut LV2Atom = 0 as *mut LV2Atom;
-        let mut bpm: *mut LV2Atom = 0 as *mut LV2Atom;
-        let mut speed: *mut LV2Atom = 0 as *mut LV2Atom;
+        unsafe {
+            (*obj).foreach(|prop: *mut LV2AtomPropertyBody| {
+                let key = (*prop).key;
+                if key == uris.patch_property && (*prop).value.mytype == uris.atom_urid {
+                    property = (*(&(*prop).value as *const LV2Atom as *const LV2AtomURID)).body;
+                } else if key == uris.patch_value {
+                    value = &mut (*prop).value;
+                }
+                false
+            });
+
+            if value.is_null() {
+                return;
+            }
+            if property == uris.metro_tempo && (*value).mytype == uris.atom_float {
+                let tempo = (*(value as *const LV2AtomFloat)).body as f64;
+                self.set_tempo(tempo);
+            } else if property == uris.metro_sound && (*value).mytype == uris.atom_int {
+                if let Some(sound) = Sound::from_i32((*(value as *const LV2AtomInt)).body) {
+                    self.set_sound(sound);
+                }
+            }
+        }
+    }
+
+    fn update_position(&mut self, obj: *mut LV2AtomObject) -> () {
+        let uris = &self.uris;
 
-        let descr = [ObjectHelper{key: uris.time_bar_beat, atom: &mut beat},
-            ObjectHelper{key: uris.time_beats_per_minute, atom: &mut bpm},
-            ObjectHelper{key: uris.time_speed, atom: &mut speed}];
+
+        let mut beat: *mut LV2Atom = ptr::null_mut();
+        let mut bpm: *mut LV2Atom = ptr::null_mut();
+        let mut speed: *mut LV2Atom = ptr::null_mut();
 
         unsafe { 
             
-            lv2_atom_object_get(obj, &descr[..]);
+            // lv2_atom_object_get() of lv2_raw stops after the first
+            // property, so walk all of them here
+            (*obj).foreach(|prop: *mut LV2AtomPropertyBody| {
+                let key = (*prop).key;
+                if key == uris.time_bar_beat {
+                    beat = &mut (*prop).value;
+                } else if key == uris.time_beats_per_minute {
+                    bpm = &mut (*prop).value;
+                } else if key == uris.time_speed {
+                    speed = &mut (*prop).value;
+                }
+                false
+            });
 
             if !bpm.is_null() && (*bpm).mytype == uris.atom_float {
                 self.bpm = (*(bpm as *const LV2AtomFloat)).body as f64;
@@ -268,39 +523,74 @@ impl Metro {
 }
 
 
-/* Define a macro for converting a gain in dB to a coefficient. */
-//#define DB_CO(g) ((g) > -90.0f ? powf(10.0f, (g) * 0.05f) : 0.0f)
-
-fn db_co(g: f32) -> f64 {
-    if g > -90.0_f32 {
-        let base = 10.0_f64;
-        base.powf((g as f64) * 0.05_f64)
+impl Plugin for Metro {
+    const URI: &'static [u8] = METRO_URI;
+
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust Example Metronome",
+        classes: &[],
+        project: "http://lv2plug.in/ns/lv2",
+        maintainer: None,
+        license: "http://opensource.org/licenses/isc",
+        required_features: &["urid:map"],
+        optional_features: &["lv2:hardRTCapable"],
+        extension_data: &["state:interface"],
+        supported_options: &[],
+        ports: PORTS,
+        parameters: PARAMETERS
+    };
+
+    fn instantiate(rate: f64, features: &Features) -> Option<Metro> {
+        let map = features.urid_map()?;
+
+        let uris = MetroURIs {
+                atom_blank: map.map(LV2_ATOM__BLANK),
+                atom_float: map.map(LV2_ATOM__FLOAT),
+                atom_int: map.map(LV2_ATOM__INT),
+                atom_object: map.map(LV2_ATOM__OBJECT),
+                atom_urid: map.map(LV2_ATOM__URID),
+                metro_sound: map.map(METRO_SOUND),
+                metro_tempo: map.map(METRO_TEMPO),
+                patch_property: map.map(LV2_PATCH__PROPERTY),
+                patch_set: map.map(LV2_PATCH__SET),
+                patch_value: map.map(LV2_PATCH__VALUE),
+                time_position: map.map(LV2_TIME__POSITION),
+                time_bar_beat: map.map(LV2_TIME__BARBEAT),
+                time_beats_per_minute: map.map(LV2_TIME__BEATSPERMINUTE),
+                time_speed: map.map(LV2_TIME__SPEED)
+            };
+
+        let waves = [Sound::High, Sound::Low, Sound::Square].iter()
+            .map(|sound| click_wave(*sound, rate))
+            .collect();
+
+        Some(Metro::new(uris, rate, 120.0, waves))
     }
-    else {
-        0.0_f64
-    }
-}
-
-struct Descriptor(LV2Descriptor);
-
 
-impl Descriptor {
-    pub extern "C" fn activate(_handle: LV2Handle) {
-        let mut metro = unsafe { &mut *(_handle as *mut Metro) };
-
-        metro.elapsed_len = 0;
-        metro.wave_offset = 0;
-        metro.state = State::StateOff;
+    fn extension_data(uri: &CStr) -> *const c_void {
+        if uri.to_bytes_with_nul() == LV2_STATE__INTERFACE {
+            STATE.as_ptr()
+        } else {
+            ptr::null()
+        }
     }
 
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::MetroControl) => self.ports.control.connect(data),
+            Some(PortIndex::MetroOut) => self.ports.output.connect(data),
+            None => ()
+        }
+    }
 
-    pub extern "C" fn deactivate(_handle: LV2Handle) {}
-
-
-    pub extern "C" fn run(_handle: LV2Handle, sample_count: u32) {
-        let metro = unsafe { &mut *(_handle as *mut Metro) };
+    fn activate(&mut self) {
+        self.elapsed_len = 0;
+        self.wave_offset = 0;
+        self.state = State::StateOff;
+    }
 
-        let inp = metro.ports.control;
+    fn run(&mut self, sample_count: u32) {
+        let inp = self.ports.control.as_ptr();
         let mut last_t = 0;
 
         unsafe {
@@ -310,16 +600,18 @@ impl Descriptor {
             while !lv2_atom_sequence_is_end(&(*inp).body, (*inp).atom.size, ev) {
 
 
-                metro.play(last_t, (*ev).time_as_frames() as u32);
+                self.play(last_t, (*ev).time_as_frames() as u32);
 
-                if ((*ev).body.mytype == metro.uris.atom_object) ||
-                    ((*ev).body.mytype == metro.uris.atom_blank) {
+                if ((*ev).body.mytype == self.uris.atom_object) ||
+                    ((*ev).body.mytype == self.uris.atom_blank) {
 
                     let addr: *mut LV2Atom = &mut ((*ev).body);
                     let obj = addr as *mut LV2AtomObject;
 
-                    if (*obj).body.otype == metro.uris.time_position {
-                        metro.update_position(obj);
+                    if (*obj).body.otype == self.uris.time_position {
+                        self.update_position(obj);
+                    } else if (*obj).body.otype == self.uris.patch_set {
+                        self.set_parameter(obj);
                     }
                 }
                 last_t = (*ev).time_as_frames() as u32;
@@ -328,135 +620,29 @@ impl Descriptor {
             }
         }
 
-        metro.play(last_t, sample_count);
-    }            
-
-
-    pub extern "C" fn connect_port(instance: LV2Handle,
-        port: u32,
-        data : *mut c_void)
-    {
-        let mut metro = unsafe { &mut *(instance as *mut Metro) };
-        let p = PortIndex::from_u32(port);
-
-        println!("connect_port: {}", port);
-
-        match p {
-            Some(PortIndex::MetroControl) => metro.ports.control = data as *mut LV2AtomSequence,
-            Some(PortIndex::MetroOut) => metro.ports.output = data as *mut f32 ,
-            None => println!("Not a valid port index: {}", port)
-        }
-    }
-
-
-
-    pub extern "C" fn instantiate(_desc: *const LV2Descriptor,
-        _rate: f64,
-        _bundle_path: *const c_char,
-        _features: *const *const LV2Feature) -> LV2Handle {
-
-            let ptr: *mut Metro;
-
-            unsafe {
-                let mut map = 0 as *const LV2UridMap;
-                let mut i = 0;
-                let mut feature = *_features;
-                let nul = 0 as *const LV2Feature;
-
-                while feature != nul {
-                    let f = CStr::from_ptr((*feature).uri).to_string_lossy().into_owned();
-                    if f == LV2_URID__MAP {
-                        map = (*feature).data as *const LV2UridMap;
-                        break;
-                    }
-
-                    feature = *_features.offset(i);
-                    i += 1;
-                }
-
-                if map.is_null() {
-                    ptr = 0 as *mut Metro;
-                } else {
-                    let f = (*map).map;
-
-                    let uris = MetroURIs {
-                            atom_blank: f((*map).handle, LV2_ATOM__BLANK.as_ptr() as *const c_char),
-                            atom_float: f((*map).handle, LV2_ATOM__FLOAT.as_ptr() as *const c_char),
-                            atom_object: f((*map).handle, LV2_ATOM__OBJECT.as_ptr() as *const c_char),
-                            atom_path: f((*map).handle, LV2_ATOM__PATH.as_ptr() as *const c_char),
-                            atom_resource: f((*map).handle, LV2_ATOM__RESOURCE.as_ptr() as *const c_char),
-                            atom_sequence: f((*map).handle, LV2_ATOM__SEQUENCE.as_ptr() as *const c_char),
-                            time_position: f((*map).handle, LV2_TIME__POSITION.as_ptr() as *const c_char),
-                            time_bar_beat: f((*map).handle, LV2_TIME__BARBEAT.as_ptr() as *const c_char),
-                            time_beats_per_minute: f((*map).handle, LV2_TIME__BEATSPERMINUTE.as_ptr() as *const c_char),
-                            time_speed: f((*map).handle, LV2_TIME__SPEED.as_ptr() as *const c_char)
-                        };
-
-                    let freq = 440.0 * 2.0;
-                    let amp = 0.5;
-
-                    let mut data = Vec::new();
-                    data.set_len((_rate / freq) as usize);
-
-                    let mut i = 0;
-                    for it in &mut data {
-                        *it = (((i as f64) * 2.0 * PI * freq / _rate).sin() * amp) as f32;
-                        i += 1;
-                    }
-
-                    ptr = transmute(Box::new(Metro::new(map, 
-                                uris, 
-                                _rate, 
-                                120.0,
-                                data
-                                )));
-                }
-
-            }
-
-            return ptr as LV2Handle;
-    }
-
-    pub extern "C" fn cleanup(handle: LV2Handle) {
-        unsafe { 
-            let _drop: Box<Metro> = transmute(((handle as *mut Metro)));
-        }
-    }
-
-    pub extern "C" fn extension_data(_uri: *const u8) -> *const c_void {
-        return 0 as *const c_void
+        self.play(last_t, sample_count);
     }
 }
 
-static mut DESCRIPTOR: LV2Descriptor = LV2Descriptor {
-    uri: 0 as *const c_char,
-    instantiate: Descriptor::instantiate,
-    connect_port: Descriptor::connect_port,
-    activate: Some(Descriptor::activate),
-    run: Descriptor::run,
-    deactivate: Some(Descriptor::deactivate),
-    cleanup: Descriptor::cleanup,
-    extension_data: Descriptor::extension_data
-};
 
 /*
-   The `lv2_descriptor()` function is the entry point to the plugin library.  The
-   host will load the library and call this function repeatedly with increasing
-   indices to find all the plugins defined in the library.  The index is not an
-   indentifier, the URI of the returned descriptor is used to determine the
-   identify of the plugin.
-
-   This method is in the ``discovery'' threading class, so no other functions
-   or methods in this plugin library will be called concurrently with it.
+   The internal tempo and the click sound are saved with the session.  The
+   tempo sent by the host is not, the host sends it again anyway.
 */
+static STATE: StateInterface = StateInterface::new::<Metro>();
 
-#[no_mangle]
-pub extern "C" fn lv2_descriptor(index: u32) -> *const LV2Descriptor {
-    match index {
-        0 => unsafe {
-            DESCRIPTOR.uri = MIDIGATE_URI.as_ptr() as *const c_char;
-            return &DESCRIPTOR as *const LV2Descriptor },
-        _ => return 0 as *const LV2Descriptor
+impl rustlv2::State for Metro {
+    fn save(&self, store: &mut StateStore, _features: &Features) -> Result<(), StateError> {
+        store.store_f32(self.uris.metro_tempo, self.uris.atom_float, self.tempo as f32)?;
+        store.store_i32(self.uris.metro_sound, self.uris.atom_int, self.sound as i32)
     }
-}
 
+    fn restore(&mut self, retrieve: &StateRetrieve, _features: &Features) -> Result<(), StateError> {
+        let tempo = retrieve.retrieve_f32(self.uris.metro_tempo, self.uris.atom_float)?;
+        let sound = retrieve.retrieve_i32(self.uris.metro_sound, self.uris.atom_int)?;
+
+        self.set_tempo(tempo as f64);
+        self.set_sound(Sound::from_i32(sound).ok_or(StateError::BadType)?);
+        Ok(())
+    }
+}
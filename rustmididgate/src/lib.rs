@@ -1,7 +1,135 @@
 #[cfg(test)]
 mod tests {
+    use rustlv2::host::*;
+    use super::*;
+
+    // the plugin is exported by the bundle, the tests need their own entry point
+    lv2_descriptors!(MidiGate);
+
+    const NOTE_ON: [u8; 3] = [0x90, 60, 100];
+    const NOTE_OFF: [u8; 3] = [0x80, 60, 0];
+
+    fn gate<'h>(host: &'h TestHost, input: &[f32]) -> Instance<'h> {
+        let mut gate = host.instantiate(lv2_descriptor, "http://example.org/rustmidigate", 48000.0).unwrap();
+        gate.set_audio_input(PortIndex::MGIn as u32, input);
+        gate.connect_audio_output(PortIndex::MGOut as u32);
+        gate.set_atom_input(PortIndex::MGControl as u32, &[]);
+        gate.activate();
+        gate
+    }
+
+    #[test]
+    fn descriptor() {
+        let host = TestHost::new();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rustmidigate", 48000.0).is_some());
+        assert!(lv2_descriptor(1).is_null());
+    }
+
+    #[test]
+    fn requires_urid_map() {
+        let host = TestHost::bare();
+        assert!(host.instantiate(lv2_descriptor, "http://example.org/rustmidigate", 48000.0).is_none());
+    }
+
+    #[test]
+    fn closed_without_notes() {
+        let host = TestHost::new();
+        let mut gate = gate(&host, &[1.0; 256]);
+
+        gate.run(256);
+        assert!(gate.audio_output(PortIndex::MGOut as u32).iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn opens_on_note() {
+        let host = TestHost::new();
+        let mut gate = gate(&host, &[1.0; 256]);
+
+        gate.set_atom_input(PortIndex::MGControl as u32,
+            &[AtomEvent::midi(&host, 64, &NOTE_ON), AtomEvent::midi(&host, 192, &NOTE_OFF)]);
+        gate.run(256);
+
+        let out = gate.audio_output(PortIndex::MGOut as u32);
+        assert!(out[..64].iter().all(|x| *x == 0.0));
+        assert!(out[64..192].iter().all(|x| *x == 1.0));
+        assert!(out[192..].iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn stays_open_across_blocks() {
+        let host = TestHost::new();
+        let mut gate = gate(&host, &[1.0; 128]);
+
+        gate.set_atom_input(PortIndex::MGControl as u32, &[AtomEvent::midi(&host, 0, &NOTE_ON)]);
+        gate.run(128);
+        gate.set_atom_input(PortIndex::MGControl as u32, &[]);
+        gate.run(128);
+
+        assert!(gate.audio_output(PortIndex::MGOut as u32).iter().all(|x| *x == 1.0));
+    }
+
+    #[test]
+    fn program_one_inverts() {
+        let host = TestHost::new();
+        let mut gate = gate(&host, &[1.0; 256]);
+
+        gate.set_atom_input(PortIndex::MGControl as u32,
+            &[AtomEvent::midi(&host, 0, &[0xC0, 1]), AtomEvent::midi(&host, 128, &NOTE_ON)]);
+        gate.run(256);
+
+        let out = gate.audio_output(PortIndex::MGOut as u32);
+        assert!(out[..128].iter().all(|x| *x == 1.0));
+        assert!(out[128..].iter().all(|x| *x == 0.0));
+    }
+
     #[test]
-    fn it_works() {
+    fn unmatched_note_off() {
+        let host = TestHost::new();
+        let mut gate = gate(&host, &[1.0; 256]);
+
+        gate.set_atom_input(PortIndex::MGControl as u32,
+            &[AtomEvent::midi(&host, 0, &NOTE_OFF), AtomEvent::midi(&host, 128, &NOTE_ON)]);
+        gate.run(256);
+
+        let out = gate.audio_output(PortIndex::MGOut as u32);
+        assert!(out[..128].iter().all(|x| *x == 0.0));
+        assert!(out[128..].iter().all(|x| *x == 1.0));
+    }
+
+    #[test]
+    fn logs_program_changes() {
+        let host = TestHost::new();
+        {
+            let mut gate = gate(&host, &[1.0; 64]);
+            gate.set_atom_input(PortIndex::MGControl as u32,
+                &[AtomEvent::midi(&host, 0, &[0xC0, 1]), AtomEvent::midi(&host, 32, &[0xC0, 5])]);
+            gate.run(64);
+        }
+
+        assert_eq!(host.log_messages(), vec![
+            (host.map(LV2_LOG__NOTE), "rustmidigate: program 1\n".to_string()),
+            (host.map(LV2_LOG__WARNING), "rustmidigate: ignored program 5\n".to_string())
+        ]);
+    }
+
+    #[test]
+    fn program_is_saved() {
+        let host = TestHost::new();
+        let mut gate1 = gate(&host, &[1.0; 64]);
+        gate1.set_atom_input(PortIndex::MGControl as u32, &[AtomEvent::midi(&host, 0, &[0xC0, 1])]);
+        gate1.run(64);
+        let state = gate1.save_state().unwrap().unwrap();
+
+        // restored before activation, like a host loading a session
+        let mut gate2 = host.instantiate(lv2_descriptor, "http://example.org/rustmidigate", 48000.0).unwrap();
+        gate2.restore_state(&state).unwrap();
+        gate2.set_audio_input(PortIndex::MGIn as u32, &[1.0; 64]);
+        gate2.connect_audio_output(PortIndex::MGOut as u32);
+        gate2.set_atom_input(PortIndex::MGControl as u32, &[]);
+        gate2.activate();
+        gate2.run(64);
+
+        assert!(gate2.audio_output(PortIndex::MGOut as u32).iter().all(|x| *x == 1.0));
     }
 }
 
@@ -33,15 +161,16 @@ mod tests {
 
 extern crate libc;
 extern crate lv2_raw;
+extern crate rustlv2;
 extern crate num;
 
 
 use lv2_raw::*;
-use std::f32;
+use rustlv2::*;
 use std::f64;
-use std::mem::*;
-use libc::{c_char, c_void};
-use std::ffi::*;
+use libc::c_void;
+use std::ffi::CStr;
+use std::ptr;
 
 
 /*
@@ -51,72 +180,95 @@ use std::ffi::*;
    file is a good convention to follow.  If this URI does not match that used
    in the data files, the host will fail to load the plugin.
 */
-static MIDIGATE_URI: &'static [u8] = b"http://example.org/rustmidigate\0";
+const MIDIGATE_URI: &'static [u8] = b"http://example.org/rustmidigate\0";
+
+/* The state key of the current program. */
+const MIDIGATE_PROGRAM: &'static [u8] = b"http://example.org/rustmidigate#program\0";
 
 /*
    In code, ports are referred to by index.  An enumeration of port indices
    should be defined for readability.
 */
-enum PortIndex {
-    MGControl  = 0,
-    MGIn  = 1,
-    MGOut = 2
-}
-
-impl PortIndex {
-
-    fn from_u32(x: u32) -> Option<PortIndex> {
-        match x {
-            0 => Some(PortIndex::MGControl),
-            1 => Some(PortIndex::MGIn),
-            2 => Some(PortIndex::MGOut),
-            _ => None
-        }
+port_index! {
+    enum PortIndex {
+        MGControl  = 0,
+        MGIn  = 1,
+        MGOut = 2
     }
 }
 
+/*
+   This plugin has three ports.  There is an audio input and output as before,
+   as well as a new AtomPort.  An AtomPort buffer contains an Atom, which is a
+   generic container for any type of data.  In this case, we want to receive
+   MIDI events, so the (mandatory) atom:bufferType is atom:Sequence, which is
+   a series of events with time stamps.
+
+   Events themselves are also generic and can contain any type of data, but in
+   this case we are only interested in MIDI events.  The (optional)
+   atom:supports property describes which event types are supported.  Though
+   not required, this information should always be given so the host knows what
+   types of event it can expect the plugin to understand.
+
+   The (optional) lv2:designation of this port is lv2:control, which
+   indicates that this is the "main" control port where the host should send
+   events it expects to configure the plugin, in this case changing the MIDI
+   program.  This is necessary since it is possible to have several MIDI input
+   ports, though typically it is best to have one.
+*/
+const PORTS: &'static [PortInfo] = &[
+    PortInfo::atom_in(PortIndex::MGControl as u32, "control", "Control")
+        .supports(&["midi:MidiEvent"])
+        .designation("lv2:control"),
+    PortInfo::audio_in(PortIndex::MGIn as u32, "in", "In"),
+    PortInfo::audio_out(PortIndex::MGOut as u32, "out", "Out")
+];
+
 /*
    Every plugin defines a private structure for the plugin instance.  All data
    associated with a plugin instance is stored here, and is available to
    every instance method.  In this simple plugin, only port buffers need to be
    stored, since there is no additional instance data.
 */
-#[repr(C)]
-struct MidiGate {
+pub struct MidiGate {
     // Port buffers
-    control: *mut LV2AtomSequence,
-    input: *const f32,
-    output: *mut f32,
-
-    map: *const LV2UridMap,
+    control: AtomIn,
+    input: AudioIn,
+    output: AudioOut,
 
     midi_event: LV2Urid,
+    atom_int: LV2Urid,
+    program_key: LV2Urid,
 
     n_active_notes: u32,
     program: u32,
+
+    log: Logger
 }
 
 impl MidiGate {
-    fn new(m: *const LV2UridMap, event: &LV2Urid) -> MidiGate {
+    fn new(map: &UridMap, log: Logger) -> MidiGate {
         MidiGate { 
-            control: (0 as *mut LV2AtomSequence),
-            input: (0 as *const f32),   
-            output: (0 as *mut f32),
+            control: AtomIn::new(),
+            input: AudioIn::new(),
+            output: AudioOut::new(),
 
-            map: m,
-
-            midi_event: *event,
+            midi_event: map.map(LV2_MIDI__MIDIEVENT),
+            atom_int: map.map(LV2_ATOM__INT),
+            program_key: map.map(MIDIGATE_PROGRAM),
             n_active_notes: 0,
-            program: 0
+            program: 0,
+
+            log: log
         }
     }
 
-    fn write_output(&mut self, offset: isize, len: usize) -> () {
+    fn write_output(&mut self, offset: usize, len: usize) {
         let active = if self.program == 0 { self.n_active_notes > 0 } 
                         else { self.n_active_notes == 0 };
 
-        let input = unsafe { std::slice::from_raw_parts(self.input.offset(offset), len) };
-        let output = unsafe { std::slice::from_raw_parts_mut(self.output.offset(offset), len) };
+        let input = &self.input.as_slice(offset + len)[offset..];
+        let output = &mut self.output.as_mut_slice(offset + len)[offset..];
 
         if active {
             output.copy_from_slice(input);
@@ -129,167 +281,113 @@ impl MidiGate {
 }
 
 
-/* Define a macro for converting a gain in dB to a coefficient. */
-//#define DB_CO(g) ((g) > -90.0f ? powf(10.0f, (g) * 0.05f) : 0.0f)
+impl Plugin for MidiGate {
+    const URI: &'static [u8] = MIDIGATE_URI;
 
-fn db_co(g: f32) -> f64 {
-    if g > -90.0_f32 {
-        let base = 10.0_f64;
-        base.powf((g as f64) * 0.05_f64)
-    }
-    else {
-        0.0_f64
-    }
-}
-
-struct Descriptor(LV2Descriptor);
+    const INFO: PluginInfo = PluginInfo {
+        name: "Rust Example MIDI Gate",
+        classes: &[],
+        project: "http://lv2plug.in/ns/lv2",
+        maintainer: None,
+        license: "http://opensource.org/licenses/isc",
+        required_features: &["urid:map"],
+        optional_features: &["lv2:hardRTCapable", "log:log"],
+        extension_data: &["state:interface"],
+        supported_options: &[],
+        ports: PORTS,
+        parameters: &[]
+    };
 
+    fn instantiate(_rate: f64, features: &Features) -> Option<MidiGate> {
+        let map = features.urid_map()?;
 
-impl Descriptor {
-    pub extern "C" fn activate(_handle: LV2Handle) {
-        let mut gate = unsafe { &mut *(_handle as *mut MidiGate) };
-
-        gate.n_active_notes = 0;
-        gate.program = 0;
+        Some(MidiGate::new(&map, Logger::new(features, "rustmidigate")))
     }
 
+    fn connect_port(&mut self, port: u32, data: *mut c_void) {
+        match PortIndex::from_u32(port) {
+            Some(PortIndex::MGControl) => self.control.connect(data),
+            Some(PortIndex::MGIn) => self.input.connect(data),
+            Some(PortIndex::MGOut) => self.output.connect(data),
+            None => ()
+        }
+    }
 
-    pub extern "C" fn deactivate(_handle: LV2Handle) {}
+    /*
+       The program is not reset here, it may just have been restored from
+       the state.
+    */
+    fn activate(&mut self) {
+        self.n_active_notes = 0;
+    }
 
+    fn extension_data(uri: &CStr) -> *const c_void {
+        if uri.to_bytes_with_nul() == LV2_STATE__INTERFACE {
+            STATE.as_ptr()
+        } else {
+            ptr::null()
+        }
+    }
 
-    pub extern "C" fn run(_handle: LV2Handle, sample_count: u32) {
-        let gate = unsafe { &mut *(_handle as *mut MidiGate) };
+    fn run(&mut self, sample_count: u32) {
         let mut offset = 0;
 
         unsafe {
+            let control = self.control.as_ptr();
             let f = |it: *const LV2AtomEvent| { 
-                        if (*it).body.mytype == gate.midi_event {
+                        // the audio up to the event is still gated with the
+                        // old state
+                        let frames = (*it).time_as_frames() as usize;
+                        self.write_output(offset, frames - offset);
+                        offset = frames;
+
+                        if (*it).body.mytype == self.midi_event {
                             let msg_raw = it.offset(1) as *const u8;
                             let msg = std::slice::from_raw_parts(msg_raw, (*it).body.size as usize);
                             match lv2_midi_message_type(msg) {
                                 LV2MidiMessageType::LV2MidiMsgNoteOn => { 
-                                        gate.n_active_notes += 1;
-                                        println!("NOTE_ON");
+                                        self.n_active_notes += 1;
+                                        self.log.rt(Level::Trace, "note on", Some(msg[1] as i64));
                                     },
                                 LV2MidiMessageType::LV2MidiMsgNoteOff => {
-                                        gate.n_active_notes -= 1;
-                                        println!("NOTE_OFF");
+                                        self.n_active_notes = self.n_active_notes.saturating_sub(1);
+                                        self.log.rt(Level::Trace, "note off", Some(msg[1] as i64));
                                     },
                                 LV2MidiMessageType::LV2MidiMsgPgmChange => {
-                                        println!("PGM_CHG");
-                                        if (msg[1] == 0) || (msg[1] == 1) { gate.program = msg[1] as u32; }
+                                        if (msg[1] == 0) || (msg[1] == 1) {
+                                            self.program = msg[1] as u32;
+                                            self.log.rt(Level::Note, "program", Some(msg[1] as i64));
+                                        } else {
+                                            self.log.rt(Level::Warning, "ignored program", Some(msg[1] as i64));
+                                        }
                                     },
-                                _ => return
+                                _ => ()
                             }   
                         }
-
-                        let frames = (*it).time_as_frames();
-                        gate.write_output(offset, frames as usize - offset as usize);
-                        offset = frames as isize;
                     };
 
-            let ref mut control = *gate.control;
-            control.foreach(f);
-        }
-        gate.write_output(offset, sample_count as usize - offset as usize);
-    }            
-
-
-    pub extern "C" fn connect_port(instance: LV2Handle,
-        port: u32,
-        data : *mut c_void)
-    {
-        let mut gate = unsafe { &mut *(instance as *mut MidiGate) };
-        let p = PortIndex::from_u32(port);
-
-        println!("connect_port: {}", port);
-
-        match p {
-            Some(PortIndex::MGControl) => gate.control = data as *mut LV2AtomSequence,
-            Some(PortIndex::MGIn) => gate.input = data as *const f32 ,
-            Some(PortIndex::MGOut) => gate.output = data as *mut f32 ,
-            None => println!("Not a valid port index: {}", port)
+            (*control).foreach(f);
         }
+        self.write_output(offset, sample_count as usize - offset);
     }
+}
 
 
-    pub extern "C" fn instantiate(_desc: *const LV2Descriptor,
-        _rate: f64,
-        _bundle_path: *const c_char,
-        _features: *const *const LV2Feature) -> LV2Handle {
-
-            let ptr: *mut MidiGate;
-
-            unsafe {
-                let mut map = 0 as *const LV2UridMap;
-                let mut i = 0;
-                let mut feature = *_features;
-                let nul = 0 as *const LV2Feature;
-
-                while feature != nul {
-                    let f = CStr::from_ptr((*feature).uri).to_string_lossy().into_owned();
-                    if f == LV2_URID__MAP {
-                        map = (*feature).data as *const LV2UridMap;
-                        break;
-                    }
-
-                    feature = *_features.offset(i);
-                    i += 1;
-                }
-
-                if map.is_null() {
-                    ptr = 0 as *mut MidiGate;
-                } else {
-                    let f = (*map).map;
-                    let ev = f((*map).handle, LV2_MIDI__MIDIEVENT.as_ptr() as *const c_char);
-                    ptr = transmute(Box::new(MidiGate::new(map, &ev)));
-                }
-
-            }
+/* The current program is saved with the session. */
+static STATE: StateInterface = StateInterface::new::<MidiGate>();
 
-            return ptr as LV2Handle;
+impl State for MidiGate {
+    fn save(&self, store: &mut StateStore, _features: &Features) -> Result<(), StateError> {
+        store.store_i32(self.program_key, self.atom_int, self.program as i32)
     }
 
-    pub extern "C" fn cleanup(handle: LV2Handle) {
-        unsafe { 
-            let _drop: Box<MidiGate> = transmute(((handle as *mut MidiGate)));
+    fn restore(&mut self, retrieve: &StateRetrieve, _features: &Features) -> Result<(), StateError> {
+        match retrieve.retrieve_i32(self.program_key, self.atom_int)? {
+            program @ 0..=1 => {
+                self.program = program as u32;
+                Ok(())
+            },
+            _ => Err(StateError::BadType)
         }
     }
-
-    pub extern "C" fn extension_data(_uri: *const u8) -> *const c_void {
-        return 0 as *const c_void
-    }
-}
-
-static mut DESCRIPTOR: LV2Descriptor = LV2Descriptor {
-    uri: 0 as *const c_char,
-    instantiate: Descriptor::instantiate,
-    connect_port: Descriptor::connect_port,
-    activate: Some(Descriptor::activate),
-    run: Descriptor::run,
-    deactivate: Some(Descriptor::deactivate),
-    cleanup: Descriptor::cleanup,
-    extension_data: Descriptor::extension_data
-};
-
-/*
-   The `lv2_descriptor()` function is the entry point to the plugin library.  The
-   host will load the library and call this function repeatedly with increasing
-   indices to find all the plugins defined in the library.  The index is not an
-   indentifier, the URI of the returned descriptor is used to determine the
-   identify of the plugin.
-
-   This method is in the ``discovery'' threading class, so no other functions
-   or methods in this plugin library will be called concurrently with it.
-*/
-
-#[no_mangle]
-pub extern "C" fn lv2_descriptor(index: u32) -> *const LV2Descriptor {
-    match index {
-        0 => unsafe {
-            DESCRIPTOR.uri = MIDIGATE_URI.as_ptr() as *const c_char;
-            return &DESCRIPTOR as *const LV2Descriptor },
-        _ => return 0 as *const LV2Descriptor
-    }
 }
-
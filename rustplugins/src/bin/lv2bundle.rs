@@ -0,0 +1,45 @@
+/*
+   Writes the data files of the bundle (manifest.ttl and the plugin
+   descriptions) into the bundle directory given on the command line:
+
+       cargo run --bin lv2bundle -- ~/.lv2/rustplugins.lv2
+*/
+
+extern crate rustplugins;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+
+fn main() {
+    let dir = match env::args().nth(1) {
+        Some(dir) => dir,
+        None => {
+            eprintln!("usage: lv2bundle <bundle directory>");
+            process::exit(2);
+        }
+    };
+
+    let files = match rustplugins::bundle_files() {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("lv2bundle: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let dir = Path::new(&dir);
+    if let Err(err) = fs::create_dir_all(dir) {
+        eprintln!("lv2bundle: {}: {}", dir.display(), err);
+        process::exit(1);
+    }
+    for (name, content) in files {
+        let path = dir.join(name);
+        if let Err(err) = fs::write(&path, content) {
+            eprintln!("lv2bundle: {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
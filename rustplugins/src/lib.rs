@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use rustlv2::host::*;
+    use std::ffi::CStr;
+    use super::*;
+
+    const URIS: [&'static str; 8] = [
+        "http://example.org/rustamp",
+        "http://example.org/rusteq",
+        "http://example.org/rustparaeq",
+        "http://example.org/rustvowel",
+        "http://example.org/rustcrossover",
+        "http://example.org/rustdynamiceq",
+        "http://example.org/rustmetro",
+        "http://example.org/rustmidigate"
+    ];
+
+    #[test]
+    fn enumerates_all_plugins() {
+        for (index, uri) in URIS.iter().enumerate() {
+            let descriptor = lv2_descriptor(index as u32);
+            assert!(!descriptor.is_null());
+            assert_eq!(unsafe { CStr::from_ptr((*descriptor).uri) }.to_str().unwrap(), *uri);
+        }
+        assert!(lv2_descriptor(URIS.len() as u32).is_null());
+    }
+
+    #[test]
+    fn instantiates_all_plugins() {
+        let host = TestHost::new();
+        for uri in URIS.iter() {
+            assert!(host.instantiate(lv2_descriptor, uri, 48000.0).is_some(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn manifest_lists_all_plugins() {
+        let files = bundle_files().unwrap();
+        assert_eq!(files[0].0, "manifest.ttl");
+        for uri in URIS.iter() {
+            assert!(files[0].1.contains(&format!("<{}>", uri)), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn one_description_per_plugin() {
+        let files = bundle_files().unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.0.as_str()).collect();
+        assert_eq!(names, ["manifest.ttl", "rustamp.ttl", "rusteq.ttl", "rustparaeq.ttl", "rustvowel.ttl", "rustcrossover.ttl",
+            "rustdynamiceq.ttl", "rustmetro.ttl", "rustmidigate.ttl"]);
+        for (file, uri) in files[1..].iter().zip(URIS.iter()) {
+            assert!(file.1.contains(&format!("\n<{}>\n", uri)), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn eq_output_symbols() {
+        let files = bundle_files().unwrap();
+        let eq = &files[2].1;
+        assert!(eq.contains("lv2:symbol \"outl\""));
+        assert!(eq.contains("lv2:symbol \"outr\""));
+    }
+}
+
+
+/*
+   The bundle library.  All plugins of this repository are linked into one
+   shared object, so they can be installed as a single LV2 bundle together
+   with `manifest.ttl` and the plugin descriptions, which are generated by
+   the `lv2bundle` binary from the plugins' metadata.
+
+   New plugins are added to the list below.
+*/
+
+extern crate rustlv2;
+extern crate rustamp;
+extern crate rusteq;
+extern crate rustmetro;
+extern crate rustmidigate;
+
+use rustlv2::*;
+
+
+lv2_bundle!("librustplugins.so";
+    rustamp::Amp,
+    rusteq::EQ,
+    rusteq::ParaEQ,
+    rusteq::VowelFilter,
+    rusteq::Crossover,
+    rusteq::DynamicEQ,
+    rustmetro::Metro,
+    rustmidigate::MidiGate
+);